@@ -10,19 +10,6 @@ if ! command -v cargo &> /dev/null; then
     exit 1
 fi
 
-# Check if Solana CLI is installed
-if ! command -v solana &> /dev/null; then
-    echo "❌ Error: Solana CLI not found"
-    echo "Please install Solana CLI first:"
-    echo "sh -c \"$(curl -sSfL https://release.anza.xyz/stable/install)\""
-    exit 1
-fi
-
-# Display current configuration
-echo "📋 Current Solana Configuration:"
-solana config get
-
-echo ""
 echo "📦 Building project..."
 cargo build --release
 
@@ -59,33 +46,35 @@ else
     echo "✅ Port $PORT doesn't require elevated privileges"
 fi
 
+# Settings come from the config file when it exists (reloaded on SIGHUP or
+# when the file changes, see config.example.toml), otherwise from these flags
+CONFIG_FILE="${CONFIG_FILE:-testnet.toml}"
+if [ -f "$CONFIG_FILE" ]; then
+    PROXY_ARGS=(--port $PORT --config "$CONFIG_FILE")
+else
+    PROXY_ARGS=(
+        --port $PORT
+        --cluster-url https://rpc.testnet.x1.xyz
+        --discovery-interval 3600
+        --node-health-timeout 30
+        --rpc-request-timeout 60
+    )
+fi
+
 echo ""
 echo "🏃 Starting X1 RPC Proxy Server..."
 echo "Service will run at: http://localhost:$PORT"
 echo "Target cluster: https://rpc.testnet.x1.xyz"
+if [ -f "$CONFIG_FILE" ]; then
+    echo "Config file: $CONFIG_FILE"
+fi
 echo ""
 
 # Start the service with or without sudo
 if [ "$USE_SUDO" = true ]; then
     echo "🔐 Starting with elevated privileges..."
     # Preserve environment variables for cargo and solana
-    sudo -E env PATH="$PATH" HOME="$HOME" USER="$USER" cargo run --release -- \
-        --port $PORT \
-        --cluster-url https://rpc.testnet.x1.xyz \
-        --health-check-interval 3600 \
-        --node-health-timeout 30 \
-        --rpc-request-timeout 60 \
-        # --max-concurrent-rpc-requests 2 \
-        # --max-concurrent-tests 5 \
-        # --max-queue-wait-time 30
+    sudo -E env PATH="$PATH" HOME="$HOME" USER="$USER" cargo run --release -- "${PROXY_ARGS[@]}"
 else
-    cargo run --release -- \
-        --port $PORT \
-        --cluster-url https://rpc.testnet.x1.xyz \
-        --health-check-interval 3600 \
-        --node-health-timeout 30 \
-        --rpc-request-timeout 60 \
-        # --max-concurrent-rpc-requests 100 \
-        # --max-concurrent-tests 50 \
-        # --max-queue-wait-time 30
-fi 
\ No newline at end of file
+    cargo run --release -- "${PROXY_ARGS[@]}"
+fi
\ No newline at end of file
@@ -0,0 +1,261 @@
+use anyhow::Result;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::types::RpcRequest;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const SIGNATURE_LEN: usize = 64;
+/// Base64 characters covering the signature count and first signature of a transaction
+const SIGNATURE_PREFIX_CHARS: usize = 4 * (3 + SIGNATURE_LEN).div_ceil(3);
+
+/// Methods whose answers depend on which node's view of the chain they come from
+pub const DEFAULT_AFFINITY_METHODS: &str =
+    "sendTransaction,getSignatureStatuses,getSignaturesForAddress,getTransaction";
+
+/// What pins a request to a node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AffinityMode {
+    /// Every request goes through the load-balancing strategy
+    #[default]
+    Off,
+    /// The client's API key, or its IP without one, so a client polling after
+    /// `sendTransaction` keeps asking the node it sent the transaction to
+    Client,
+    /// The account or signature a request is about, so paging through one
+    /// address always reads the same node. `sendTransaction` is keyed by the
+    /// transaction's first signature, the one `getSignatureStatuses` and
+    /// `getTransaction` then ask about. Requests without one fall back to the client.
+    Params,
+}
+
+impl FromStr for AffinityMode {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(AffinityMode::Off),
+            "client" => Ok(AffinityMode::Client),
+            "params" => Ok(AffinityMode::Params),
+            _ => Err(anyhow::anyhow!("unknown affinity mode '{}', expected off, client or params", s)),
+        }
+    }
+}
+
+/// Which requests are routed by consistent hash instead of the load-balancing strategy
+#[derive(Debug, Clone)]
+pub struct Affinity {
+    mode: AffinityMode,
+    /// All methods when empty
+    methods: HashSet<String>,
+}
+
+impl Affinity {
+    pub fn new(mode: AffinityMode, methods: Vec<String>) -> Self {
+        Self {
+            mode,
+            methods: methods.into_iter().collect(),
+        }
+    }
+    
+    pub fn mode(&self) -> AffinityMode {
+        self.mode
+    }
+    
+    /// Key that pins `request` from `client` to a node, `None` when any node may answer it
+    pub fn key(&self, request: &RpcRequest, client: &str) -> Option<String> {
+        if !self.methods.is_empty() && !self.methods.contains(&request.method) {
+            return None;
+        }
+        match self.mode {
+            AffinityMode::Off => None,
+            AffinityMode::Client => Some(client.to_string()),
+            AffinityMode::Params => {
+                let key = match request.method.as_str() {
+                    "sendTransaction" => transaction_signature(request),
+                    _ => params_key(request),
+                };
+                Some(key.unwrap_or_else(|| client.to_string()))
+            }
+        }
+    }
+}
+
+/// Leading account or signature of the params, `[key, ...]` or `[[key, ...], ...]`
+fn params_key(request: &RpcRequest) -> Option<String> {
+    match request.params.as_ref()?.as_array()?.first()? {
+        serde_json::Value::String(key) => Some(key.clone()),
+        serde_json::Value::Array(keys) => keys.first()?.as_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+/// First signature of a `sendTransaction`'s transaction, base58 encoded like the
+/// signatures clients poll for afterwards
+fn transaction_signature(request: &RpcRequest) -> Option<String> {
+    let params = request.params.as_ref()?.as_array()?;
+    let encoded = params.first()?.as_str()?;
+    let encoding = params
+        .get(1)
+        .and_then(|config| config.get("encoding"))
+        .and_then(|encoding| encoding.as_str())
+        .unwrap_or("base58");
+    
+    let transaction = match encoding {
+        // Only the leading signature is needed, which sits in the first few characters
+        "base64" => base64::engine::general_purpose::STANDARD
+            .decode(encoded.get(..SIGNATURE_PREFIX_CHARS).unwrap_or(encoded))
+            .ok()?,
+        "base58" => base58_decode(encoded)?,
+        _ => return None,
+    };
+    
+    // compact-u16 signature count, then the signatures
+    let (count, offset) = short_vec_len(&transaction)?;
+    if count == 0 {
+        return None;
+    }
+    transaction.get(offset..offset + SIGNATURE_LEN).map(base58_encode)
+}
+
+/// Length prefix of a serialized vector and the bytes it takes, 7 bits per byte
+fn short_vec_len(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut len = 0;
+    for (i, byte) in bytes.iter().take(3).enumerate() {
+        len |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((len, i + 1));
+        }
+    }
+    None
+}
+
+fn base58_encode(bytes: &[u8]) -> String {
+    // Base 58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in bytes {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(digits.iter().rev().map(|digit| BASE58_ALPHABET[*digit as usize] as char));
+    encoded
+}
+
+fn base58_decode(encoded: &str) -> Option<Vec<u8>> {
+    // Bytes, least significant first
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in encoded.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|digit| *digit == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    
+    let zeros = encoded.bytes().take_while(|c| *c == b'1').count();
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes.iter().rev());
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn request(method: &str, params: serde_json::Value) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(1),
+            method: method.to_string(),
+            params: Some(params),
+        }
+    }
+    
+    /// Two signatures followed by a made up message
+    fn transaction() -> (Vec<u8>, String) {
+        let first: Vec<u8> = (1..=SIGNATURE_LEN as u8).collect();
+        let mut transaction = vec![2];
+        transaction.extend(&first);
+        transaction.extend([7; SIGNATURE_LEN]);
+        transaction.extend([9; 200]);
+        (transaction, base58_encode(&first))
+    }
+    
+    #[test]
+    fn base58_round_trips() {
+        assert_eq!(base58_encode(b"Hello World!"), "2NEpo7TZRRrLZSi2U");
+        assert_eq!(base58_decode("2NEpo7TZRRrLZSi2U").unwrap(), b"Hello World!");
+        assert_eq!(base58_encode(&[0, 0, 1]), "112");
+        assert_eq!(base58_decode("112").unwrap(), [0, 0, 1]);
+        assert_eq!(base58_encode(&[]), "");
+        assert!(base58_decode("0OIl").is_none());
+    }
+    
+    #[test]
+    fn short_vec_lengths() {
+        assert_eq!(short_vec_len(&[0x01, 0xff]), Some((1, 1)));
+        assert_eq!(short_vec_len(&[0x80, 0x01]), Some((128, 2)));
+        assert_eq!(short_vec_len(&[0xff, 0xff, 0x03]), Some((0xffff, 3)));
+        assert_eq!(short_vec_len(&[0x80]), None);
+    }
+    
+    #[test]
+    fn send_transaction_lands_with_its_signature_status_polls() {
+        let affinity = Affinity::new(AffinityMode::Params, Vec::new());
+        let (transaction, signature) = transaction();
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&transaction);
+        
+        let status_key = affinity.key(&request("getSignatureStatuses", json!([[signature.clone()]])), "client");
+        assert_eq!(status_key.as_deref(), Some(signature.as_str()));
+        assert_eq!(affinity.key(&request("getTransaction", json!([signature.clone()])), "client"), status_key);
+        assert_eq!(
+            affinity.key(&request("sendTransaction", json!([base64, { "encoding": "base64" }])), "client"),
+            status_key
+        );
+        assert_eq!(
+            affinity.key(&request("sendTransaction", json!([base58_encode(&transaction)])), "client"),
+            status_key
+        );
+    }
+    
+    #[test]
+    fn undecodable_transactions_fall_back_to_the_client() {
+        let affinity = Affinity::new(AffinityMode::Params, Vec::new());
+        for params in [json!(["not base64!", { "encoding": "base64" }]), json!(["AA==", { "encoding": "base64" }]), json!([])] {
+            assert_eq!(affinity.key(&request("sendTransaction", params), "client").as_deref(), Some("client"));
+        }
+    }
+    
+    #[test]
+    fn keys_follow_the_mode_and_methods() {
+        let getter = request("getSignaturesForAddress", json!(["Vote111111111111111111111111111111111111111"]));
+        
+        assert_eq!(Affinity::new(AffinityMode::Off, Vec::new()).key(&getter, "client"), None);
+        assert_eq!(Affinity::new(AffinityMode::Client, Vec::new()).key(&getter, "client").as_deref(), Some("client"));
+        assert_eq!(
+            Affinity::new(AffinityMode::Params, Vec::new()).key(&getter, "client").as_deref(),
+            Some("Vote111111111111111111111111111111111111111")
+        );
+        assert_eq!(Affinity::new(AffinityMode::Params, vec!["getTransaction".to_string()]).key(&getter, "client"), None);
+    }
+}
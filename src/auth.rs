@@ -0,0 +1,401 @@
+use anyhow::Result;
+use axum::http::{header, HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::warn;
+
+use crate::types::RpcError;
+
+/// Request header carrying a client's API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Query parameter carrying a client's API key (`/?api-key=<key>`)
+pub const API_KEY_QUERY_PARAM: &str = "api-key";
+
+/// A client key and the policy applied to requests made with it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    /// Identifies the key in logs, metrics and the admin API, the key itself is never shown.
+    /// Required and unique in the configuration, taken from the path in the admin API.
+    #[serde(default)]
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub key: String,
+    /// Methods the key may call, all methods when empty
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    /// Browser origins the key may be used from, any origin when empty
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Requests per second for the key, clients using it fall under the per-IP limit when unset
+    #[serde(default)]
+    pub rps: Option<f64>,
+    /// Defaults to two seconds worth of requests
+    #[serde(default)]
+    pub burst: Option<u32>,
+    /// Extra labels on the key's usage metrics, e.g. `customer = "acme"`
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl FromStr for ApiKey {
+    type Err = anyhow::Error;
+    
+    /// Unrestricted key given on the command line as `name:key`
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, key)) = s.split_once(':') else {
+            return Err(anyhow::anyhow!("expected name:key, got '{}'", s));
+        };
+        Ok(Self {
+            name: name.to_string(),
+            key: key.to_string(),
+            allowed_methods: Vec::new(),
+            allowed_origins: Vec::new(),
+            rps: None,
+            burst: None,
+            labels: BTreeMap::new(),
+        })
+    }
+}
+
+/// Keys are identified by name in usage, metrics and the admin API, so every
+/// configured key needs a name of its own
+pub fn check_names(api_keys: &[ApiKey]) -> Result<()> {
+    let mut names = HashSet::new();
+    for api_key in api_keys {
+        if api_key.name.is_empty() {
+            return Err(anyhow::anyhow!("API key without a name, every key needs one"));
+        }
+        if !names.insert(api_key.name.as_str()) {
+            return Err(anyhow::anyhow!("API key name '{}' is used more than once", api_key.name));
+        }
+    }
+    Ok(())
+}
+
+impl ApiKey {
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods.is_empty() || self.allowed_methods.iter().any(|allowed| allowed == method)
+    }
+    
+    /// Requests without an `Origin` header don't come from a browser and aren't restricted
+    pub fn allows_origin(&self, origin: Option<&str>) -> bool {
+        match origin {
+            Some(origin) => {
+                self.allowed_origins.is_empty()
+                    || self.allowed_origins.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+            }
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthError {
+    /// No key was sent but keys are required
+    Missing,
+    /// The key sent is not configured or was revoked
+    Invalid,
+    /// The key may not be used from the request's origin
+    OriginNotAllowed,
+}
+
+impl AuthError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::Missing | AuthError::Invalid => StatusCode::UNAUTHORIZED,
+            AuthError::OriginNotAllowed => StatusCode::FORBIDDEN,
+        }
+    }
+    
+    pub fn rpc_error(&self) -> RpcError {
+        let (code, message) = match self {
+            AuthError::Missing => (-32001, "API key required"),
+            AuthError::Invalid => (-32001, "Invalid API key"),
+            AuthError::OriginNotAllowed => (-32003, "Origin not allowed for this API key"),
+        };
+        RpcError { code, message: message.to_string(), data: None }
+    }
+}
+
+/// Error for a method the client's key may not call
+pub fn method_not_allowed(method: &str) -> RpcError {
+    RpcError {
+        code: -32601,
+        message: "Method not allowed for this API key".to_string(),
+        data: Some(serde_json::json!({ "method": method })),
+    }
+}
+
+/// Key sent with the request, taken from the path (`/<key>`), the
+/// `x-api-key` header or the `api-key` query parameter, in that order.
+/// Empty ones are skipped.
+pub fn presented_key<'a>(
+    path_key: Option<&'a str>,
+    headers: &'a HeaderMap,
+    query: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+    let header_key = headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok());
+    let query_key = query.get(API_KEY_QUERY_PARAM).map(|key| key.as_str());
+    [path_key, header_key, query_key].into_iter().flatten().find(|key| !key.is_empty())
+}
+
+pub fn request_origin(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::ORIGIN).and_then(|value| value.to_str().ok())
+}
+
+/// Requests made with one key and the rate limit cost they were charged
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct KeyUsage {
+    pub requests: u64,
+    pub cost: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyUsage {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub usage: KeyUsage,
+}
+
+#[derive(Default)]
+struct Keys {
+    /// From the config file or command line, replaced on reload
+    configured: Vec<ApiKey>,
+    /// Added (`Some`) or revoked (`None`) at runtime by name, kept across config reloads
+    overrides: HashMap<String, Option<ApiKey>>,
+    /// Effective keys, by name and by key
+    by_name: BTreeMap<String, Arc<ApiKey>>,
+    by_key: HashMap<String, Arc<ApiKey>>,
+}
+
+impl Keys {
+    fn rebuild(&mut self) {
+        let mut by_name: BTreeMap<String, Arc<ApiKey>> = self
+            .configured
+            .iter()
+            .map(|api_key| (api_key.name.clone(), Arc::new(api_key.clone())))
+            .collect();
+        for (name, api_key) in &self.overrides {
+            match api_key {
+                Some(api_key) => by_name.insert(name.clone(), Arc::new(api_key.clone())),
+                None => by_name.remove(name),
+            };
+        }
+        
+        self.by_key.clear();
+        for api_key in by_name.values() {
+            if let Some(other) = self.by_key.insert(api_key.key.clone(), Arc::clone(api_key)) {
+                warn!("⚠️  API keys '{}' and '{}' share the same key, using '{}'", other.name, api_key.name, api_key.name);
+            }
+        }
+        self.by_name = by_name;
+    }
+}
+
+/// API keys accepted by the proxy, from the configuration plus the ones
+/// managed at runtime through the admin API, and their usage
+#[derive(Default)]
+pub struct ApiKeys {
+    keys: RwLock<Keys>,
+    usage: Mutex<BTreeMap<String, KeyUsage>>,
+}
+
+impl ApiKeys {
+    pub fn new(configured: Vec<ApiKey>) -> Self {
+        let api_keys = Self::default();
+        api_keys.set_configured(configured);
+        api_keys
+    }
+    
+    /// Replace the configured keys, keys added or revoked at runtime stay as they are
+    pub fn set_configured(&self, configured: Vec<ApiKey>) {
+        let mut keys = self.keys.write().unwrap();
+        if keys.configured != configured {
+            keys.configured = configured;
+            keys.rebuild();
+        }
+    }
+    
+    /// Add a key or replace the one with the same name
+    pub fn upsert(&self, api_key: ApiKey) {
+        let mut keys = self.keys.write().unwrap();
+        keys.overrides.insert(api_key.name.clone(), Some(api_key));
+        keys.rebuild();
+    }
+    
+    /// Revoke the key named `name`, returning whether it existed
+    pub fn revoke(&self, name: &str) -> bool {
+        let mut keys = self.keys.write().unwrap();
+        let existed = keys.by_name.contains_key(name);
+        keys.overrides.insert(name.to_string(), None);
+        keys.rebuild();
+        existed
+    }
+    
+    pub fn list(&self) -> Vec<Arc<ApiKey>> {
+        self.keys.read().unwrap().by_name.values().cloned().collect()
+    }
+    
+    pub fn count(&self) -> usize {
+        self.keys.read().unwrap().by_name.len()
+    }
+    
+    /// Key policy for a request. Requests without a key are let through
+    /// anonymously unless `required`, an unknown key is always rejected.
+    pub fn authenticate(
+        &self,
+        presented: Option<&str>,
+        origin: Option<&str>,
+        required: bool,
+    ) -> Result<Option<Arc<ApiKey>>, AuthError> {
+        let Some(presented) = presented else {
+            return if required { Err(AuthError::Missing) } else { Ok(None) };
+        };
+        
+        let api_key = self.keys.read().unwrap().by_key.get(presented).cloned().ok_or(AuthError::Invalid)?;
+        if !api_key.allows_origin(origin) {
+            return Err(AuthError::OriginNotAllowed);
+        }
+        Ok(Some(api_key))
+    }
+    
+    pub fn record_usage(&self, api_key: &ApiKey, requests: u64, cost: u32) {
+        let mut usage = self.usage.lock().unwrap();
+        let entry = usage.entry(api_key.name.clone()).or_default();
+        entry.requests += requests;
+        entry.cost += cost as u64;
+    }
+    
+    /// Usage of every current key, plus revoked keys that were used before
+    pub fn usage(&self) -> Vec<ApiKeyUsage> {
+        let keys = self.keys.read().unwrap();
+        let usage = self.usage.lock().unwrap();
+        
+        let mut names: Vec<&String> = keys.by_name.keys().chain(usage.keys()).collect();
+        names.sort();
+        names.dedup();
+        
+        names
+            .into_iter()
+            .map(|name| ApiKeyUsage {
+                name: name.clone(),
+                labels: keys.by_name.get(name).map(|api_key| api_key.labels.clone()).unwrap_or_default(),
+                usage: usage.get(name).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Compare the admin token without leaking how much of it matched through timing
+pub fn token_matches(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn api_key(name: &str, key: &str) -> ApiKey {
+        format!("{}:{}", name, key).parse().unwrap()
+    }
+    
+    #[test]
+    fn key_names_must_be_present_and_unique() {
+        assert!(check_names(&[api_key("a", "1"), api_key("b", "2")]).is_ok());
+        assert!(check_names(&[api_key("", "1")]).is_err());
+        assert!(check_names(&[api_key("a", "1"), api_key("a", "2")]).is_err());
+        
+        let unnamed: ApiKey = toml::from_str("key = \"secret\"").unwrap();
+        assert!(check_names(&[unnamed]).is_err());
+    }
+    
+    fn origins(allowed_origins: &[&str]) -> ApiKey {
+        ApiKey {
+            allowed_origins: allowed_origins.iter().map(|origin| origin.to_string()).collect(),
+            ..api_key("web", "secret")
+        }
+    }
+    
+    #[test]
+    fn keys_are_taken_from_the_path_then_the_header_then_the_query() {
+        let mut headers = HeaderMap::new();
+        headers.insert(API_KEY_HEADER, "from-header".parse().unwrap());
+        let query = HashMap::from([(API_KEY_QUERY_PARAM.to_string(), "from-query".to_string())]);
+        let no_headers = HeaderMap::new();
+        let no_query = HashMap::new();
+        
+        assert_eq!(presented_key(Some("from-path"), &headers, &query), Some("from-path"));
+        assert_eq!(presented_key(None, &headers, &query), Some("from-header"));
+        assert_eq!(presented_key(None, &no_headers, &query), Some("from-query"));
+        assert_eq!(presented_key(None, &no_headers, &no_query), None);
+        
+        // an empty key counts as none sent
+        assert_eq!(presented_key(Some(""), &headers, &query), Some("from-header"));
+        let mut empty_header = HeaderMap::new();
+        empty_header.insert(API_KEY_HEADER, "".parse().unwrap());
+        assert_eq!(presented_key(None, &empty_header, &query), Some("from-query"));
+        let empty_query = HashMap::from([(API_KEY_QUERY_PARAM.to_string(), String::new())]);
+        assert_eq!(presented_key(Some(""), &empty_header, &empty_query), None);
+    }
+    
+    #[test]
+    fn origins_match_case_insensitively_or_by_wildcard() {
+        assert!(origins(&[]).allows_origin(Some("https://anywhere.example")));
+        
+        let key = origins(&["https://app.example"]);
+        assert!(key.allows_origin(Some("https://app.example")));
+        assert!(key.allows_origin(Some("HTTPS://App.Example")));
+        assert!(!key.allows_origin(Some("https://evil.example")));
+        assert!(!key.allows_origin(Some("https://app.example.evil")));
+        // not a browser
+        assert!(key.allows_origin(None));
+        
+        assert!(origins(&["https://app.example", "*"]).allows_origin(Some("https://other.example")));
+    }
+    
+    #[test]
+    fn unknown_keys_and_foreign_origins_are_refused() {
+        let api_keys = ApiKeys::new(vec![origins(&["https://app.example"])]);
+        assert_eq!(api_keys.authenticate(None, None, false).unwrap(), None);
+        assert_eq!(api_keys.authenticate(None, None, true).unwrap_err(), AuthError::Missing);
+        assert_eq!(api_keys.authenticate(Some("guess"), None, false).unwrap_err(), AuthError::Invalid);
+        assert_eq!(
+            api_keys.authenticate(Some("secret"), Some("https://evil.example"), false).unwrap_err(),
+            AuthError::OriginNotAllowed
+        );
+        assert_eq!(api_keys.authenticate(Some("secret"), Some("https://app.example"), true).unwrap().unwrap().name, "web");
+    }
+    
+    #[test]
+    fn runtime_changes_survive_a_reload() {
+        let api_keys = ApiKeys::new(vec![api_key("acme", "a1"), api_key("globex", "g1")]);
+        api_keys.upsert(api_key("acme", "a2"));
+        api_keys.upsert(api_key("initech", "i1"));
+        assert!(api_keys.revoke("globex"));
+        assert!(!api_keys.revoke("nobody"));
+        
+        // the config now changes every key, runtime changes still win
+        api_keys.set_configured(vec![api_key("acme", "a3"), api_key("globex", "g2"), api_key("umbrella", "u1")]);
+        let names: Vec<String> = api_keys.list().iter().map(|api_key| api_key.name.clone()).collect();
+        assert_eq!(names, vec!["acme", "initech", "umbrella"]);
+        
+        let authenticated = |key: &str| api_keys.authenticate(Some(key), None, true).ok().flatten().map(|api_key| api_key.name.clone());
+        assert_eq!(authenticated("a2").as_deref(), Some("acme"));
+        assert_eq!(authenticated("a1"), None);
+        assert_eq!(authenticated("a3"), None);
+        assert_eq!(authenticated("g2"), None);
+        assert_eq!(authenticated("i1").as_deref(), Some("initech"));
+        assert_eq!(authenticated("u1").as_deref(), Some("umbrella"));
+        
+        // a revoked key can be added back
+        api_keys.upsert(api_key("globex", "g3"));
+        assert_eq!(authenticated("g3").as_deref(), Some("globex"));
+        assert_eq!(api_keys.count(), 4);
+    }
+}
@@ -0,0 +1,296 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::types::RpcRequest;
+
+/// `getVersion` differs between nodes and changes on upgrades, so it is only held briefly
+const VERSION_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CachePolicy {
+    /// Result can never change, kept until evicted by the size limit
+    Immutable,
+    /// Result is served for a short time before going upstream again
+    Ttl(Duration),
+}
+
+impl CachePolicy {
+    fn class(&self) -> usize {
+        match self {
+            CachePolicy::Immutable => 0,
+            CachePolicy::Ttl(_) => 1,
+        }
+    }
+}
+
+/// Commitment requested in a method's config object, if any
+fn requested_commitment(request: &RpcRequest) -> Option<&str> {
+    request
+        .params
+        .as_ref()?
+        .as_array()?
+        .iter()
+        .find_map(|param| param.get("commitment")?.as_str())
+}
+
+/// How `request` may be cached. Commitment-sensitive methods are only cached
+/// when their result is final, or for a TTL short enough to stay within a slot.
+pub fn cache_policy(request: &RpcRequest, short_ttl: Duration) -> Option<CachePolicy> {
+    match request.method.as_str() {
+        "getGenesisHash" => Some(CachePolicy::Immutable),
+        // both default to finalized, confirmed blocks can still be dropped
+        "getBlock" | "getTransaction" => match requested_commitment(request) {
+            None | Some("finalized") => Some(CachePolicy::Immutable),
+            Some(_) => None,
+        },
+        "getVersion" => Some(CachePolicy::Ttl(VERSION_TTL)),
+        "getLatestBlockhash" | "getSlot" | "getEpochInfo" | "getBlockHeight" if !short_ttl.is_zero() => {
+            Some(CachePolicy::Ttl(short_ttl))
+        }
+        _ => None,
+    }
+}
+
+/// Method and params, requests with the same key get the same result
+pub fn request_key(request: &RpcRequest) -> String {
+    match &request.params {
+        Some(params) => format!("{}:{}", request.method, params),
+        None => request.method.clone(),
+    }
+}
+
+struct Entry {
+    result: Arc<str>,
+    expires_at: Option<Instant>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Access tick -> key, oldest first
+    recency: BTreeMap<u64, String>,
+    bytes: usize,
+    tick: u64,
+}
+
+impl Inner {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.bytes -= key.len() + entry.result.len();
+        }
+    }
+    
+    fn evict_to(&mut self, max_bytes: usize) {
+        while self.bytes > max_bytes {
+            let Some((_, key)) = self.recency.pop_first() else { break };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= key.len() + entry.result.len();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheClassStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub immutable: CacheClassStats,
+    pub short_lived: CacheClassStats,
+}
+
+/// Results of cacheable RPC methods, keyed by method and params and bounded
+/// by the total size of the cached results in least recently used order
+#[derive(Default)]
+pub struct ResponseCache {
+    inner: Mutex<Inner>,
+    hits: [AtomicU64; 2],
+    misses: [AtomicU64; 2],
+}
+
+impl ResponseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Cached `result` JSON for `request`, counting the lookup as a hit or miss
+    pub fn get(&self, request: &RpcRequest, policy: CachePolicy) -> Option<Arc<str>> {
+        let key = request_key(request);
+        let mut inner = self.inner.lock().unwrap();
+        
+        let expired = match inner.entries.get(&key) {
+            Some(entry) => entry.expires_at.is_some_and(|expires_at| expires_at <= Instant::now()),
+            None => {
+                drop(inner);
+                self.misses[policy.class()].fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        
+        if expired {
+            inner.remove(&key);
+            drop(inner);
+            self.misses[policy.class()].fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        
+        inner.tick += 1;
+        let tick = inner.tick;
+        let entry = inner.entries.get_mut(&key).unwrap();
+        let previous = std::mem::replace(&mut entry.last_used, tick);
+        let result = Arc::clone(&entry.result);
+        inner.recency.remove(&previous);
+        inner.recency.insert(tick, key);
+        drop(inner);
+        
+        self.hits[policy.class()].fetch_add(1, Ordering::Relaxed);
+        Some(result)
+    }
+    
+    /// Store the result of a successful upstream response. Errors and null
+    /// results (e.g. a transaction the node hasn't seen yet) are not cached.
+    pub fn insert(&self, request: &RpcRequest, policy: CachePolicy, response: &serde_json::Value, max_bytes: usize) {
+        if response.get("error").is_some() {
+            return;
+        }
+        let result = match response.get("result") {
+            Some(result) if !result.is_null() => result.to_string(),
+            _ => return,
+        };
+        
+        let key = request_key(request);
+        if key.len() + result.len() > max_bytes {
+            debug!("💾 Not caching [{}] result of {} bytes, larger than the cache", request.method, result.len());
+            return;
+        }
+        
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(&key);
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.bytes += key.len() + result.len();
+        inner.recency.insert(tick, key.clone());
+        inner.entries.insert(key, Entry {
+            result: result.into(),
+            expires_at: match policy {
+                CachePolicy::Immutable => None,
+                CachePolicy::Ttl(ttl) => Some(Instant::now() + ttl),
+            },
+            last_used: tick,
+        });
+        inner.evict_to(max_bytes);
+    }
+    
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        let class_stats = |class: usize| CacheClassStats {
+            hits: self.hits[class].load(Ordering::Relaxed),
+            misses: self.misses[class].load(Ordering::Relaxed),
+        };
+        
+        CacheStats {
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+            immutable: class_stats(CachePolicy::Immutable.class()),
+            short_lived: class_stats(CachePolicy::Ttl(Duration::ZERO).class()),
+        }
+    }
+}
+
+/// JSON-RPC response for a cached result
+pub fn cached_response(result: &str, id: &serde_json::Value) -> String {
+    format!(r#"{{"jsonrpc":"2.0","result":{},"id":{}}}"#, result, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    const SHORT_TTL: Duration = Duration::from_millis(400);
+    
+    fn request(method: &str, params: serde_json::Value) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(1),
+            method: method.to_string(),
+            params: Some(params),
+        }
+    }
+    
+    #[test]
+    fn finalized_blocks_and_transactions_are_immutable() {
+        for method in ["getBlock", "getTransaction"] {
+            assert_eq!(cache_policy(&request(method, json!([1])), SHORT_TTL), Some(CachePolicy::Immutable));
+            let finalized = request(method, json!([1, {"commitment": "finalized"}]));
+            assert_eq!(cache_policy(&finalized, SHORT_TTL), Some(CachePolicy::Immutable));
+            let confirmed = request(method, json!([1, {"commitment": "confirmed"}]));
+            assert_eq!(cache_policy(&confirmed, SHORT_TTL), None);
+        }
+    }
+    
+    #[test]
+    fn short_lived_methods_follow_the_configured_ttl() {
+        let slot = request("getSlot", json!([]));
+        assert_eq!(cache_policy(&slot, SHORT_TTL), Some(CachePolicy::Ttl(SHORT_TTL)));
+        assert_eq!(cache_policy(&slot, Duration::ZERO), None);
+        
+        let version = request("getVersion", json!([]));
+        assert_eq!(cache_policy(&version, Duration::ZERO), Some(CachePolicy::Ttl(VERSION_TTL)));
+        assert_eq!(cache_policy(&request("getBalance", json!(["a"])), SHORT_TTL), None);
+    }
+    
+    #[test]
+    fn expired_entries_are_misses() {
+        let cache = ResponseCache::new();
+        let slot = request("getSlot", json!([]));
+        let policy = CachePolicy::Ttl(Duration::ZERO);
+        cache.insert(&slot, policy, &json!({"result": 5}), 1024);
+        
+        assert!(cache.get(&slot, policy).is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.short_lived.misses), (0, 1));
+    }
+    
+    #[test]
+    fn errors_and_null_results_are_not_cached() {
+        let cache = ResponseCache::new();
+        let tx = request("getTransaction", json!(["sig"]));
+        cache.insert(&tx, CachePolicy::Immutable, &json!({"result": null}), 1024);
+        cache.insert(&tx, CachePolicy::Immutable, &json!({"error": {"code": -32000}}), 1024);
+        
+        assert!(cache.get(&tx, CachePolicy::Immutable).is_none());
+        
+        cache.insert(&tx, CachePolicy::Immutable, &json!({"result": {"slot": 7}}), 1024);
+        assert_eq!(cache.get(&tx, CachePolicy::Immutable).as_deref(), Some(r#"{"slot":7}"#));
+    }
+    
+    #[test]
+    fn least_recently_used_entries_are_evicted_first() {
+        let cache = ResponseCache::new();
+        let [a, b, c] = ["a", "b", "c"].map(|key| request("getBlock", json!([key])));
+        // room for two entries
+        let max_bytes = 2 * (request_key(&a).len() + 1);
+        for block in [&a, &b] {
+            cache.insert(block, CachePolicy::Immutable, &json!({"result": 1}), max_bytes);
+        }
+        
+        cache.get(&a, CachePolicy::Immutable);
+        cache.insert(&c, CachePolicy::Immutable, &json!({"result": 1}), max_bytes);
+        
+        assert!(cache.get(&a, CachePolicy::Immutable).is_some());
+        assert!(cache.get(&b, CachePolicy::Immutable).is_none());
+        assert!(cache.get(&c, CachePolicy::Immutable).is_some());
+    }
+}
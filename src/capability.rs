@@ -0,0 +1,153 @@
+use crate::types::{RpcNode, RpcRequest};
+use crate::version::{MethodVersions, VersionRange};
+
+/// What a request needs from a node beyond its recent state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Needs {
+    /// Oldest slot the request reads a block of
+    pub slot: Option<u64>,
+    pub transaction_history: bool,
+    pub spl_token_owner_index: bool,
+    pub program_id_index: bool,
+    /// Versions the node has to run
+    pub version: VersionRange,
+}
+
+impl Needs {
+    pub fn of(request: &RpcRequest, method_versions: &MethodVersions) -> Self {
+        Needs {
+            version: method_versions.for_method(&request.method),
+            ..Needs::of_params(request)
+        }
+    }
+    
+    fn of_params(request: &RpcRequest) -> Self {
+        let params = request.params.as_ref().and_then(|params| params.as_array());
+        let param = |index: usize| params.and_then(|params| params.get(index));
+        
+        match request.method.as_str() {
+            "getBlock" | "getBlockTime" | "getBlocks" | "getBlocksWithLimit" => Needs {
+                slot: param(0).and_then(|slot| slot.as_u64()),
+                ..Needs::default()
+            },
+            "getTransaction" | "getSignaturesForAddress" => Needs {
+                transaction_history: true,
+                ..Needs::default()
+            },
+            "getSignatureStatuses" => Needs {
+                transaction_history: param(1)
+                    .and_then(|config| config.get("searchTransactionHistory"))
+                    .and_then(|search| search.as_bool())
+                    .unwrap_or(false),
+                ..Needs::default()
+            },
+            "getTokenAccountsByOwner" => Needs {
+                spl_token_owner_index: true,
+                ..Needs::default()
+            },
+            "getProgramAccounts" => Needs {
+                program_id_index: true,
+                ..Needs::default()
+            },
+            _ => Needs::default(),
+        }
+    }
+    
+    /// Needs of several requests travelling together
+    pub fn of_all<'a>(requests: impl IntoIterator<Item = &'a RpcRequest>, method_versions: &MethodVersions) -> Self {
+        requests
+            .into_iter()
+            .map(|request| Needs::of(request, method_versions))
+            .fold(Needs::default(), |acc, needs| Needs {
+                slot: match (acc.slot, needs.slot) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                },
+                transaction_history: acc.transaction_history || needs.transaction_history,
+                spl_token_owner_index: acc.spl_token_owner_index || needs.spl_token_owner_index,
+                program_id_index: acc.program_id_index || needs.program_id_index,
+                version: acc.version.intersect(needs.version),
+            })
+    }
+    
+    /// Whether `node` runs a version the request may go to. A node whose version
+    /// is unknown only qualifies without a version requirement.
+    pub fn version_allows(&self, node: &RpcNode) -> bool {
+        self.version.contains(node.version)
+    }
+    
+    /// Whether `node` can answer as far as its last capability probe tells.
+    /// A node whose ledger range is unknown is trusted with any slot.
+    pub fn capabilities_met_by(&self, node: &RpcNode) -> bool {
+        let capabilities = &node.capabilities;
+        let slot_available = match (self.slot, capabilities.oldest_slot()) {
+            (Some(slot), Some(oldest)) => slot >= oldest,
+            _ => true,
+        };
+        
+        slot_available
+            && (!self.transaction_history || capabilities.transaction_history)
+            && (!self.spl_token_owner_index || capabilities.spl_token_owner_index)
+            && (!self.program_id_index || capabilities.program_id_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn request(method: &str, params: serde_json::Value) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(1),
+            method: method.to_string(),
+            params: Some(params),
+        }
+    }
+    
+    #[test]
+    fn needs_follow_the_method_and_params() {
+        assert_eq!(Needs::of_params(&request("getBlock", json!([1234]))).slot, Some(1234));
+        assert_eq!(Needs::of_params(&request("getBlocks", json!([10, 20]))).slot, Some(10));
+        assert_eq!(Needs::of_params(&request("getBlockTime", json!(["x"]))).slot, None);
+        assert!(Needs::of_params(&request("getTransaction", json!(["sig"]))).transaction_history);
+        assert!(Needs::of_params(&request("getSignaturesForAddress", json!(["addr"]))).transaction_history);
+        assert!(Needs::of_params(&request("getTokenAccountsByOwner", json!(["owner"]))).spl_token_owner_index);
+        assert!(Needs::of_params(&request("getProgramAccounts", json!(["program"]))).program_id_index);
+        assert_eq!(Needs::of_params(&request("getBalance", json!(["addr"]))), Needs::default());
+    }
+    
+    #[test]
+    fn signature_statuses_only_need_history_when_searching_it() {
+        let recent = request("getSignatureStatuses", json!([["sig"]]));
+        let searching = request("getSignatureStatuses", json!([["sig"], { "searchTransactionHistory": true }]));
+        assert!(!Needs::of_params(&recent).transaction_history);
+        assert!(Needs::of_params(&searching).transaction_history);
+    }
+    
+    #[test]
+    fn batches_need_everything_their_requests_need() {
+        let requests = [
+            request("getBlock", json!([500])),
+            request("getBlock", json!([200])),
+            request("getTransaction", json!(["sig"])),
+        ];
+        let needs = Needs::of_all(&requests, &MethodVersions::default());
+        assert_eq!(needs.slot, Some(200));
+        assert!(needs.transaction_history);
+        assert!(!needs.program_id_index);
+    }
+    
+    #[test]
+    fn unknown_ledger_range_is_trusted_with_any_slot() {
+        let mut node = RpcNode::new("http://a".to_string());
+        let needs = Needs { slot: Some(100), ..Needs::default() };
+        assert!(needs.capabilities_met_by(&node));
+        
+        node.capabilities.first_available_block = Some(150);
+        assert!(!needs.capabilities_met_by(&node));
+        node.capabilities.first_available_block = Some(100);
+        assert!(needs.capabilities_met_by(&node));
+    }
+}
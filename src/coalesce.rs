@@ -0,0 +1,199 @@
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+use crate::cache::request_key;
+use crate::types::{RpcError, RpcRequest};
+
+/// Methods with side effects, every call has to reach a node
+const NON_COALESCED_METHODS: [&str; 2] = ["sendTransaction", "requestAirdrop"];
+
+/// Raw upstream response body, or the error returned to the client
+pub type Outcome = Result<Bytes, (StatusCode, RpcError)>;
+
+pub fn is_coalescable(request: &RpcRequest) -> bool {
+    !NON_COALESCED_METHODS.contains(&request.method.as_str())
+}
+
+/// Identical (method, params) requests currently being forwarded upstream
+#[derive(Default)]
+pub struct InFlightRequests {
+    flights: Mutex<HashMap<String, watch::Receiver<Option<Outcome>>>>,
+}
+
+pub enum Flight<'a> {
+    /// First caller, forwards the request and shares the outcome
+    Leader(FlightLeader<'a>),
+    /// Identical request already in flight, wait for its outcome
+    Follower(FlightFollower),
+}
+
+pub struct FlightLeader<'a> {
+    in_flight: &'a InFlightRequests,
+    /// Taken once the flight is removed, a later flight may reuse the key
+    key: Option<String>,
+    sender: watch::Sender<Option<Outcome>>,
+}
+
+pub struct FlightFollower {
+    receiver: watch::Receiver<Option<Outcome>>,
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn join(&self, request: &RpcRequest) -> Flight<'_> {
+        let key = request_key(request);
+        let mut flights = self.flights.lock().unwrap();
+        
+        if let Some(receiver) = flights.get(&key) {
+            return Flight::Follower(FlightFollower { receiver: receiver.clone() });
+        }
+        
+        let (sender, receiver) = watch::channel(None);
+        flights.insert(key.clone(), receiver);
+        Flight::Leader(FlightLeader { in_flight: self, key: Some(key), sender })
+    }
+}
+
+impl FlightLeader<'_> {
+    pub fn complete(mut self, outcome: &Outcome) {
+        self.land();
+        self.sender.send_replace(Some(outcome.clone()));
+    }
+    
+    fn land(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.in_flight.flights.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+impl Drop for FlightLeader<'_> {
+    // A leader dropped without completing (e.g. its client disconnected) must not leave
+    // the key behind, its followers see the closed channel and forward on their own
+    fn drop(&mut self) {
+        self.land();
+    }
+}
+
+impl FlightFollower {
+    /// The leader's outcome, or `None` if the leader went away without one
+    pub async fn wait(mut self) -> Option<Outcome> {
+        let outcome = self.receiver.wait_for(|outcome| outcome.is_some()).await.ok()?;
+        outcome.clone()
+    }
+}
+
+/// Response body with the JSON-RPC `id` replaced by the caller's own
+pub fn with_id(body: &Bytes, id: &serde_json::Value) -> Bytes {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(mut response) if response.is_object() => {
+            response["id"] = id.clone();
+            Bytes::from(response.to_string())
+        }
+        _ => body.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn request(method: &str, params: serde_json::Value, id: u64) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(id),
+            method: method.to_string(),
+            params: Some(params),
+        }
+    }
+    
+    fn leader(flight: Flight<'_>) -> FlightLeader<'_> {
+        match flight {
+            Flight::Leader(leader) => leader,
+            Flight::Follower(_) => panic!("expected to lead the flight"),
+        }
+    }
+    
+    fn follower(flight: Flight<'_>) -> FlightFollower {
+        match flight {
+            Flight::Follower(follower) => follower,
+            Flight::Leader(_) => panic!("expected to follow the flight"),
+        }
+    }
+    
+    #[tokio::test]
+    async fn followers_get_the_leaders_answer_under_their_own_id() {
+        let in_flight = InFlightRequests::new();
+        let leading = leader(in_flight.join(&request("getBalance", json!(["abc"]), 1)));
+        let followers: Vec<_> = (2..4)
+            .map(|id| {
+                let request = request("getBalance", json!(["abc"]), id);
+                let follower = follower(in_flight.join(&request));
+                tokio::spawn(async move { follower.wait().await.map(|outcome| with_id(&outcome.unwrap(), &request.id)) })
+            })
+            .collect();
+        // other params are another flight
+        drop(leader(in_flight.join(&request("getBalance", json!(["xyz"]), 4))));
+        
+        leading.complete(&Ok(Bytes::from(r#"{"jsonrpc":"2.0","id":1,"result":{"value":7}}"#)));
+        for (follower, id) in followers.into_iter().zip(2..) {
+            let body = follower.await.unwrap().expect("follower got no answer");
+            let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(response, json!({ "jsonrpc": "2.0", "id": id, "result": { "value": 7 } }));
+        }
+        
+        // the next identical request goes upstream again
+        leader(in_flight.join(&request("getBalance", json!(["abc"]), 5)));
+    }
+    
+    #[tokio::test]
+    async fn errors_are_shared_too() {
+        let in_flight = InFlightRequests::new();
+        let leading = leader(in_flight.join(&request("getSlot", json!([]), 1)));
+        let waiting = follower(in_flight.join(&request("getSlot", json!([]), 2)));
+        
+        let error = RpcError { code: -32005, message: "Node is behind".to_string(), data: None };
+        leading.complete(&Err((StatusCode::BAD_GATEWAY, error)));
+        let Some(Err((status, error))) = waiting.wait().await else {
+            panic!("follower didn't get the leader's error");
+        };
+        assert_eq!((status, error.code), (StatusCode::BAD_GATEWAY, -32005));
+    }
+    
+    #[tokio::test]
+    async fn a_dropped_leader_releases_its_followers_and_the_key() {
+        let in_flight = InFlightRequests::new();
+        let leading = leader(in_flight.join(&request("getSlot", json!([]), 1)));
+        let waiting = follower(in_flight.join(&request("getSlot", json!([]), 2)));
+        let waited = tokio::spawn(waiting.wait());
+        
+        drop(leading);
+        assert!(waited.await.unwrap().is_none());
+        assert!(in_flight.flights.lock().unwrap().is_empty());
+        leader(in_flight.join(&request("getSlot", json!([]), 3)));
+    }
+    
+    #[test]
+    fn requests_with_side_effects_are_never_coalesced() {
+        assert!(!is_coalescable(&request("sendTransaction", json!(["AQID"]), 1)));
+        assert!(!is_coalescable(&request("requestAirdrop", json!(["abc", 1]), 1)));
+        assert!(is_coalescable(&request("getBalance", json!(["abc"]), 1)));
+        assert!(is_coalescable(&request("simulateTransaction", json!(["AQID"]), 1)));
+    }
+    
+    #[test]
+    fn with_id_leaves_non_object_bodies_alone() {
+        let batch = Bytes::from(r#"[{"jsonrpc":"2.0","id":1,"result":0}]"#);
+        assert_eq!(with_id(&batch, &json!(9)), batch);
+        let garbled = Bytes::from("not json");
+        assert_eq!(with_id(&garbled, &json!(9)), garbled);
+        assert_eq!(with_id(&Bytes::from(r#"{"id":1}"#), &json!("abc")), Bytes::from(r#"{"id":"abc"}"#));
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+use crate::affinity::AffinityMode;
+use crate::auth::{self, ApiKey};
+use crate::node_cache::{GroupStrategy, NodeWeight, Strategy};
+use crate::probe::{BuiltinProbe, Probe};
+use crate::rate_limit::{MethodCost, TrustedProxy};
+use crate::retry::{MethodRetryPolicy, RetryPolicy};
+use crate::version::{MethodVersion, Version};
+
+/// How often the config file's modification time is checked
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings read from a TOML or YAML config file. Every field mirrors the
+/// command line flag of the same name and is optional; flags given on the
+/// command line take precedence over the file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub port: Option<u16>,
+    pub pubsub_port: Option<u16>,
+    pub cluster_url: Option<String>,
+    pub expected_genesis_hash: Option<String>,
+    pub expected_shred_version: Option<u16>,
+    pub min_node_version: Option<Version>,
+    pub max_node_version: Option<Version>,
+    pub method_versions: Option<Vec<MethodVersion>>,
+    pub gossip_entrypoints: Option<Vec<String>>,
+    pub gossip_timeout: Option<u64>,
+    pub static_nodes: Option<Vec<String>>,
+    pub discovery_interval: Option<u64>,
+    pub evict_after_missed_discoveries: Option<u32>,
+    pub health_check_interval: Option<u64>,
+    pub suspect_check_interval: Option<u64>,
+    pub node_health_timeout: Option<u64>,
+    pub capability_check_interval: Option<u64>,
+    pub builtin_probes: Option<Vec<BuiltinProbe>>,
+    pub probes: Option<Vec<Probe>>,
+    pub rpc_request_timeout: Option<u64>,
+    pub stream_threshold_kb: Option<usize>,
+    pub max_response_mb: Option<usize>,
+    pub max_transfer_time: Option<u64>,
+    pub upstream_pool_max_idle: Option<usize>,
+    pub upstream_pool_idle_timeout: Option<u64>,
+    pub upstream_connect_timeout: Option<u64>,
+    pub disable_upstream_http2: Option<bool>,
+    pub max_concurrent_tests: Option<usize>,
+    pub max_concurrent_rpc_requests: Option<usize>,
+    pub max_queue_wait_time: Option<u64>,
+    pub batch_chunk_size: Option<usize>,
+    pub max_retry_attempts: Option<u32>,
+    pub retry_budget: Option<u64>,
+    pub method_retry_policies: Option<Vec<MethodRetryPolicyEntry>>,
+    pub never_retry_methods: Option<Vec<String>>,
+    pub response_cache_mb: Option<usize>,
+    pub short_cache_ttl_ms: Option<u64>,
+    pub disable_request_coalescing: Option<bool>,
+    pub rate_limit_rps: Option<f64>,
+    pub rate_limit_burst: Option<u32>,
+    pub method_costs: Option<Vec<MethodCost>>,
+    pub trusted_proxies: Option<Vec<TrustedProxy>>,
+    pub api_keys: Option<Vec<ApiKey>>,
+    pub require_api_key: Option<bool>,
+    pub admin_token: Option<String>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub denied_methods: Option<Vec<String>>,
+    pub guarded_programs: Option<Vec<String>>,
+    pub max_multiple_accounts: Option<usize>,
+    pub max_signatures_limit: Option<u64>,
+    pub max_block_range: Option<u64>,
+    pub max_slot_lag: Option<u64>,
+    pub quarantine_after_failures: Option<u32>,
+    pub quarantine_backoff: Option<u64>,
+    pub max_quarantine_backoff: Option<u64>,
+    pub load_balancing: Option<Strategy>,
+    pub group_load_balancing: Option<Vec<GroupStrategy>>,
+    pub node_weights: Option<Vec<NodeWeight>>,
+    pub affinity: Option<AffinityMode>,
+    pub affinity_methods: Option<Vec<String>>,
+    pub hedge_requests: Option<bool>,
+    pub hedge_methods: Option<Vec<String>>,
+    pub hedge_percentile: Option<u8>,
+    pub hedge_budget_percent: Option<u8>,
+    pub verbose: Option<bool>,
+}
+
+/// Table form of `--method-retry-policy`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MethodRetryPolicyEntry {
+    pub method: String,
+    pub max_attempts: u32,
+    pub budget_secs: u64,
+}
+
+impl From<MethodRetryPolicyEntry> for MethodRetryPolicy {
+    fn from(entry: MethodRetryPolicyEntry) -> Self {
+        Self {
+            method: entry.method,
+            policy: RetryPolicy {
+                max_attempts: entry.max_attempts.max(1),
+                budget: Duration::from_secs(entry.budget_secs),
+            },
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Parse `path` as YAML for `.yaml`/`.yml` files and as TOML otherwise
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        
+        let is_yaml = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"));
+        
+        let file: Self = if is_yaml {
+            serde_yaml::from_str(&contents)
+                .with_context(|| format!("invalid YAML in config file {}", path.display()))?
+        } else {
+            toml::from_str(&contents)
+                .with_context(|| format!("invalid TOML in config file {}", path.display()))?
+        };
+        
+        if let Some(api_keys) = &file.api_keys {
+            auth::check_names(api_keys).with_context(|| format!("invalid api_keys in config file {}", path.display()))?;
+        }
+        Ok(file)
+    }
+}
+
+/// Signals when the config file should be re-read: on SIGHUP, or when the
+/// file's modification time changes.
+pub struct ReloadTrigger {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    sighup: tokio::signal::unix::Signal,
+}
+
+impl ReloadTrigger {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let modified = modified_time(&path);
+        Ok(Self {
+            path,
+            modified,
+            #[cfg(unix)]
+            sighup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?,
+        })
+    }
+    
+    pub async fn wait(&mut self) {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        interval.tick().await;
+        
+        loop {
+            tokio::select! {
+                _ = sighup(self) => {
+                    debug!("Received SIGHUP, reloading {}", self.path.display());
+                    self.modified = modified_time(&self.path);
+                    return;
+                }
+                _ = interval.tick() => {
+                    let modified = modified_time(&self.path);
+                    if modified != self.modified {
+                        debug!("Config file {} changed, reloading", self.path.display());
+                        self.modified = modified;
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn sighup(trigger: &mut ReloadTrigger) {
+    trigger.sighup.recv().await;
+}
+
+#[cfg(not(unix))]
+async fn sighup(_trigger: &mut ReloadTrigger) {
+    std::future::pending::<()>().await;
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn load(name: &str, contents: &str) -> Result<ConfigFile> {
+        let path = std::env::temp_dir().join(format!("x1-rpc-proxy-config-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let file = ConfigFile::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+    
+    #[test]
+    fn toml_and_yaml_read_the_same_keys() {
+        let toml = load("same.toml", "port = 9000\nstatic_nodes = [\"http://10.0.0.5:8899\"]\n").unwrap();
+        let yaml = load("same.yml", "port: 9000\nstatic_nodes:\n  - http://10.0.0.5:8899\n").unwrap();
+        for file in [toml, yaml] {
+            assert_eq!(file.port, Some(9000));
+            assert_eq!(file.static_nodes, Some(vec!["http://10.0.0.5:8899".to_string()]));
+            assert_eq!(file.cluster_url, None);
+        }
+    }
+    
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let error = load("typo.toml", "port = 9000\nmax_slot_lagg = 10\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("max_slot_lagg"), "{:#}", error);
+        let error = load("typo.yaml", "port: 9000\nmax_slot_lagg: 10\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("max_slot_lagg"), "{:#}", error);
+        
+        // nested tables too
+        let error = load("nested.toml", "[[method_retry_policies]]\nmethod = \"getBlock\"\nmax_attempts = 1\nbudget_secs = 5\nbudget = 5\n");
+        assert!(error.is_err());
+    }
+    
+    #[test]
+    fn api_keys_need_unique_names() {
+        let keys = "[[api_keys]]\nname = \"acme\"\nkey = \"a\"\n\n[[api_keys]]\nname = \"acme\"\nkey = \"b\"\n";
+        let error = load("keys.toml", keys).unwrap_err();
+        assert!(format!("{:#}", error).contains("'acme' is used more than once"), "{:#}", error);
+        assert!(load("unnamed.toml", "[[api_keys]]\nkey = \"a\"\n").is_err());
+    }
+}
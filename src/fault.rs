@@ -0,0 +1,93 @@
+use reqwest::StatusCode;
+
+/// Who is to blame for an upstream failure or JSON-RPC error, ordered from
+/// least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fault {
+    /// The request itself was refused, every node would answer the same.
+    /// The upstream error is passed to the client unchanged.
+    Request,
+    /// The node is fine but can't answer this request right now (rate limited,
+    /// ledger pruned, block not there yet), another node may
+    Unavailable,
+    /// The node is broken, unreachable or behind the cluster and should stop receiving traffic
+    Node,
+}
+
+impl Fault {
+    /// Whether another node may give a different answer
+    pub fn is_retryable(self) -> bool {
+        self != Fault::Request
+    }
+    
+    /// Whether the node itself is at fault and should be penalized
+    pub fn is_node_failure(self) -> bool {
+        self == Fault::Node
+    }
+}
+
+/// Classify a JSON-RPC error code returned by a node
+pub fn rpc_error_fault(code: i32) -> Fault {
+    match code {
+        // node unhealthy or behind the cluster
+        -32005 => Fault::Node,
+        // block cleaned up, block not available, long-term storage slot skipped,
+        // key excluded from secondary index, transaction history not available,
+        // block status not available yet, min context slot not reached,
+        // long-term storage unreachable
+        -32001 | -32004 | -32009 | -32010 | -32011 | -32014 | -32016 | -32019 => Fault::Unavailable,
+        // internal error on the node
+        -32603 => Fault::Unavailable,
+        _ => Fault::Request,
+    }
+}
+
+/// Classify a non-2xx HTTP status returned by a node
+pub fn status_fault(status: StatusCode) -> Fault {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::REQUEST_TIMEOUT => Fault::Unavailable,
+        // the endpoint isn't an RPC service we may use
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => Fault::Node,
+        status if status.is_client_error() => Fault::Request,
+        _ => Fault::Node,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn rpc_errors_are_classified() {
+        assert_eq!(rpc_error_fault(-32005), Fault::Node);
+        for code in [-32001, -32004, -32009, -32010, -32011, -32014, -32016, -32019, -32603] {
+            assert_eq!(rpc_error_fault(code), Fault::Unavailable, "{}", code);
+        }
+        for code in [-32600, -32601, -32602, -32002, -32003] {
+            assert_eq!(rpc_error_fault(code), Fault::Request, "{}", code);
+        }
+    }
+    
+    #[test]
+    fn http_statuses_are_classified() {
+        assert_eq!(status_fault(StatusCode::TOO_MANY_REQUESTS), Fault::Unavailable);
+        assert_eq!(status_fault(StatusCode::REQUEST_TIMEOUT), Fault::Unavailable);
+        assert_eq!(status_fault(StatusCode::FORBIDDEN), Fault::Node);
+        assert_eq!(status_fault(StatusCode::NOT_FOUND), Fault::Node);
+        assert_eq!(status_fault(StatusCode::BAD_REQUEST), Fault::Request);
+        assert_eq!(status_fault(StatusCode::PAYLOAD_TOO_LARGE), Fault::Request);
+        assert_eq!(status_fault(StatusCode::BAD_GATEWAY), Fault::Node);
+        assert_eq!(status_fault(StatusCode::SERVICE_UNAVAILABLE), Fault::Node);
+    }
+    
+    #[test]
+    fn only_request_faults_are_final() {
+        assert!(!Fault::Request.is_retryable());
+        assert!(Fault::Unavailable.is_retryable());
+        assert!(Fault::Node.is_retryable());
+        
+        assert!(!Fault::Unavailable.is_node_failure());
+        assert!(Fault::Node.is_node_failure());
+        assert!(Fault::Request < Fault::Unavailable && Fault::Unavailable < Fault::Node);
+    }
+}
@@ -1,34 +1,100 @@
 use anyhow::Result;
+use ed25519_dalek::SigningKey;
+use rand::Rng;
 use serde_json::Value;
-use std::process::Command;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
 use tracing::{debug, info, warn};
 
+use crate::gossip_wire::{self, ContactInfo, CrdsFilter, Hash, Protocol, Pubkey};
+use crate::rpc_client::UpstreamClient;
 use crate::types::RpcNode;
+use crate::version::Version;
+
+/// Standard gossip port, used to derive an entrypoint from the cluster URL
+const DEFAULT_GOSSIP_PORT: u16 = 8001;
+
+/// Pull requests split the CRDS hash space into 2^PULL_MASK_BITS partitions,
+/// one bloom filter each, so every request fits in a single packet
+const PULL_MASK_BITS: u32 = 4;
+const PULL_BLOOM_BITS: u64 = 6144;
+const PULL_BLOOM_KEYS: usize = 3;
+const PULL_INTERVAL: Duration = Duration::from_millis(500);
+/// Stop once this many pull rounds in a row brought no new contact info
+const PULL_IDLE_ROUNDS: u32 = 4;
 
 pub struct GossipClient {
     cluster_url: String,
+    entrypoints: Vec<String>,
+    gossip_timeout: Duration,
+    /// Used for the `getClusterNodes` fallback, which gets `rpc_timeout_secs` to answer
+    upstream: UpstreamClient,
+    rpc_timeout_secs: u64,
+    /// Nodes on other shred versions are dropped, the entrypoint's is adopted when unset
+    shred_version: Option<u16>,
+    adopted_shred_version: Mutex<Option<u16>>,
 }
 
 impl GossipClient {
-    pub fn new_with_cluster(cluster_url: &str) -> Self {
+    /// `entrypoints` are gossip `host:port` addresses. When empty, the cluster
+    /// URL's host on the standard gossip port is used.
+    pub fn new_with_cluster(
+        cluster_url: &str,
+        entrypoints: Vec<String>,
+        gossip_timeout: Duration,
+        upstream: UpstreamClient,
+        rpc_timeout_secs: u64,
+    ) -> Self {
+        let entrypoints = if entrypoints.is_empty() {
+            reqwest::Url::parse(cluster_url)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| format!("{}:{}", host, DEFAULT_GOSSIP_PORT)))
+                .into_iter()
+                .collect()
+        } else {
+            entrypoints
+        };
+        
         Self {
             cluster_url: cluster_url.to_string(),
+            entrypoints,
+            gossip_timeout,
+            upstream,
+            rpc_timeout_secs,
+            shred_version: None,
+            adopted_shred_version: Mutex::new(None),
         }
     }
     
+    pub fn with_shred_version(mut self, shred_version: Option<u16>) -> Self {
+        self.shred_version = shred_version;
+        self
+    }
+    
+    /// Shred version discovered nodes must be on: the configured one, otherwise
+    /// the one adopted from a gossip entrypoint, if any answered yet
+    pub fn shred_version(&self) -> Option<u16> {
+        self.shred_version.or(*self.adopted_shred_version.lock().unwrap())
+    }
+    
+    /// Nodes of the cluster from gossip, or its `getClusterNodes` when gossip fails.
+    /// Fails when neither lists any node, see `default_rpc_nodes`.
     pub async fn get_rpc_nodes(&self) -> Result<Vec<RpcNode>> {
         info!("Getting X1 cluster RPC nodes via gossip...");
         
-        // First try using solana gossip command to connect to X1 cluster
-        match self.try_solana_gossip().await {
+        // First pull contact infos straight from the cluster's gossip network
+        match self.try_native_gossip().await {
             Ok(nodes) => {
                 if !nodes.is_empty() {
                     return Ok(nodes);
                 }
-                warn!("Solana gossip command returned no nodes, trying RPC API method");
+                warn!("Gossip returned no RPC nodes, trying RPC API method");
             }
             Err(e) => {
-                warn!("Solana gossip command failed: {}, trying RPC API method", e);
+                warn!("Gossip discovery failed: {}, trying RPC API method", e);
             }
         }
         
@@ -38,82 +104,81 @@ impl GossipClient {
                 if !nodes.is_empty() {
                     return Ok(nodes);
                 }
+                Err(anyhow::anyhow!("neither gossip nor getClusterNodes listed any RPC node"))
             }
             Err(e) => {
                 warn!("RPC API method failed: {}", e);
+                Err(e)
             }
         }
-        
-        // Finally use default nodes
-        self.get_default_rpc_nodes()
     }
     
-    async fn try_solana_gossip(&self) -> Result<Vec<RpcNode>> {
-        // First configure solana CLI to connect to X1 cluster
-        let config_output = Command::new("solana")
-            .args(&["config", "set", "--url", &self.cluster_url])
-            .output()?;
-        
-        if !config_output.status.success() {
-            let stderr = String::from_utf8_lossy(&config_output.stderr);
-            warn!("Failed to configure Solana CLI: {}", stderr);
-        } else {
-            info!("Configured Solana CLI to connect to: {}", self.cluster_url);
-        }
+    /// Pull contact infos from the first entrypoint that answers
+    async fn try_native_gossip(&self) -> Result<Vec<RpcNode>> {
+        let mut last_error = anyhow::anyhow!("no gossip entrypoints configured");
         
-        // Execute solana gossip command to get cluster info
-        let output = Command::new("solana")
-            .args(&["gossip"])
-            .output()?;
-        
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("Gossip command execution failed: {}", stderr));
+        for entrypoint in &self.entrypoints {
+            let addr = match tokio::net::lookup_host(entrypoint.as_str()).await {
+                Ok(mut addrs) => match addrs.next() {
+                    Some(addr) => addr,
+                    None => {
+                        warn!("Gossip entrypoint {} did not resolve", entrypoint);
+                        last_error = anyhow::anyhow!("gossip entrypoint {} resolved to no address", entrypoint);
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    warn!("Failed to resolve gossip entrypoint {}: {}", entrypoint, e);
+                    last_error = e.into();
+                    continue;
+                }
+            };
+            
+            info!("Pulling contact infos from gossip entrypoint {} ({})", entrypoint, addr);
+            match pull_contact_infos(addr, self.gossip_timeout, self.shred_version()).await {
+                Ok((shred_version, contact_infos)) => {
+                    if self.shred_version.is_none() && shred_version != 0 {
+                        *self.adopted_shred_version.lock().unwrap() = Some(shred_version);
+                    }
+                    let nodes: Vec<RpcNode> = contact_infos
+                        .into_iter()
+                        .filter_map(|info| {
+                            let rpc = info.rpc.filter(|addr| !addr.ip().is_unspecified())?;
+                            let pubsub = info.rpc_pubsub.map(|addr| addr.to_string());
+                            let version = info.version.map(|(major, minor, patch)| Version {
+                                major: major.into(),
+                                minor: minor.into(),
+                                patch: patch.into(),
+                            });
+                            Some(RpcNode::new(format!("http://{}", rpc))
+                                .with_pubsub(pubsub)
+                                .with_shred_version(Some(info.shred_version))
+                                .with_version(version, info.feature_set))
+                        })
+                        .collect();
+                    info!("Parsed {} RPC nodes from gossip", nodes.len());
+                    return Ok(nodes);
+                }
+                Err(e) => {
+                    warn!("Gossip pull from {} failed: {}", entrypoint, e);
+                    last_error = e;
+                }
+            }
         }
         
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        debug!("Gossip command output: {}", stdout);
-        
-        self.parse_gossip_output(&stdout)
+        Err(last_error)
     }
     
     async fn try_rpc_cluster_nodes(&self) -> Result<Vec<RpcNode>> {
         info!("Getting cluster nodes via RPC API...");
         
-        let client = reqwest::Client::new();
-        let request_body = serde_json::json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "getClusterNodes"
-        });
-        
-        let response = client
-            .post(&self.cluster_url)
-            .json(&request_body)
-            .send()
-            .await?;
-        
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!("RPC request failed: {}", response.status()));
-        }
-        
-        let rpc_response: Value = response.json().await?;
-        
-        if let Some(error) = rpc_response.get("error") {
-            return Err(anyhow::anyhow!("RPC error: {}", error));
-        }
-        
-        if let Some(result) = rpc_response.get("result") {
-            if let Some(nodes) = result.as_array() {
-                return self.parse_cluster_nodes(nodes);
-            }
-        }
-        
-        Err(anyhow::anyhow!("Invalid RPC response format"))
+        let nodes = self.upstream.get_cluster_nodes(&self.cluster_url, self.rpc_timeout_secs).await?;
+        self.parse_cluster_nodes(&nodes)
     }
     
     fn parse_cluster_nodes(&self, nodes: &[Value]) -> Result<Vec<RpcNode>> {
         let mut rpc_nodes = Vec::new();
+        let expected_shred_version = self.shred_version();
         
         for node in nodes {
             if let Some(rpc_addr) = node.get("rpc") {
@@ -124,7 +189,27 @@ impl GossipClient {
                         } else {
                             format!("http://{}", rpc_str)
                         };
-                        rpc_nodes.push(RpcNode::new(endpoint));
+                        let pubsub = node.get("pubsub")
+                            .and_then(|pubsub| pubsub.as_str())
+                            .filter(|pubsub| !pubsub.is_empty())
+                            .map(|pubsub| pubsub.to_string());
+                        let shred_version = node.get("shredVersion")
+                            .and_then(|version| version.as_u64())
+                            .and_then(|version| u16::try_from(version).ok());
+                        // Nodes on another shred version belong to a different cluster or fork
+                        if matches!((shred_version, expected_shred_version), (Some(actual), Some(expected)) if actual != expected) {
+                            continue;
+                        }
+                        let version = node.get("version")
+                            .and_then(|version| version.as_str())
+                            .and_then(|version| version.parse().ok());
+                        let feature_set = node.get("featureSet")
+                            .and_then(|feature_set| feature_set.as_u64())
+                            .and_then(|feature_set| u32::try_from(feature_set).ok());
+                        rpc_nodes.push(RpcNode::new(endpoint)
+                            .with_pubsub(pubsub)
+                            .with_shred_version(shred_version)
+                            .with_version(version, feature_set));
                     }
                 }
             }
@@ -134,93 +219,239 @@ impl GossipClient {
         Ok(rpc_nodes)
     }
     
-    fn parse_gossip_output(&self, output: &str) -> Result<Vec<RpcNode>> {
-        let mut nodes = Vec::new();
-        let mut node_count = 0;
+    /// Nodes to fall back on when discovery fails: the cluster URL itself, plus a
+    /// local node. Health checks drop any that belong to another cluster.
+    pub fn default_rpc_nodes(&self) -> Vec<RpcNode> {
+        let default_endpoints = vec![
+            self.cluster_url.as_str(),
+            "http://127.0.0.1:8899",     // Local test node
+        ];
         
-        for line in output.lines() {
-            let line = line.trim();
-            
-            // Look for node count information
-            if line.contains("Nodes:") {
-                if let Some(count_str) = line.split("Nodes:").nth(1) {
-                    if let Ok(count) = count_str.trim().parse::<u32>() {
-                        node_count = count;
-                        info!("Gossip discovered {} nodes", node_count);
-                    }
-                }
-                continue;
-            }
-            
-            // Parse node info lines - usually format: IP:PORT | other info
-            if let Some(rpc_addr) = self.extract_rpc_from_gossip_line(line) {
-                let node = RpcNode::new(rpc_addr);
-                nodes.push(node);
-            }
-        }
+        let nodes: Vec<RpcNode> = default_endpoints
+            .into_iter()
+            .map(|endpoint| RpcNode::new(endpoint.to_string()))
+            .collect();
         
-        info!("Parsed {} RPC nodes from gossip (total nodes: {})", nodes.len(), node_count);
-        Ok(nodes)
+        info!("Using default RPC node list with {} nodes", nodes.len());
+        nodes
     }
+}
+
+/// Join the gossip network as a spy and collect contact infos through pull
+/// requests to `entrypoint`. Answers pings so the entrypoint accepts our pulls.
+/// Only nodes on `expected_shred_version` are kept; without one, the
+/// entrypoint's shred version is adopted (a spy starts at 0). Returns the shred
+/// version the nodes were kept for, 0 if none was adopted, with their contact infos.
+pub async fn pull_contact_infos(
+    entrypoint: SocketAddr,
+    timeout: Duration,
+    expected_shred_version: Option<u16>,
+) -> Result<(u16, Vec<ContactInfo>)> {
+    let bind_addr: SocketAddr = if entrypoint.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let socket = UdpSocket::bind(bind_addr).await?;
+    let local_addr = socket.local_addr()?;
+    let keypair = SigningKey::generate(&mut rand::rngs::OsRng);
+    
+    let mut shred_version: u16 = expected_shred_version.unwrap_or(0);
+    let mut seen: HashSet<Hash> = HashSet::new();
+    let mut contact_infos: HashMap<Pubkey, ContactInfo> = HashMap::new();
+    let mut buf = [0u8; gossip_wire::PACKET_DATA_SIZE];
     
-    fn extract_rpc_from_gossip_line(&self, line: &str) -> Option<String> {
-        // Look for lines containing port information
-        if !line.contains(':') {
-            return None;
+    let deadline = Instant::now() + timeout;
+    let mut idle_rounds = 0;
+    let mut known_before_round = 0;
+    
+    while Instant::now() < deadline {
+        let wallclock = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis() as u64;
+        let caller = gossip_wire::signed_contact_info(
+            &keypair,
+            wallclock,
+            shred_version,
+            &[gossip_wire::gossip_socket(local_addr)],
+        );
+        
+        for partition in 0..(1u64 << PULL_MASK_BITS) {
+            let keys = (0..PULL_BLOOM_KEYS).map(|_| rand::thread_rng().gen()).collect();
+            let mut filter = CrdsFilter::new(PULL_BLOOM_BITS, keys, PULL_MASK_BITS, partition);
+            for hash in seen.iter().filter(|hash| gossip_wire::hash_as_u64(hash) >> (64 - PULL_MASK_BITS) == partition) {
+                filter.add(hash);
+            }
+            socket.send_to(&gossip_wire::encode_pull_request(&filter, &caller), entrypoint).await?;
         }
         
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        
-        for part in parts {
-            if part.contains(':') {
-                let addr_parts: Vec<&str> = part.split(':').collect();
-                if addr_parts.len() >= 2 {
-                    let ip = addr_parts[0].trim();
-                    let port_str = addr_parts[1].trim();
-                    
-                    // Extract port number (may have other characters)
-                    let port_only: String = port_str.chars()
-                        .take_while(|c| c.is_ascii_digit())
-                        .collect();
-                    
-                    if let Ok(port) = port_only.parse::<u16>() {
-                        // Check if it's an RPC port
-                        if self.is_rpc_port(port) {
-                            return Some(format!("http://{}:{}", ip, port));
+        let round_end = (Instant::now() + PULL_INTERVAL).min(deadline);
+        while let Ok(Ok((len, from))) = tokio::time::timeout_at(round_end.into(), socket.recv_from(&mut buf)).await {
+            match gossip_wire::decode_packet(&buf[..len]) {
+                Ok(Protocol::Ping { token }) => {
+                    debug!("Answering gossip ping from {}", from);
+                    socket.send_to(&gossip_wire::encode_pong(&keypair, &token), from).await?;
+                }
+                Ok(Protocol::Values { from: responder, values }) => {
+                    for value in values {
+                        seen.insert(value.hash);
+                        let Some(info) = value.contact_info else {
+                            continue;
+                        };
+                        
+                        if shred_version == 0 && info.pubkey == responder && info.shred_version != 0 {
+                            info!("Adopting shred version {} from gossip entrypoint {}", info.shred_version, entrypoint);
+                            shred_version = info.shred_version;
+                        }
+                        
+                        let newer = contact_infos
+                            .get(&info.pubkey)
+                            .is_none_or(|known| known.wallclock < info.wallclock);
+                        if newer {
+                            contact_infos.insert(info.pubkey, info);
                         }
                     }
                 }
+                Ok(_) => {}
+                Err(e) => debug!("Ignoring undecodable gossip packet from {}: {}", from, e),
             }
         }
         
-        None
+        if contact_infos.len() == known_before_round {
+            idle_rounds += 1;
+            if idle_rounds >= PULL_IDLE_ROUNDS && !contact_infos.is_empty() {
+                break;
+            }
+        } else {
+            idle_rounds = 0;
+            known_before_round = contact_infos.len();
+        }
+    }
+    
+    if contact_infos.is_empty() {
+        return Err(anyhow::anyhow!("no contact infos received from {}", entrypoint));
     }
     
-    fn is_rpc_port(&self, port: u16) -> bool {
-        // Common RPC ports
-        match port {
-            8899 => true,  // Standard Solana RPC port
-            8900 => true,  // Alternative RPC port
-            8001 => true,  // Sometimes used as RPC port
-            9090 => true,  // Other common port
-            _ => false,
+    // Nodes on another shred version belong to a different cluster or fork
+    let contact_infos: Vec<ContactInfo> = contact_infos
+        .into_values()
+        .filter(|info| shred_version == 0 || info.shred_version == shred_version)
+        .collect();
+    
+    info!("Gossip pull from {} returned {} contact infos (shred version {})", 
+          entrypoint, contact_infos.len(), shred_version);
+    Ok((shred_version, contact_infos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gossip_stand_in::{AdvertisedNode, LocalGossipNode};
+    use crate::rpc_client::UpstreamClientConfig;
+    
+    const SHRED_VERSION: u16 = 4242;
+    
+    fn advertised(port: u16, shred_version: u16) -> AdvertisedNode {
+        AdvertisedNode {
+            rpc: format!("127.0.0.1:{}", port).parse().unwrap(),
+            rpc_pubsub: format!("127.0.0.1:{}", port + 1).parse().unwrap(),
+            shred_version,
         }
     }
     
-    fn get_default_rpc_nodes(&self) -> Result<Vec<RpcNode>> {
-        // Provide default RPC nodes for X1 cluster
-        let default_endpoints = vec![
-            "https://rpc.testnet.x1.xyz", // X1 testnet official RPC
-            "http://localhost:8899",      // Local test node
-            "http://127.0.0.1:8899",     // Local test node
-        ];
-        
-        let nodes: Vec<RpcNode> = default_endpoints
-            .into_iter()
-            .map(|endpoint| RpcNode::new(endpoint.to_string()))
+    fn client(entrypoints: Vec<String>, gossip_timeout: Duration) -> GossipClient {
+        let upstream = UpstreamClient::new(&UpstreamClientConfig {
+            pool_max_idle_per_node: 1,
+            pool_idle_timeout: Duration::from_secs(1),
+            connect_timeout: Duration::from_secs(1),
+            http2: false,
+        })
+        .unwrap();
+        GossipClient::new_with_cluster("http://127.0.0.1:1", entrypoints, gossip_timeout, upstream, 1)
+    }
+    
+    fn endpoints(nodes: &[RpcNode]) -> Vec<(String, Option<String>)> {
+        let mut endpoints: Vec<(String, Option<String>)> = nodes
+            .iter()
+            .map(|node| (node.endpoint.clone(), node.pubsub.clone()))
             .collect();
+        endpoints.sort();
+        endpoints
+    }
+    
+    async fn stand_in() -> LocalGossipNode {
+        LocalGossipNode::spawn(SHRED_VERSION, vec![
+            advertised(18_001, SHRED_VERSION),
+            advertised(18_011, SHRED_VERSION),
+            advertised(18_021, SHRED_VERSION + 1),
+        ])
+        .await
+        .unwrap()
+    }
+    
+    // The stand-in pings unknown callers before answering their pulls and only
+    // gives callers on shred version 0 its own contact info, so finding the nodes
+    // takes the ping/pong handshake and adopting the entrypoint's shred version.
+    #[tokio::test]
+    async fn discovers_nodes_through_a_gossip_entrypoint() {
+        let entrypoint = stand_in().await;
+        
+        let client = client(vec![entrypoint.addr().to_string()], Duration::from_secs(5));
+        assert_eq!(client.shred_version(), None);
+        let nodes = client.try_native_gossip().await.unwrap();
         
-        info!("Using default X1 cluster RPC node list with {} nodes", nodes.len());
-        Ok(nodes)
+        assert_eq!(client.shred_version(), Some(SHRED_VERSION));
+        assert_eq!(endpoints(&nodes), vec![
+            ("http://127.0.0.1:18001".to_string(), Some("127.0.0.1:18002".to_string())),
+            ("http://127.0.0.1:18011".to_string(), Some("127.0.0.1:18012".to_string())),
+        ]);
+        assert!(nodes.iter().all(|node| node.shred_version == Some(SHRED_VERSION)));
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn expected_shred_version_is_used_from_the_start() {
+        let entrypoint = stand_in().await;
+        
+        let nodes = client(vec![entrypoint.addr().to_string()], Duration::from_secs(5))
+            .with_shred_version(Some(SHRED_VERSION))
+            .try_native_gossip()
+            .await
+            .unwrap();
+        assert_eq!(nodes.len(), 2);
+        
+        // an entrypoint on another shred version never answers
+        let result = client(vec![entrypoint.addr().to_string()], Duration::from_secs(2))
+            .with_shred_version(Some(SHRED_VERSION + 7))
+            .try_native_gossip()
+            .await;
+        assert!(result.is_err());
+    }
+    
+    #[tokio::test]
+    async fn unresolvable_entrypoints_are_skipped() {
+        let entrypoint = stand_in().await;
+        
+        let nodes = client(vec!["gossip.invalid:8001".to_string(), entrypoint.addr().to_string()], Duration::from_secs(5))
+            .try_native_gossip()
+            .await
+            .unwrap();
+        assert_eq!(nodes.len(), 2);
+    }
+    
+    #[test]
+    fn cluster_nodes_on_another_shred_version_are_dropped() {
+        let cluster_nodes = serde_json::json!([
+            { "rpc": "10.0.0.1:8899", "shredVersion": SHRED_VERSION },
+            { "rpc": "10.0.0.2:8899", "shredVersion": SHRED_VERSION + 1 },
+            { "rpc": "10.0.0.3:8899" },
+            { "rpc": null, "shredVersion": SHRED_VERSION },
+        ]);
+        let cluster_nodes = cluster_nodes.as_array().unwrap();
+        
+        let nodes = client(vec![], Duration::from_secs(1)).parse_cluster_nodes(cluster_nodes).unwrap();
+        assert_eq!(nodes.len(), 3);
+        
+        let nodes = client(vec![], Duration::from_secs(1))
+            .with_shred_version(Some(SHRED_VERSION))
+            .parse_cluster_nodes(cluster_nodes)
+            .unwrap();
+        let endpoints: Vec<&str> = nodes.iter().map(|node| node.endpoint.as_str()).collect();
+        assert_eq!(endpoints, vec!["http://10.0.0.1:8899", "http://10.0.0.3:8899"]);
+    }
+}
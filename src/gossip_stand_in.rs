@@ -0,0 +1,142 @@
+use anyhow::Result;
+use ed25519_dalek::SigningKey;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use crate::gossip_wire::{self, Hash, Protocol, PACKET_DATA_SIZE};
+
+// Local stand-in for a gossip entrypoint, so discovery can be exercised
+// without a cluster. It follows the entrypoint rules a spy has to deal with:
+// unknown senders are pinged before their pulls are answered, and callers on
+// shred version 0 only learn the entrypoint's own contact info.
+
+/// An RPC node the stand-in advertises through gossip
+#[derive(Debug, Clone, Copy)]
+pub struct AdvertisedNode {
+    pub rpc: SocketAddr,
+    pub rpc_pubsub: SocketAddr,
+    pub shred_version: u16,
+}
+
+pub struct LocalGossipNode {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl LocalGossipNode {
+    /// Bind on localhost and answer gossip traffic in the background until dropped
+    pub async fn spawn(shred_version: u16, nodes: Vec<AdvertisedNode>) -> Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+        let keypair = SigningKey::generate(&mut rand::rngs::OsRng);
+        let wallclock = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis() as u64;
+        
+        let own_value = gossip_wire::signed_contact_info(
+            &keypair,
+            wallclock,
+            shred_version,
+            &[gossip_wire::gossip_socket(addr)],
+        );
+        
+        let mut values = vec![own_value.clone()];
+        for node in nodes {
+            let node_keypair = SigningKey::generate(&mut rand::rngs::OsRng);
+            values.push(gossip_wire::signed_contact_info(
+                &node_keypair,
+                wallclock,
+                node.shred_version,
+                &gossip_wire::rpc_sockets(node.rpc, node.rpc_pubsub),
+            ));
+        }
+        
+        let handle = tokio::spawn(serve(socket, keypair, shred_version, own_value, values));
+        Ok(Self { addr, handle })
+    }
+    
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for LocalGossipNode {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve(socket: UdpSocket, keypair: SigningKey, shred_version: u16, own_value: Vec<u8>, values: Vec<Vec<u8>>) {
+    let pubkey = keypair.verifying_key().to_bytes();
+    let mut pending_pongs: HashMap<SocketAddr, Hash> = HashMap::new();
+    let mut verified: HashSet<SocketAddr> = HashSet::new();
+    let mut buf = [0u8; gossip_wire::PACKET_DATA_SIZE];
+    
+    while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+        match gossip_wire::decode_packet(&buf[..len]) {
+            Ok(Protocol::PullRequest { filter, caller }) => {
+                if !verified.contains(&from) {
+                    let token: Hash = rand::thread_rng().gen();
+                    pending_pongs.insert(from, gossip_wire::pong_hash(&token));
+                    let _ = socket.send_to(&gossip_wire::encode_ping(&keypair, &token), from).await;
+                    continue;
+                }
+                
+                let caller_shred_version = caller.contact_info.map(|info| info.shred_version).unwrap_or(0);
+                let candidates: Vec<&Vec<u8>> = if caller_shred_version == shred_version {
+                    values.iter().collect()
+                } else if caller_shred_version == 0 {
+                    vec![&own_value]
+                } else {
+                    continue;
+                };
+                
+                let matching: Vec<Vec<u8>> = candidates
+                    .into_iter()
+                    .filter(|value| {
+                        let hash = gossip_wire::sha256(value);
+                        filter.test_mask(&hash) && !filter.contains(&hash)
+                    })
+                    .cloned()
+                    .collect();
+                
+                for packet in pack_values(&pubkey, matching) {
+                    let _ = socket.send_to(&packet, from).await;
+                }
+            }
+            Ok(Protocol::Pong { hash }) if pending_pongs.get(&from) == Some(&hash) => {
+                pending_pongs.remove(&from);
+                verified.insert(from);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Split values into pull response packets that fit the gossip packet size
+fn pack_values(pubkey: &[u8; 32], values: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    // protocol tag + responder pubkey + value count
+    const HEADER_SIZE: usize = 4 + 32 + 8;
+    
+    let mut packets = Vec::new();
+    let mut batch: Vec<Vec<u8>> = Vec::new();
+    let mut batch_size = HEADER_SIZE;
+    
+    for value in values {
+        if !batch.is_empty() && batch_size + value.len() > PACKET_DATA_SIZE {
+            packets.push(gossip_wire::encode_pull_response(pubkey, &batch));
+            batch.clear();
+            batch_size = HEADER_SIZE;
+        }
+        batch_size += value.len();
+        batch.push(value);
+    }
+    
+    if !batch.is_empty() {
+        packets.push(gossip_wire::encode_pull_response(pubkey, &batch));
+    }
+    packets
+}
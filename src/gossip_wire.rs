@@ -0,0 +1,925 @@
+use anyhow::Result;
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// Wire format of the Solana gossip protocol (bincode), limited to what a
+// pull-only discovery client needs: ping/pong, pull requests/responses and
+// contact infos. Every other CRDS value is decoded only far enough to skip it.
+
+/// Maximum UDP payload a gossip node accepts
+pub const PACKET_DATA_SIZE: usize = 1232;
+
+const PING_PONG_HASH_PREFIX: &[u8] = b"SOLANA_PING_PONG";
+
+const PROTOCOL_PULL_REQUEST: u32 = 0;
+const PROTOCOL_PULL_RESPONSE: u32 = 1;
+const PROTOCOL_PUSH_MESSAGE: u32 = 2;
+const PROTOCOL_PING: u32 = 4;
+const PROTOCOL_PONG: u32 = 5;
+
+const CRDS_LEGACY_CONTACT_INFO: u32 = 0;
+const CRDS_CONTACT_INFO: u32 = 11;
+
+const SOCKET_TAG_GOSSIP: u8 = 0;
+const SOCKET_TAG_RPC: u8 = 2;
+const SOCKET_TAG_RPC_PUBSUB: u8 = 3;
+
+pub type Pubkey = [u8; 32];
+pub type Hash = [u8; 32];
+
+/// The parts of a node's gossip ContactInfo the proxy cares about
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactInfo {
+    pub pubkey: Pubkey,
+    pub wallclock: u64,
+    pub shred_version: u16,
+    /// `major.minor.patch` of the node software, not in legacy contact infos
+    pub version: Option<(u16, u16, u16)>,
+    pub feature_set: Option<u32>,
+    pub gossip: Option<SocketAddr>,
+    pub rpc: Option<SocketAddr>,
+    pub rpc_pubsub: Option<SocketAddr>,
+}
+
+/// Decoded gossip packet
+pub enum Protocol {
+    #[cfg(any(test, feature = "gossip-stand-in"))]
+    PullRequest { filter: CrdsFilter, caller: Box<CrdsValue> },
+    /// Values pushed or pulled from `from`
+    Values { from: Pubkey, values: Vec<CrdsValue> },
+    Ping { token: Hash },
+    #[cfg(any(test, feature = "gossip-stand-in"))]
+    Pong { hash: Hash },
+    Other,
+}
+
+/// A signed CRDS value. `contact_info` is set when the value is a (legacy) ContactInfo.
+pub struct CrdsValue {
+    pub hash: Hash,
+    pub contact_info: Option<ContactInfo>,
+}
+
+/// Bloom filter over CRDS value hashes plus the mask selecting which part
+/// of the hash space a pull request covers
+pub struct CrdsFilter {
+    keys: Vec<u64>,
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_bits_set: u64,
+    mask: u64,
+    mask_bits: u32,
+}
+
+impl CrdsFilter {
+    /// Empty filter covering partition `index` of `2^mask_bits` partitions
+    pub fn new(num_bits: u64, keys: Vec<u64>, mask_bits: u32, index: u64) -> Self {
+        let ones = (!0u64).checked_shr(mask_bits).unwrap_or(0);
+        let mask = if mask_bits == 0 { !0u64 } else { (index << (64 - mask_bits)) | ones };
+        Self {
+            keys,
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_bits_set: 0,
+            mask,
+            mask_bits,
+        }
+    }
+    
+    /// Whether `hash` belongs to the partition this filter covers
+    #[cfg(any(test, feature = "gossip-stand-in"))]
+    pub fn test_mask(&self, hash: &Hash) -> bool {
+        let ones = (!0u64).checked_shr(self.mask_bits).unwrap_or(!0u64);
+        (hash_as_u64(hash) | ones) == self.mask
+    }
+    
+    pub fn add(&mut self, hash: &Hash) {
+        for key in &self.keys {
+            let pos = bloom_pos(*key, hash, self.num_bits);
+            let (word, bit) = ((pos / 64) as usize, pos % 64);
+            if self.bits[word] & (1 << bit) == 0 {
+                self.bits[word] |= 1 << bit;
+                self.num_bits_set += 1;
+            }
+        }
+    }
+    
+    #[cfg(any(test, feature = "gossip-stand-in"))]
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.keys.iter().all(|key| {
+            let pos = bloom_pos(*key, hash, self.num_bits);
+            self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0
+        })
+    }
+    
+    fn encode(&self, writer: &mut Writer) {
+        writer.u64(self.keys.len() as u64);
+        for key in &self.keys {
+            writer.u64(*key);
+        }
+        // bv::BitVec<u64>: Option<Box<[u64]>> followed by the length in bits
+        writer.u8(1);
+        writer.u64(self.bits.len() as u64);
+        for word in &self.bits {
+            writer.u64(*word);
+        }
+        writer.u64(self.num_bits);
+        writer.u64(self.num_bits_set);
+        writer.u64(self.mask);
+        writer.u32(self.mask_bits);
+    }
+    
+    #[cfg(any(test, feature = "gossip-stand-in"))]
+    fn decode(reader: &mut Reader) -> Result<Self> {
+        let num_keys = reader.len()?;
+        let keys = (0..num_keys).map(|_| reader.u64()).collect::<Result<Vec<_>>>()?;
+        let bits = match reader.u8()? {
+            0 => Vec::new(),
+            _ => {
+                let words = reader.len()?;
+                (0..words).map(|_| reader.u64()).collect::<Result<Vec<_>>>()?
+            }
+        };
+        let num_bits = reader.u64()?;
+        if num_bits == 0 || num_bits > bits.len() as u64 * 64 {
+            return Err(anyhow::anyhow!("invalid bloom filter size"));
+        }
+        Ok(Self {
+            keys,
+            bits,
+            num_bits,
+            num_bits_set: reader.u64()?,
+            mask: reader.u64()?,
+            mask_bits: reader.u32()?,
+        })
+    }
+}
+
+pub fn hash_as_u64(hash: &Hash) -> u64 {
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
+
+/// 64-bit FNV-1a seeded with `key`, the per-key hash used by gossip bloom filters
+fn fnv1a(key: u64, data: &[u8]) -> u64 {
+    data.iter().fold(key, |state, byte| (state ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Filter bit of `hash` for `key`. Validators hash filter items through
+/// `std::hash::Hash`, which feeds an array to the hasher after its length as a
+/// little-endian usize.
+fn bloom_pos(key: u64, hash: &Hash, num_bits: u64) -> u64 {
+    let state = fnv1a(key, &(hash.len() as u64).to_le_bytes());
+    fnv1a(state, hash) % num_bits
+}
+
+pub fn sha256(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+pub fn pong_hash(token: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(PING_PONG_HASH_PREFIX);
+    hasher.update(token);
+    hasher.finalize().into()
+}
+
+/// Signed ContactInfo value advertising `sockets` as (socket tag, address) pairs
+pub fn signed_contact_info(
+    keypair: &SigningKey,
+    wallclock: u64,
+    shred_version: u16,
+    sockets: &[(u8, SocketAddr)],
+) -> Vec<u8> {
+    let mut data = Writer::default();
+    data.u32(CRDS_CONTACT_INFO);
+    data.bytes(keypair.verifying_key().as_bytes());
+    data.varint(wallclock);
+    data.u64(wallclock); // outset
+    data.u16(shred_version);
+    
+    // Version { major, minor, patch, commit, feature_set, client }
+    data.varint(0);
+    data.varint(0);
+    data.varint(0);
+    data.u32(0);
+    data.u32(0);
+    data.varint(0);
+    
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for (_, addr) in sockets {
+        if !addrs.contains(&addr.ip()) {
+            addrs.push(addr.ip());
+        }
+    }
+    data.short_vec_len(addrs.len());
+    for ip in &addrs {
+        match ip {
+            IpAddr::V4(ip) => {
+                data.u32(0);
+                data.bytes(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                data.u32(1);
+                data.bytes(&ip.octets());
+            }
+        }
+    }
+    
+    // Socket entries are sorted by port, each port stored as an offset from the previous one
+    let mut entries: Vec<(u8, SocketAddr)> = sockets.to_vec();
+    entries.sort_by_key(|(_, addr)| addr.port());
+    data.short_vec_len(entries.len());
+    let mut port = 0u16;
+    for (tag, addr) in &entries {
+        data.u8(*tag);
+        data.u8(addrs.iter().position(|ip| *ip == addr.ip()).unwrap_or(0) as u8);
+        data.varint((addr.port() - port) as u64);
+        port = addr.port();
+    }
+    
+    data.short_vec_len(0); // extensions
+    
+    let data = data.into_inner();
+    let mut value = Writer::default();
+    value.bytes(&keypair.sign(&data).to_bytes());
+    value.bytes(&data);
+    value.into_inner()
+}
+
+/// Socket tags for the addresses a node advertises
+pub fn gossip_socket(addr: SocketAddr) -> (u8, SocketAddr) {
+    (SOCKET_TAG_GOSSIP, addr)
+}
+
+#[cfg(any(test, feature = "gossip-stand-in"))]
+pub fn rpc_sockets(rpc: SocketAddr, rpc_pubsub: SocketAddr) -> [(u8, SocketAddr); 2] {
+    [(SOCKET_TAG_RPC, rpc), (SOCKET_TAG_RPC_PUBSUB, rpc_pubsub)]
+}
+
+pub fn encode_pull_request(filter: &CrdsFilter, caller: &[u8]) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.u32(PROTOCOL_PULL_REQUEST);
+    filter.encode(&mut writer);
+    writer.bytes(caller);
+    writer.into_inner()
+}
+
+/// Pull response carrying already-signed values
+#[cfg(any(test, feature = "gossip-stand-in"))]
+pub fn encode_pull_response(from: &Pubkey, values: &[Vec<u8>]) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.u32(PROTOCOL_PULL_RESPONSE);
+    writer.bytes(from);
+    writer.u64(values.len() as u64);
+    for value in values {
+        writer.bytes(value);
+    }
+    writer.into_inner()
+}
+
+#[cfg(any(test, feature = "gossip-stand-in"))]
+pub fn encode_ping(keypair: &SigningKey, token: &Hash) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.u32(PROTOCOL_PING);
+    writer.bytes(keypair.verifying_key().as_bytes());
+    writer.bytes(token);
+    writer.bytes(&keypair.sign(token).to_bytes());
+    writer.into_inner()
+}
+
+pub fn encode_pong(keypair: &SigningKey, token: &Hash) -> Vec<u8> {
+    let hash = pong_hash(token);
+    let mut writer = Writer::default();
+    writer.u32(PROTOCOL_PONG);
+    writer.bytes(keypair.verifying_key().as_bytes());
+    writer.bytes(&hash);
+    writer.bytes(&keypair.sign(&hash).to_bytes());
+    writer.into_inner()
+}
+
+/// Decode a gossip packet. Values after one that can't be decoded are dropped,
+/// since bincode gives no way to find where the next one starts.
+pub fn decode_packet(packet: &[u8]) -> Result<Protocol> {
+    let mut reader = Reader::new(packet);
+    
+    match reader.u32()? {
+        #[cfg(any(test, feature = "gossip-stand-in"))]
+        PROTOCOL_PULL_REQUEST => {
+            let filter = CrdsFilter::decode(&mut reader)?;
+            let caller = decode_crds_value(&mut reader)?;
+            Ok(Protocol::PullRequest { filter, caller: Box::new(caller) })
+        }
+        PROTOCOL_PULL_RESPONSE | PROTOCOL_PUSH_MESSAGE => {
+            let from = reader.array32()?;
+            let count = reader.len()?;
+            let mut values = Vec::new();
+            for _ in 0..count {
+                match decode_crds_value(&mut reader) {
+                    Ok(value) => values.push(value),
+                    Err(_) => break,
+                }
+            }
+            Ok(Protocol::Values { from, values })
+        }
+        PROTOCOL_PING => {
+            reader.skip(32)?; // from
+            let token = reader.array32()?;
+            Ok(Protocol::Ping { token })
+        }
+        #[cfg(any(test, feature = "gossip-stand-in"))]
+        PROTOCOL_PONG => {
+            reader.skip(32)?; // from
+            let hash = reader.array32()?;
+            Ok(Protocol::Pong { hash })
+        }
+        _ => Ok(Protocol::Other),
+    }
+}
+
+fn decode_crds_value(reader: &mut Reader) -> Result<CrdsValue> {
+    let start = reader.pos;
+    reader.skip(64)?; // signature
+    
+    let contact_info = match reader.u32()? {
+        CRDS_LEGACY_CONTACT_INFO => Some(decode_legacy_contact_info(reader)?),
+        CRDS_CONTACT_INFO => Some(decode_contact_info(reader)?),
+        tag => {
+            skip_crds_data(reader, tag)?;
+            None
+        }
+    };
+    
+    Ok(CrdsValue {
+        hash: sha256(&reader.data[start..reader.pos]),
+        contact_info,
+    })
+}
+
+fn decode_contact_info(reader: &mut Reader) -> Result<ContactInfo> {
+    let pubkey = reader.array32()?;
+    let wallclock = reader.varint()?;
+    reader.u64()?; // outset
+    let shred_version = reader.u16()?;
+    
+    // Version { major, minor, patch, commit, feature_set, client }
+    let major = reader.varint()? as u16;
+    let minor = reader.varint()? as u16;
+    let patch = reader.varint()? as u16;
+    reader.u32()?;
+    let feature_set = reader.u32()?;
+    reader.varint()?;
+    
+    let num_addrs = reader.short_vec_len()?;
+    let mut addrs = Vec::with_capacity(num_addrs);
+    for _ in 0..num_addrs {
+        addrs.push(reader.ip_addr()?);
+    }
+    
+    let mut info = ContactInfo {
+        pubkey,
+        wallclock,
+        shred_version,
+        version: Some((major, minor, patch)),
+        feature_set: Some(feature_set),
+        gossip: None,
+        rpc: None,
+        rpc_pubsub: None,
+    };
+    
+    let num_sockets = reader.short_vec_len()?;
+    let mut port = 0u16;
+    for _ in 0..num_sockets {
+        let tag = reader.u8()?;
+        let index = reader.u8()? as usize;
+        port = port.wrapping_add(reader.varint()? as u16);
+        let Some(ip) = addrs.get(index) else {
+            continue;
+        };
+        let addr = SocketAddr::new(*ip, port);
+        match tag {
+            SOCKET_TAG_GOSSIP => info.gossip = Some(addr),
+            SOCKET_TAG_RPC => info.rpc = Some(addr),
+            SOCKET_TAG_RPC_PUBSUB => info.rpc_pubsub = Some(addr),
+            _ => {}
+        }
+    }
+    
+    // Extensions have no defined variants yet
+    if reader.short_vec_len()? != 0 {
+        return Err(anyhow::anyhow!("unsupported contact info extension"));
+    }
+    
+    Ok(info)
+}
+
+fn decode_legacy_contact_info(reader: &mut Reader) -> Result<ContactInfo> {
+    let pubkey = reader.array32()?;
+    // gossip, tvu, tvu_quic, repair, tpu, tpu_forwards, tpu_vote, rpc, rpc_pubsub, serve_repair
+    let mut sockets = Vec::with_capacity(10);
+    for _ in 0..10 {
+        sockets.push(reader.socket_addr()?);
+    }
+    let wallclock = reader.u64()?;
+    let shred_version = reader.u16()?;
+    
+    let specified = |addr: SocketAddr| (!addr.ip().is_unspecified() && addr.port() != 0).then_some(addr);
+    Ok(ContactInfo {
+        pubkey,
+        wallclock,
+        shred_version,
+        version: None,
+        feature_set: None,
+        gossip: specified(sockets[0]),
+        rpc: specified(sockets[7]),
+        rpc_pubsub: specified(sockets[8]),
+    })
+}
+
+/// Advance past a CRDS value that isn't a contact info
+fn skip_crds_data(reader: &mut Reader, tag: u32) -> Result<()> {
+    match tag {
+        // Vote(index, Vote { from, transaction, wallclock })
+        1 => {
+            reader.skip(1 + 32)?;
+            skip_transaction(reader)?;
+            reader.skip(8)
+        }
+        // LowestSlot(index, LowestSlot { from, root, lowest, slots, stash, wallclock })
+        2 => {
+            reader.skip(1 + 32 + 8 + 8)?;
+            let slots = reader.len()?;
+            reader.skip(slots * 8)?;
+            let stash = reader.len()?;
+            for _ in 0..stash {
+                reader.skip(8 + 4)?;
+                let compressed = reader.len()?;
+                reader.skip(compressed)?;
+            }
+            reader.skip(8)
+        }
+        // LegacySnapshotHashes / AccountsHashes { from, hashes: Vec<(Slot, Hash)>, wallclock }
+        3 | 4 => {
+            reader.skip(32)?;
+            let hashes = reader.len()?;
+            reader.skip(hashes * 40 + 8)
+        }
+        // EpochSlots(index, EpochSlots { from, slots: Vec<CompressedSlots>, wallclock })
+        5 => {
+            reader.skip(1 + 32)?;
+            let slots = reader.len()?;
+            for _ in 0..slots {
+                match reader.u32()? {
+                    0 => {
+                        reader.skip(16)?;
+                        let compressed = reader.len()?;
+                        reader.skip(compressed)?;
+                    }
+                    _ => {
+                        reader.skip(16)?;
+                        skip_bit_vec_u8(reader)?;
+                    }
+                }
+            }
+            reader.skip(8)
+        }
+        // LegacyVersion { from, wallclock, version { major, minor, patch, commit: Option<u32> } }
+        6 => {
+            reader.skip(32 + 8 + 6)?;
+            skip_option_u32(reader)
+        }
+        // Version { from, wallclock, version { .., commit: Option<u32>, feature_set } }
+        7 => {
+            reader.skip(32 + 8 + 6)?;
+            skip_option_u32(reader)?;
+            reader.skip(4)
+        }
+        // NodeInstance { from, wallclock, timestamp, token }
+        8 => reader.skip(32 + 8 + 8 + 8),
+        // DuplicateShred(index, { from, wallclock, slot, _, shred_type, num_chunks, chunk_index, chunk })
+        9 => {
+            reader.skip(2 + 32 + 8 + 8 + 4 + 1 + 1 + 1)?;
+            let chunk = reader.len()?;
+            reader.skip(chunk)
+        }
+        // SnapshotHashes { from, full: (Slot, Hash), incremental: Vec<(Slot, Hash)>, wallclock }
+        10 => {
+            reader.skip(32 + 40)?;
+            let incremental = reader.len()?;
+            reader.skip(incremental * 40 + 8)
+        }
+        // RestartLastVotedForkSlots { from, wallclock, offsets, last_voted_slot, last_voted_hash, shred_version }
+        12 => {
+            reader.skip(32 + 8)?;
+            match reader.u32()? {
+                0 => {
+                    let offsets = reader.len()?;
+                    reader.skip(offsets * 2)?;
+                }
+                _ => skip_bit_vec_u8(reader)?,
+            }
+            reader.skip(8 + 32 + 2)
+        }
+        // RestartHeaviestFork { from, wallclock, last_slot, last_slot_hash, observed_stake, shred_version }
+        13 => reader.skip(32 + 8 + 8 + 32 + 8 + 2),
+        _ => Err(anyhow::anyhow!("unknown CRDS value type {}", tag)),
+    }
+}
+
+fn skip_transaction(reader: &mut Reader) -> Result<()> {
+    let signatures = reader.short_vec_len()?;
+    reader.skip(signatures * 64)?;
+    reader.skip(3)?; // message header
+    let account_keys = reader.short_vec_len()?;
+    reader.skip(account_keys * 32 + 32)?;
+    let instructions = reader.short_vec_len()?;
+    for _ in 0..instructions {
+        reader.skip(1)?;
+        let accounts = reader.short_vec_len()?;
+        reader.skip(accounts)?;
+        let data = reader.short_vec_len()?;
+        reader.skip(data)?;
+    }
+    Ok(())
+}
+
+fn skip_bit_vec_u8(reader: &mut Reader) -> Result<()> {
+    if reader.u8()? != 0 {
+        let bytes = reader.len()?;
+        reader.skip(bytes)?;
+    }
+    reader.skip(8)
+}
+
+fn skip_option_u32(reader: &mut Reader) -> Result<()> {
+    if reader.u8()? != 0 {
+        reader.skip(4)?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+    
+    fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    
+    fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    
+    fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+    
+    /// LEB128, as used by `serde_varint`
+    fn varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+    
+    /// `short_vec` lengths use the same 7-bit encoding as varints
+    fn short_vec_len(&mut self, len: usize) {
+        self.varint(len as u64);
+    }
+    
+    fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+    
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.data.len())
+            .ok_or_else(|| anyhow::anyhow!("truncated gossip packet"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.take(n).map(|_| ())
+    }
+    
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+    
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+    
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+    
+    /// bincode collection length, bounded by what's left in the packet
+    fn len(&mut self) -> Result<usize> {
+        let len = self.u64()?;
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err(anyhow::anyhow!("collection length {} exceeds packet", len));
+        }
+        Ok(len as usize)
+    }
+    
+    fn array32(&mut self) -> Result<[u8; 32]> {
+        Ok(self.take(32)?.try_into()?)
+    }
+    
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow::anyhow!("varint overflow"))
+    }
+    
+    fn short_vec_len(&mut self) -> Result<usize> {
+        Ok(self.varint()? as usize)
+    }
+    
+    fn ip_addr(&mut self) -> Result<IpAddr> {
+        match self.u32()? {
+            0 => Ok(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(self.take(4)?)?))),
+            1 => Ok(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(self.take(16)?)?))),
+            tag => Err(anyhow::anyhow!("invalid ip address tag {}", tag)),
+        }
+    }
+    
+    fn socket_addr(&mut self) -> Result<SocketAddr> {
+        let ip = self.ip_addr()?;
+        Ok(SocketAddr::new(ip, self.u16()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn keypair() -> SigningKey {
+        SigningKey::generate(&mut rand::rngs::OsRng)
+    }
+    
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+    
+    /// Signed CRDS value with an arbitrary `tag` and already-encoded `data`
+    fn raw_value(tag: u32, data: &[u8]) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes(&[7u8; 64]);
+        writer.u32(tag);
+        writer.bytes(data);
+        writer.into_inner()
+    }
+    
+    #[test]
+    fn bloom_positions_follow_std_hash_with_fnv() {
+        use std::hash::{Hash as _, Hasher};
+        
+        let hash = sha256(b"crds value");
+        for key in [0, 1, 0xcbf2_9ce4_8422_2325, u64::MAX] {
+            let mut hasher = fnv::FnvHasher::with_key(key);
+            hash.hash(&mut hasher);
+            assert_eq!(bloom_pos(key, &hash, 6168), hasher.finish() % 6168);
+        }
+    }
+    
+    #[test]
+    fn bloom_filter_sets_the_bits_validators_check() {
+        // FNV-1a over the 8-byte length prefix and the hash, worked out independently
+        let mut filter = CrdsFilter::new(6168, vec![0, 1, 2], 0, 0);
+        filter.add(&sha256(b"crds value"));
+        
+        let set: Vec<u64> = (0..filter.num_bits)
+            .filter(|pos| filter.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+            .collect();
+        assert_eq!(set, vec![1084, 1565, 3274]);
+        assert_eq!(filter.num_bits_set, 3);
+        assert_eq!(bloom_pos(0, &[0; 32], u64::MAX), 15240490438312023072);
+    }
+    
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 300, 16_383, 16_384, u32::MAX as u64, u64::MAX] {
+            let mut writer = Writer::default();
+            writer.varint(value);
+            let bytes = writer.into_inner();
+            let mut reader = Reader::new(&bytes);
+            assert_eq!(reader.varint().unwrap(), value);
+            assert_eq!(reader.pos, bytes.len());
+        }
+    }
+    
+    #[test]
+    fn contact_info_round_trips() {
+        let keypair = keypair();
+        let rpc = addr("10.0.0.1:8899");
+        let rpc_pubsub = addr("10.0.0.1:8900");
+        let gossip = addr("10.0.0.2:8001");
+        let mut sockets = rpc_sockets(rpc, rpc_pubsub).to_vec();
+        sockets.push(gossip_socket(gossip));
+        let value = signed_contact_info(&keypair, 1_234, 42, &sockets);
+        
+        let mut reader = Reader::new(&value);
+        let decoded = decode_crds_value(&mut reader).unwrap();
+        assert_eq!(reader.pos, value.len());
+        assert_eq!(decoded.hash, sha256(&value));
+        assert_eq!(decoded.contact_info, Some(ContactInfo {
+            pubkey: keypair.verifying_key().to_bytes(),
+            wallclock: 1_234,
+            shred_version: 42,
+            version: Some((0, 0, 0)),
+            feature_set: Some(0),
+            gossip: Some(gossip),
+            rpc: Some(rpc),
+            rpc_pubsub: Some(rpc_pubsub),
+        }));
+    }
+    
+    #[test]
+    fn contact_info_keeps_ipv6_addresses() {
+        let rpc = addr("[2001:db8::1]:8899");
+        let value = signed_contact_info(&keypair(), 1, 1, &rpc_sockets(rpc, addr("[2001:db8::1]:8900")));
+        
+        let info = decode_crds_value(&mut Reader::new(&value)).unwrap().contact_info.unwrap();
+        assert_eq!(info.rpc, Some(rpc));
+        assert_eq!(info.rpc_pubsub, Some(addr("[2001:db8::1]:8900")));
+    }
+    
+    #[test]
+    fn legacy_contact_info_is_decoded() {
+        let pubkey = [3u8; 32];
+        let mut data = Writer::default();
+        data.bytes(&pubkey);
+        for index in 0..10u16 {
+            data.u32(0);
+            // unspecified addresses are dropped
+            data.bytes(&if index == 8 { [0, 0, 0, 0] } else { [10, 0, 0, 1] });
+            data.u16(8000 + index);
+        }
+        data.u64(99);
+        data.u16(5);
+        let value = raw_value(CRDS_LEGACY_CONTACT_INFO, &data.into_inner());
+        
+        let info = decode_crds_value(&mut Reader::new(&value)).unwrap().contact_info.unwrap();
+        assert_eq!(info.pubkey, pubkey);
+        assert_eq!(info.wallclock, 99);
+        assert_eq!(info.shred_version, 5);
+        assert_eq!(info.version, None);
+        assert_eq!(info.gossip, Some(addr("10.0.0.1:8000")));
+        assert_eq!(info.rpc, Some(addr("10.0.0.1:8007")));
+        assert_eq!(info.rpc_pubsub, None);
+    }
+    
+    #[test]
+    fn other_crds_values_are_skipped() {
+        // NodeInstance { from, wallclock, timestamp, token }
+        let node_instance = raw_value(8, &[1u8; 32 + 8 + 8 + 8]);
+        // Version { from, wallclock, major, minor, patch, commit: Some, feature_set }
+        let mut version = Writer::default();
+        version.bytes(&[2u8; 32 + 8 + 6]);
+        version.u8(1);
+        version.u32(0xdead);
+        version.u32(7);
+        let version = raw_value(7, &version.into_inner());
+        // SnapshotHashes { from, full, incremental: [(slot, hash)], wallclock }
+        let mut snapshot_hashes = Writer::default();
+        snapshot_hashes.bytes(&[4u8; 32 + 40]);
+        snapshot_hashes.u64(2);
+        snapshot_hashes.bytes(&[5u8; 2 * 40 + 8]);
+        let snapshot_hashes = raw_value(10, &snapshot_hashes.into_inner());
+        let contact_info = signed_contact_info(&keypair(), 1, 1, &[gossip_socket(addr("10.0.0.1:8001"))]);
+        
+        let packet = encode_pull_response(&[9u8; 32], &[node_instance, version, snapshot_hashes, contact_info]);
+        let Protocol::Values { from, values } = decode_packet(&packet).unwrap() else {
+            panic!("expected values");
+        };
+        assert_eq!(from, [9u8; 32]);
+        assert_eq!(values.len(), 4);
+        assert!(values[..3].iter().all(|value| value.contact_info.is_none()));
+        assert_eq!(values[3].contact_info.as_ref().unwrap().gossip, Some(addr("10.0.0.1:8001")));
+    }
+    
+    #[test]
+    fn values_after_an_unknown_one_are_dropped() {
+        let contact_info = signed_contact_info(&keypair(), 1, 1, &[gossip_socket(addr("10.0.0.1:8001"))]);
+        let packet = encode_pull_response(&[0u8; 32], &[contact_info.clone(), raw_value(99, &[0; 16]), contact_info]);
+        
+        let Protocol::Values { values, .. } = decode_packet(&packet).unwrap() else {
+            panic!("expected values");
+        };
+        assert_eq!(values.len(), 1);
+    }
+    
+    #[test]
+    fn pull_request_round_trips() {
+        let caller = signed_contact_info(&keypair(), 5, 42, &[gossip_socket(addr("127.0.0.1:9000"))]);
+        let seen = sha256(b"seen value");
+        let mut filter = CrdsFilter::new(6144, vec![1, 2, 3], 0, 0);
+        filter.add(&seen);
+        
+        let packet = encode_pull_request(&filter, &caller);
+        let Protocol::PullRequest { filter: decoded, caller } = decode_packet(&packet).unwrap() else {
+            panic!("expected pull request");
+        };
+        assert!(decoded.contains(&seen));
+        assert!(!decoded.contains(&sha256(b"other value")));
+        assert_eq!(decoded.num_bits_set, filter.num_bits_set);
+        assert_eq!(caller.contact_info.unwrap().shred_version, 42);
+    }
+    
+    #[test]
+    fn filter_masks_partition_the_hash_space() {
+        let mask_bits = 4;
+        let filters: Vec<CrdsFilter> = (0..1u64 << mask_bits)
+            .map(|index| CrdsFilter::new(64, vec![0], mask_bits, index))
+            .collect();
+        
+        for i in 0..100u32 {
+            let hash = sha256(&i.to_le_bytes());
+            let covering: Vec<usize> = filters
+                .iter()
+                .enumerate()
+                .filter(|(_, filter)| filter.test_mask(&hash))
+                .map(|(index, _)| index)
+                .collect();
+            assert_eq!(covering, vec![(hash_as_u64(&hash) >> (64 - mask_bits)) as usize]);
+        }
+        
+        // without mask bits one filter covers everything
+        assert!(CrdsFilter::new(64, vec![0], 0, 0).test_mask(&sha256(b"anything")));
+    }
+    
+    #[test]
+    fn ping_and_pong_round_trip() {
+        let keypair = keypair();
+        let token = [8u8; 32];
+        
+        let Protocol::Ping { token: decoded } = decode_packet(&encode_ping(&keypair, &token)).unwrap() else {
+            panic!("expected ping");
+        };
+        assert_eq!(decoded, token);
+        
+        let Protocol::Pong { hash } = decode_packet(&encode_pong(&keypair, &token)).unwrap() else {
+            panic!("expected pong");
+        };
+        assert_eq!(hash, pong_hash(&token));
+    }
+    
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let packet = encode_ping(&keypair(), &[1u8; 32]);
+        assert!(decode_packet(&packet[..40]).is_err());
+        assert!(decode_packet(&[]).is_err());
+        
+        // a collection length beyond the packet is an error, not an allocation
+        let mut writer = Writer::default();
+        writer.u32(PROTOCOL_PULL_RESPONSE);
+        writer.bytes(&[0u8; 32]);
+        writer.u64(u64::MAX);
+        assert!(Reader::new(&writer.buf[36..]).len().is_err());
+    }
+    
+    #[test]
+    fn unknown_packets_are_other() {
+        let mut writer = Writer::default();
+        writer.u32(3); // prune message
+        assert!(matches!(decode_packet(&writer.into_inner()).unwrap(), Protocol::Other));
+    }
+}
@@ -0,0 +1,216 @@
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::types::{RpcError, RpcRequest};
+
+/// Programs owning millions of accounts, scanning them without filters can take a node down
+pub const DEFAULT_GUARDED_PROGRAMS: &str =
+    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA,TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+
+/// Limits on what clients may ask nodes to do
+#[derive(Debug, Clone)]
+pub struct MethodGuards {
+    /// Only these methods are forwarded when not empty
+    allowed_methods: HashSet<String>,
+    denied_methods: HashSet<String>,
+    /// Programs that `getProgramAccounts` and `programSubscribe` may only be called on with filters
+    guarded_programs: HashSet<String>,
+    /// Limits below are disabled when 0
+    max_multiple_accounts: usize,
+    max_signatures_limit: u64,
+    max_block_range: u64,
+}
+
+/// Why a request was refused before being forwarded
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// The method is not on the allow list or is on the deny list
+    MethodNotAllowed,
+    /// The method is allowed but the params ask for too much
+    TooExpensive(String),
+}
+
+impl Rejection {
+    pub fn rpc_error(&self, method: &str) -> RpcError {
+        match self {
+            Rejection::MethodNotAllowed => RpcError {
+                code: -32601,
+                message: format!("Method not allowed: {}", method),
+                data: None,
+            },
+            Rejection::TooExpensive(reason) => RpcError {
+                code: -32602,
+                message: format!("Invalid params: {}", reason),
+                data: Some(serde_json::json!({ "method": method })),
+            },
+        }
+    }
+}
+
+impl MethodGuards {
+    pub fn new(
+        allowed_methods: Vec<String>,
+        denied_methods: Vec<String>,
+        guarded_programs: Vec<String>,
+        max_multiple_accounts: usize,
+        max_signatures_limit: u64,
+        max_block_range: u64,
+    ) -> Self {
+        Self {
+            allowed_methods: allowed_methods.into_iter().collect(),
+            denied_methods: denied_methods.into_iter().collect(),
+            guarded_programs: guarded_programs.into_iter().collect(),
+            max_multiple_accounts,
+            max_signatures_limit,
+            max_block_range,
+        }
+    }
+    
+    pub fn check(&self, request: &RpcRequest) -> Result<(), Rejection> {
+        let method = request.method.as_str();
+        if (!self.allowed_methods.is_empty() && !self.allowed_methods.contains(method))
+            || self.denied_methods.contains(method)
+        {
+            return Err(Rejection::MethodNotAllowed);
+        }
+        
+        let params = request.params.as_ref().and_then(|params| params.as_array()).map(Vec::as_slice).unwrap_or_default();
+        
+        match method {
+            "getProgramAccounts" | "programSubscribe" => {
+                let program = params.first().and_then(|program| program.as_str()).unwrap_or_default();
+                let has_filters = params
+                    .get(1)
+                    .and_then(|config| config.get("filters"))
+                    .and_then(|filters| filters.as_array())
+                    .is_some_and(|filters| !filters.is_empty());
+                if self.guarded_programs.contains(program) && !has_filters {
+                    return Err(Rejection::TooExpensive(format!(
+                        "{} on {} requires memcmp or dataSize filters", method, program
+                    )));
+                }
+            }
+            "getMultipleAccounts" => {
+                let accounts = params.first().and_then(|accounts| accounts.as_array()).map_or(0, Vec::len);
+                if self.max_multiple_accounts > 0 && accounts > self.max_multiple_accounts {
+                    return Err(Rejection::TooExpensive(format!(
+                        "getMultipleAccounts requested {} accounts, at most {} are allowed", accounts, self.max_multiple_accounts
+                    )));
+                }
+            }
+            "getSignaturesForAddress" => {
+                // nodes return up to 1000 signatures when no limit is given
+                let limit = params.get(1).and_then(|config| config.get("limit")).and_then(Value::as_u64).unwrap_or(1000);
+                if self.max_signatures_limit > 0 && limit > self.max_signatures_limit {
+                    return Err(Rejection::TooExpensive(format!(
+                        "getSignaturesForAddress limit {} is above the maximum of {}, pass a smaller limit",
+                        limit, self.max_signatures_limit
+                    )));
+                }
+            }
+            "getBlocks" => {
+                let start = params.first().and_then(Value::as_u64);
+                let end = params.get(1).and_then(Value::as_u64);
+                if let (Some(start), Some(end), true) = (start, end, self.max_block_range > 0) {
+                    if end.saturating_sub(start) > self.max_block_range {
+                        return Err(Rejection::TooExpensive(format!(
+                            "getBlocks range of {} slots is above the maximum of {}", end - start, self.max_block_range
+                        )));
+                    }
+                }
+            }
+            "getBlocksWithLimit" => {
+                let limit = params.get(1).and_then(Value::as_u64).unwrap_or_default();
+                if self.max_block_range > 0 && limit > self.max_block_range {
+                    return Err(Rejection::TooExpensive(format!(
+                        "getBlocksWithLimit limit {} is above the maximum of {}", limit, self.max_block_range
+                    )));
+                }
+            }
+            _ => {}
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    const TOKEN_PROGRAM: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+    
+    fn request(method: &str, params: Value) -> RpcRequest {
+        RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(1),
+            method: method.to_string(),
+            params: Some(params),
+        }
+    }
+    
+    fn guards() -> MethodGuards {
+        MethodGuards::new(vec![], vec!["requestAirdrop".to_string()], vec![TOKEN_PROGRAM.to_string()], 100, 1000, 500)
+    }
+    
+    fn is_too_expensive(result: Result<(), Rejection>) -> bool {
+        matches!(result, Err(Rejection::TooExpensive(_)))
+    }
+    
+    #[test]
+    fn allow_and_deny_lists() {
+        assert_eq!(guards().check(&request("requestAirdrop", json!([]))), Err(Rejection::MethodNotAllowed));
+        assert_eq!(guards().check(&request("getSlot", json!([]))), Ok(()));
+        
+        let allow_only = MethodGuards::new(vec!["getSlot".to_string()], vec![], vec![], 0, 0, 0);
+        assert_eq!(allow_only.check(&request("getSlot", json!([]))), Ok(()));
+        assert_eq!(allow_only.check(&request("getBalance", json!([]))), Err(Rejection::MethodNotAllowed));
+    }
+    
+    #[test]
+    fn guarded_programs_require_filters() {
+        for method in ["getProgramAccounts", "programSubscribe"] {
+            assert!(is_too_expensive(guards().check(&request(method, json!([TOKEN_PROGRAM])))));
+            let empty = request(method, json!([TOKEN_PROGRAM, {"filters": []}]));
+            assert!(is_too_expensive(guards().check(&empty)));
+            let filtered = request(method, json!([TOKEN_PROGRAM, {"filters": [{"dataSize": 165}]}]));
+            assert_eq!(guards().check(&filtered), Ok(()));
+            assert_eq!(guards().check(&request(method, json!(["Other1111"]))), Ok(()));
+        }
+    }
+    
+    #[test]
+    fn multiple_accounts_limit() {
+        let accounts = |count: usize| request("getMultipleAccounts", json!([vec!["a"; count]]));
+        assert_eq!(guards().check(&accounts(100)), Ok(()));
+        assert!(is_too_expensive(guards().check(&accounts(101))));
+    }
+    
+    #[test]
+    fn signatures_limit_defaults_to_what_nodes_return() {
+        let signatures = |config: Value| request("getSignaturesForAddress", json!(["a", config]));
+        assert_eq!(guards().check(&signatures(json!({}))), Ok(()));
+        assert!(is_too_expensive(guards().check(&signatures(json!({"limit": 1001})))));
+        
+        let strict = MethodGuards::new(vec![], vec![], vec![], 0, 100, 0);
+        assert!(is_too_expensive(strict.check(&signatures(json!({})))));
+        assert_eq!(strict.check(&signatures(json!({"limit": 100}))), Ok(()));
+    }
+    
+    #[test]
+    fn block_range_limits() {
+        assert_eq!(guards().check(&request("getBlocks", json!([1000, 1500]))), Ok(()));
+        assert!(is_too_expensive(guards().check(&request("getBlocks", json!([1000, 1501])))));
+        assert!(is_too_expensive(guards().check(&request("getBlocksWithLimit", json!([1000, 501])))));
+        assert_eq!(guards().check(&request("getBlocksWithLimit", json!([1000, 500]))), Ok(()));
+    }
+    
+    #[test]
+    fn zero_disables_the_limits() {
+        let unlimited = MethodGuards::new(vec![], vec![], vec![], 0, 0, 0);
+        assert_eq!(unlimited.check(&request("getMultipleAccounts", json!([vec!["a"; 1000]]))), Ok(()));
+        assert_eq!(unlimited.check(&request("getSignaturesForAddress", json!(["a", {"limit": 5000}]))), Ok(()));
+        assert_eq!(unlimited.check(&request("getBlocks", json!([0, 1_000_000]))), Ok(()));
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Latency-sensitive reads worth a second upstream call when the first node is slow
+pub const DEFAULT_HEDGE_METHODS: &str =
+    "getAccountInfo,getLatestBlockhash,getBalance,getMultipleAccounts,getSlot,getBlockHeight";
+
+/// Recent successful latencies kept per method
+const LATENCY_WINDOW: usize = 256;
+/// Latencies needed before a method's percentile is trusted enough to hedge on
+const MIN_LATENCY_SAMPLES: usize = 20;
+/// Hedges that may be saved up while traffic is fast, so a burst of slow nodes can still be hedged
+const MAX_SAVED_HEDGES: f64 = 10.0;
+
+/// Which requests are hedged and how aggressively
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    enabled: bool,
+    methods: HashSet<String>,
+    /// Latency percentile of a method after which the second node is asked, e.g. 95.0
+    percentile: f64,
+    /// Hedges allowed per hedgeable request, below 1 so upstream load never doubles
+    budget: f64,
+}
+
+impl HedgePolicy {
+    pub fn new(enabled: bool, methods: Vec<String>, percentile: u8, budget_percent: u8) -> Self {
+        Self {
+            enabled,
+            methods: methods.into_iter().collect(),
+            percentile: f64::from(percentile.clamp(1, 99)),
+            budget: f64::from(budget_percent.min(50)) / 100.0,
+        }
+    }
+    
+    pub fn is_hedged(&self, method: &str) -> bool {
+        self.enabled && self.methods.contains(method)
+    }
+    
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    
+    pub fn percentile(&self) -> f64 {
+        self.percentile
+    }
+    
+    pub fn budget(&self) -> f64 {
+        self.budget
+    }
+}
+
+/// Latency history and hedge budget shared by all requests
+#[derive(Default)]
+pub struct Hedger {
+    latencies: Mutex<HashMap<String, VecDeque<Duration>>>,
+    /// Hedges currently allowed, earned a fraction at a time by hedgeable requests
+    budget: Mutex<f64>,
+}
+
+impl Hedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Record how long a node took to answer `method` successfully
+    pub fn observe(&self, method: &str, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let window = latencies.entry(method.to_string()).or_default();
+        if window.len() == LATENCY_WINDOW {
+            window.pop_front();
+        }
+        window.push_back(latency);
+    }
+    
+    /// How long to wait for the first node before asking a second one, `None`
+    /// until enough of `method`'s latencies have been seen
+    pub fn delay(&self, method: &str, policy: &HedgePolicy) -> Option<Duration> {
+        let latencies = self.latencies.lock().unwrap();
+        let window = latencies.get(method)?;
+        if window.len() < MIN_LATENCY_SAMPLES {
+            return None;
+        }
+        
+        let mut sorted: Vec<Duration> = window.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = ((policy.percentile / 100.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+    
+    /// Credit the budget for one hedgeable request
+    pub fn earn(&self, policy: &HedgePolicy) {
+        let mut budget = self.budget.lock().unwrap();
+        *budget = (*budget + policy.budget).min(MAX_SAVED_HEDGES);
+    }
+    
+    /// Take one hedge from the budget, false when it is spent
+    pub fn try_spend(&self) -> bool {
+        let mut budget = self.budget.lock().unwrap();
+        if *budget < 1.0 {
+            return false;
+        }
+        *budget -= 1.0;
+        true
+    }
+    
+    /// Current hedge delay of every method with enough latency history
+    pub fn delays(&self, policy: &HedgePolicy) -> Vec<(String, Duration)> {
+        let methods: Vec<String> = self.latencies.lock().unwrap().keys().cloned().collect();
+        let mut delays: Vec<(String, Duration)> = methods
+            .into_iter()
+            .filter_map(|method| self.delay(&method, policy).map(|delay| (method, delay)))
+            .collect();
+        delays.sort();
+        delays
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn policy(percentile: u8, budget_percent: u8) -> HedgePolicy {
+        HedgePolicy::new(true, vec!["getSlot".to_string()], percentile, budget_percent)
+    }
+    
+    /// Hedger that has seen `getSlot` answered in 1..=count milliseconds
+    fn hedger_with(count: u64) -> Hedger {
+        let hedger = Hedger::new();
+        for ms in 1..=count {
+            hedger.observe("getSlot", Duration::from_millis(ms));
+        }
+        hedger
+    }
+    
+    #[test]
+    fn no_delay_until_enough_samples() {
+        let hedger = hedger_with(MIN_LATENCY_SAMPLES as u64 - 1);
+        assert_eq!(hedger.delay("getSlot", &policy(95, 10)), None);
+        assert_eq!(hedger.delay("getBalance", &policy(95, 10)), None);
+        
+        hedger.observe("getSlot", Duration::from_millis(20));
+        assert!(hedger.delay("getSlot", &policy(95, 10)).is_some());
+    }
+    
+    #[test]
+    fn delay_is_the_latency_percentile() {
+        let hedger = hedger_with(100);
+        assert_eq!(hedger.delay("getSlot", &policy(95, 10)), Some(Duration::from_millis(95)));
+        assert_eq!(hedger.delay("getSlot", &policy(50, 10)), Some(Duration::from_millis(50)));
+        // percentiles are clamped to 1..=99
+        assert_eq!(hedger.delay("getSlot", &policy(0, 10)), Some(Duration::from_millis(1)));
+        assert_eq!(hedger.delay("getSlot", &policy(100, 10)), Some(Duration::from_millis(99)));
+    }
+    
+    #[test]
+    fn delay_follows_the_latest_window() {
+        let hedger = hedger_with(LATENCY_WINDOW as u64);
+        for _ in 0..LATENCY_WINDOW {
+            hedger.observe("getSlot", Duration::from_secs(1));
+        }
+        assert_eq!(hedger.delay("getSlot", &policy(50, 10)), Some(Duration::from_secs(1)));
+    }
+    
+    #[test]
+    fn hedges_are_earned_and_capped() {
+        let hedger = Hedger::new();
+        let policy = policy(95, 25);
+        assert!(!hedger.try_spend());
+        
+        for _ in 0..4 {
+            hedger.earn(&policy);
+        }
+        assert!(hedger.try_spend());
+        assert!(!hedger.try_spend());
+        
+        for _ in 0..1000 {
+            hedger.earn(&policy);
+        }
+        let spent = (0..100).filter(|_| hedger.try_spend()).count();
+        assert_eq!(spent, MAX_SAVED_HEDGES as usize);
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::rpc_client::UpstreamClient;
+use crate::types::{ProbeResult, RpcNode};
+
+/// Name of the identity check among a node's probe results
+const IDENTITY_PROBE: &str = "clusterIdentity";
+
+/// Genesis hash and shred version every node has to match before it receives traffic
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExpectedCluster {
+    /// Learned from the cluster URL when not configured
+    pub genesis_hash: Option<String>,
+    /// Only checked for nodes whose shred version is known from gossip
+    pub shred_version: Option<u16>,
+}
+
+/// Cluster identity nodes are verified against, shared by the health check tasks
+#[derive(Default)]
+pub struct ClusterIdentity {
+    /// Genesis hash the cluster URL reported, with that URL
+    learned: Mutex<Option<(String, String)>>,
+    /// Shred version gossip adopted, with the entrypoints it came from
+    adopted_shred_version: Mutex<Option<(Vec<String>, u16)>>,
+}
+
+impl ClusterIdentity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Genesis hash nodes must report: the configured one, otherwise the one
+    /// `cluster_url` reports. Learned once per cluster URL, a genesis hash never changes.
+    pub async fn genesis_hash(
+        &self,
+        upstream: &UpstreamClient,
+        expected: &ExpectedCluster,
+        cluster_url: &str,
+        timeout_secs: u64,
+    ) -> Option<String> {
+        if let Some(genesis_hash) = &expected.genesis_hash {
+            return Some(genesis_hash.clone());
+        }
+        if let Some((url, genesis_hash)) = self.learned.lock().unwrap().as_ref() {
+            if url == cluster_url {
+                return Some(genesis_hash.clone());
+            }
+        }
+        
+        match upstream.get_genesis_hash(cluster_url, timeout_secs).await {
+            Some(genesis_hash) => {
+                info!("🪪 Cluster {} has genesis hash {}, nodes must match it", cluster_url, genesis_hash);
+                *self.learned.lock().unwrap() = Some((cluster_url.to_string(), genesis_hash.clone()));
+                Some(genesis_hash)
+            }
+            None => {
+                warn!("⚠️  Could not get the genesis hash of {}, no node can be verified until it answers or expected_genesis_hash is set",
+                      cluster_url);
+                None
+            }
+        }
+    }
+    
+    /// Record the shred version discovery adopted from the gossip `entrypoints`
+    pub fn adopt_shred_version(&self, entrypoints: &[String], shred_version: u16) {
+        let mut adopted = self.adopted_shred_version.lock().unwrap();
+        if adopted.as_ref().is_some_and(|(from, known)| from == entrypoints && *known == shred_version) {
+            return;
+        }
+        info!("🪪 Gossip entrypoints are on shred version {}, nodes must match it", shred_version);
+        *adopted = Some((entrypoints.to_vec(), shred_version));
+    }
+    
+    /// Shred version nodes must advertise: the configured one, otherwise the one
+    /// adopted from the gossip `entrypoints` in use
+    pub fn shred_version(&self, expected: &ExpectedCluster, entrypoints: &[String]) -> Option<u16> {
+        expected.shred_version.or_else(|| {
+            self.adopted_shred_version
+                .lock()
+                .unwrap()
+                .as_ref()
+                .filter(|(from, _)| from == entrypoints)
+                .map(|(_, shred_version)| *shred_version)
+        })
+    }
+}
+
+/// Whether `node` is known to belong to another cluster, as opposed to not
+/// having reported which one it belongs to
+pub fn is_foreign(
+    node: &RpcNode,
+    genesis_hash: Option<&str>,
+    expected_genesis_hash: Option<&str>,
+    expected_shred_version: Option<u16>,
+) -> bool {
+    mismatch(node, genesis_hash, expected_genesis_hash, expected_shred_version).is_some()
+}
+
+fn mismatch(
+    node: &RpcNode,
+    genesis_hash: Option<&str>,
+    expected_genesis_hash: Option<&str>,
+    expected_shred_version: Option<u16>,
+) -> Option<String> {
+    match (genesis_hash, expected_genesis_hash) {
+        (Some(actual), Some(expected)) if actual != expected => {
+            Some(format!("genesis hash {}, expected {}", actual, expected))
+        }
+        _ => match (node.shred_version, expected_shred_version) {
+            (Some(actual), Some(expected)) if actual != expected => {
+                Some(format!("shred version {}, expected {}", actual, expected))
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Check `node`, which reported `genesis_hash`, against the expected cluster.
+/// A node can't be verified while the expected genesis hash is unknown.
+pub fn verify(
+    node: &RpcNode,
+    genesis_hash: Option<&str>,
+    expected_genesis_hash: Option<&str>,
+    expected_shred_version: Option<u16>,
+) -> ProbeResult {
+    let error = mismatch(node, genesis_hash, expected_genesis_hash, expected_shred_version)
+        .or_else(|| match (genesis_hash, expected_genesis_hash) {
+            (_, None) => Some("cluster genesis hash unknown".to_string()),
+            (None, Some(_)) => Some("no genesis hash reported".to_string()),
+            _ => None,
+        });
+    
+    ProbeResult {
+        name: IDENTITY_PROBE.to_string(),
+        passed: error.is_none(),
+        required: true,
+        weight: 0.0,
+        latency_ms: 0,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn node(shred_version: Option<u16>) -> RpcNode {
+        RpcNode::new("http://a".to_string()).with_shred_version(shred_version)
+    }
+    
+    #[test]
+    fn mismatches_are_foreign_but_silence_is_not() {
+        let node = node(Some(1));
+        assert!(!is_foreign(&node, Some("GH"), Some("GH"), Some(1)));
+        assert!(is_foreign(&node, Some("other"), Some("GH"), Some(1)));
+        assert!(is_foreign(&node, Some("GH"), Some("GH"), Some(2)));
+        assert!(!is_foreign(&node, None, Some("GH"), None));
+        assert!(!is_foreign(&node, Some("GH"), None, None));
+    }
+    
+    #[test]
+    fn unverifiable_nodes_fail_the_check() {
+        assert!(verify(&node(None), Some("GH"), Some("GH"), None).passed);
+        assert!(!verify(&node(None), None, Some("GH"), None).passed);
+        assert!(!verify(&node(None), Some("GH"), None, None).passed);
+        assert!(!verify(&node(Some(2)), Some("GH"), Some("GH"), Some(1)).passed);
+    }
+    
+    #[test]
+    fn adopted_shred_version_applies_to_its_entrypoints_unless_one_is_configured() {
+        let identity = ClusterIdentity::new();
+        let entrypoints = vec!["entrypoint:8001".to_string()];
+        let unconfigured = ExpectedCluster::default();
+        assert_eq!(identity.shred_version(&unconfigured, &entrypoints), None);
+        
+        identity.adopt_shred_version(&entrypoints, 7);
+        assert_eq!(identity.shred_version(&unconfigured, &entrypoints), Some(7));
+        assert_eq!(identity.shred_version(&unconfigured, &["other:8001".to_string()]), None);
+        
+        let configured = ExpectedCluster { shred_version: Some(9), ..ExpectedCluster::default() };
+        assert_eq!(identity.shred_version(&configured, &entrypoints), Some(9));
+    }
+}
@@ -1,8 +1,27 @@
+pub mod affinity;
+pub mod auth;
+pub mod cache;
+pub mod capability;
+pub mod coalesce;
+pub mod config;
+pub mod fault;
 pub mod gossip;
+#[cfg(any(test, feature = "gossip-stand-in"))]
+pub mod gossip_stand_in;
+pub mod gossip_wire;
+pub mod guard;
+pub mod hedge;
+pub mod identity;
+pub mod metrics;
 pub mod rpc_client;
 pub mod proxy;
+pub mod pubsub;
+pub mod rate_limit;
+pub mod retry;
 pub mod node_cache;
+pub mod probe;
 pub mod types;
+pub mod version;
 
 pub use gossip::GossipClient;
 pub use node_cache::NodeCache;
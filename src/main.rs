@@ -1,43 +1,170 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::Arc;
+use tokio::sync::watch;
 use tokio::time::{sleep, Duration};
-use tracing::{info, warn, error};
+use tracing::{debug, info, warn, error};
 
+mod affinity;
+mod auth;
+mod cache;
+mod capability;
+mod coalesce;
+mod config;
+mod fault;
 mod gossip;
+mod guard;
+mod hedge;
+mod identity;
+mod version;
+// the binary doesn't use the stand-in's half of the wire format
+#[cfg_attr(feature = "gossip-stand-in", allow(dead_code))]
+mod gossip_wire;
+#[cfg(test)]
+mod gossip_stand_in;
+mod metrics;
 mod rpc_client;
 mod proxy;
+mod pubsub;
+mod rate_limit;
+mod retry;
 mod node_cache;
+mod probe;
 mod types;
 
+use config::{ConfigFile, ReloadTrigger};
 use gossip::GossipClient;
-use node_cache::NodeCache;
-use proxy::ProxyServer;
+use node_cache::{CheckOutcome, CheckSchedule, LoadBalancing, NodeCache, QuarantinePolicy};
+use proxy::{ProxyServer, ProxySettings};
+use rpc_client::{UpstreamClient, UpstreamClientConfig};
 
-#[derive(Parser)]
+/// How often the health check scheduler looks for nodes due for a check
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Clone)]
 #[command(name = "x1-rpc-proxy")]
 #[command(about = "X1 Blockchain RPC Proxy Server")]
 struct Args {
+    /// TOML or YAML config file (`.yaml`/`.yml`), reloaded on SIGHUP or when it changes.
+    /// Flags given on the command line take precedence over the file.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    
     /// Proxy server listening port
     #[arg(short, long, default_value = "8080")]
     port: u16,
     
+    /// Additional port serving PubSub websockets (websocket upgrades are always accepted on --port)
+    #[arg(long)]
+    pubsub_port: Option<u16>,
+    
     /// X1 cluster RPC URL
     #[arg(long, default_value = "https://rpc.testnet.x1.xyz")]
     cluster_url: String,
     
-    /// Node health check interval (seconds)
-    #[arg(long, default_value = "30")]
+    /// Genesis hash every node must report before it receives traffic (defaults to the cluster URL's)
+    #[arg(long)]
+    expected_genesis_hash: Option<String>,
+    
+    /// Shred version every node discovered through gossip must advertise (defaults to the gossip entrypoint's)
+    #[arg(long)]
+    expected_shred_version: Option<u16>,
+    
+    /// Oldest node software version that receives traffic, e.g. 2.0.15
+    #[arg(long)]
+    min_node_version: Option<version::Version>,
+    
+    /// Newest node software version that receives traffic
+    #[arg(long)]
+    max_node_version: Option<version::Version>,
+    
+    /// Node versions a method needs as method:min_version[:max_version] (repeatable),
+    /// e.g. getBlock:1.18.0 or simulateTransaction::1.18.26
+    #[arg(long = "method-version")]
+    method_versions: Vec<version::MethodVersion>,
+    
+    /// Gossip entrypoint host:port to discover nodes from (repeatable, defaults to the cluster URL host on port 8001)
+    #[arg(long = "gossip-entrypoint")]
+    gossip_entrypoints: Vec<String>,
+    
+    /// How long a gossip discovery pull may run (seconds)
+    #[arg(long, default_value = "10")]
+    gossip_timeout: u64,
+    
+    /// RPC node URL health-checked alongside discovered nodes (repeatable)
+    #[arg(long = "static-node")]
+    static_nodes: Vec<String>,
+    
+    /// How often nodes are rediscovered through gossip and the cluster URL (seconds)
+    #[arg(long, default_value = "60")]
+    discovery_interval: u64,
+    
+    /// Drop nodes that discovery stopped listing after this many rounds in a row (0 keeps them)
+    #[arg(long, default_value = "3")]
+    evict_after_missed_discoveries: u32,
+    
+    /// Health check interval of healthy nodes (seconds)
+    #[arg(long, default_value_t = node_cache::DEFAULT_HEALTH_CHECK_INTERVAL_SECS)]
     health_check_interval: u64,
     
+    /// Health check interval of suspect nodes, which failed forwarded requests recently (seconds)
+    #[arg(long, default_value_t = node_cache::DEFAULT_SUSPECT_CHECK_INTERVAL_SECS)]
+    suspect_check_interval: u64,
+    
     /// Node health check timeout (seconds)
     #[arg(long, default_value = "2")]
     node_health_timeout: u64,
     
+    /// How often a node's history and index capabilities are probed again (seconds).
+    /// They are also probed when a node is discovered and when its version changes.
+    #[arg(long, default_value = "3600")]
+    capability_check_interval: u64,
+    
+    /// Built-in health check probes run on every node (comma separated)
+    #[arg(long, value_delimiter = ',', default_value = probe::DEFAULT_BUILTIN_PROBES)]
+    builtin_probes: Vec<probe::BuiltinProbe>,
+    
+    /// Extra required health check probe as method or method:<params JSON> (repeatable).
+    /// Probes with assertions on the response go in the config file.
+    #[arg(long = "probe")]
+    probes: Vec<probe::Probe>,
+    
     /// RPC request timeout (seconds)
     #[arg(long, default_value = "60")]
     rpc_request_timeout: u64,
     
+    /// Responses larger than this are streamed to the client instead of buffered (KiB).
+    /// Streamed responses are not cached or shared between coalesced requests.
+    #[arg(long, default_value = "1024")]
+    stream_threshold_kb: usize,
+    
+    /// Largest upstream response passed on to a client (MiB, 0 disables the limit)
+    #[arg(long, default_value = "1024")]
+    max_response_mb: usize,
+    
+    /// Time allowed for receiving an upstream response body once the node started answering (seconds)
+    #[arg(long, default_value = "300")]
+    max_transfer_time: u64,
+    
+    /// Idle keep-alive connections kept open per upstream node
+    #[arg(long, default_value = "32")]
+    upstream_pool_max_idle: usize,
+    
+    /// How long an idle upstream connection is kept open (seconds)
+    #[arg(long, default_value = "90")]
+    upstream_pool_idle_timeout: u64,
+    
+    /// Upstream connection establishment timeout, separate from request timeouts (seconds)
+    #[arg(long, default_value = "5")]
+    upstream_connect_timeout: u64,
+    
+    /// Only speak HTTP/1.1 to upstream nodes instead of negotiating HTTP/2 over TLS
+    #[arg(long)]
+    disable_upstream_http2: bool,
+    
     /// Maximum concurrent node tests (auto-adjusted based on CPU cores if not specified)
     #[arg(long)]
     max_concurrent_tests: Option<usize>,
@@ -50,35 +177,364 @@ struct Args {
     #[arg(long, default_value = "30")]
     max_queue_wait_time: u64,
     
+    /// Maximum number of requests from one JSON-RPC batch forwarded to a single node
+    #[arg(long, default_value = "20")]
+    batch_chunk_size: usize,
+    
+    /// Maximum attempts per request (on different nodes) for connection errors, timeouts and 5xx
+    #[arg(long, default_value = "3")]
+    max_retry_attempts: u32,
+    
+    /// Total time budget across retries (seconds, defaults to --rpc-request-timeout)
+    #[arg(long)]
+    retry_budget: Option<u64>,
+    
+    /// Per-method retry policy as method:max_attempts:budget_secs (repeatable)
+    #[arg(long = "method-retry-policy")]
+    method_retry_policies: Vec<retry::MethodRetryPolicy>,
+    
+    /// Methods that are only retried when the client sends `x-allow-retry: true`
+    #[arg(long, value_delimiter = ',', default_value = "sendTransaction")]
+    never_retry_methods: Vec<String>,
+    
+    /// Response cache size for finalized blocks/transactions and short-lived results (MiB, 0 disables caching)
+    #[arg(long, default_value = "128")]
+    response_cache_mb: usize,
+    
+    /// How long getSlot, getLatestBlockhash, getEpochInfo and getBlockHeight results are cached (milliseconds, 0 disables)
+    #[arg(long, default_value = "400")]
+    short_cache_ttl_ms: u64,
+    
+    /// Forward identical (method, params) requests that are in flight at the same time separately
+    #[arg(long)]
+    disable_request_coalescing: bool,
+    
+    /// Requests per second allowed per client IP (0 disables per-IP rate limiting)
+    #[arg(long, default_value = "0")]
+    rate_limit_rps: f64,
+    
+    /// Requests a client IP may burst above its rate (defaults to two seconds worth of requests)
+    #[arg(long)]
+    rate_limit_burst: Option<u32>,
+    
+    /// Rate limit cost of a method as method:cost, on top of the defaults for heavy methods (repeatable)
+    #[arg(long = "method-cost")]
+    method_costs: Vec<rate_limit::MethodCost>,
+    
+    /// Proxy IP or CIDR whose X-Forwarded-For header identifies the client (repeatable)
+    #[arg(long = "trusted-proxy")]
+    trusted_proxies: Vec<rate_limit::TrustedProxy>,
+    
+    /// API key accepted as a path segment (`/<key>`), `x-api-key` header or `api-key` query parameter,
+    /// as name:key (repeatable). Keys with method, origin and rate limit policies go in the config file.
+    #[arg(long = "api-key")]
+    api_keys: Vec<auth::ApiKey>,
+    
+    /// Reject requests that don't carry a valid API key
+    #[arg(long)]
+    require_api_key: bool,
+    
+    /// Bearer token for the /admin API used to manage API keys at runtime (the API is disabled when not set).
+    /// When set, /stats, /performance and /metrics need it too; per-key usage metrics are only shown with it.
+    #[arg(long)]
+    admin_token: Option<String>,
+    
+    /// Only forward these methods (comma separated, all methods when empty)
+    #[arg(long, value_delimiter = ',')]
+    allowed_methods: Vec<String>,
+    
+    /// Never forward these methods (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    denied_methods: Vec<String>,
+    
+    /// Programs getProgramAccounts and programSubscribe may only be called on with filters (comma separated)
+    #[arg(long, value_delimiter = ',', default_value = guard::DEFAULT_GUARDED_PROGRAMS)]
+    guarded_programs: Vec<String>,
+    
+    /// Maximum accounts in one getMultipleAccounts call (0 disables the check)
+    #[arg(long, default_value = "100")]
+    max_multiple_accounts: usize,
+    
+    /// Maximum getSignaturesForAddress limit, requests without a limit count as 1000 (0 disables the check)
+    #[arg(long, default_value = "1000")]
+    max_signatures_limit: u64,
+    
+    /// Maximum slot range of getBlocks and limit of getBlocksWithLimit (0 disables the check)
+    #[arg(long, default_value = "10000")]
+    max_block_range: u64,
+    
+    /// Maximum number of slots a node may trail the cluster tip and still receive traffic
+    #[arg(long, default_value_t = node_cache::DEFAULT_MAX_SLOT_LAG)]
+    max_slot_lag: u64,
+    
+    /// Consecutive failed requests after which a node is quarantined
+    #[arg(long, default_value_t = node_cache::DEFAULT_QUARANTINE_AFTER_FAILURES)]
+    quarantine_after_failures: u32,
+    
+    /// How long a node's first quarantine lasts before it is re-probed, doubled for each further quarantine in a row (seconds)
+    #[arg(long, default_value_t = node_cache::DEFAULT_QUARANTINE_BACKOFF_SECS)]
+    quarantine_backoff: u64,
+    
+    /// Longest quarantine backoff (seconds)
+    #[arg(long, default_value_t = node_cache::DEFAULT_MAX_QUARANTINE_BACKOFF_SECS)]
+    max_quarantine_backoff: u64,
+    
+    /// Load-balancing strategy: top-n-random, p2c (power of two choices on outstanding
+    /// requests and latency), weighted-round-robin or least-outstanding
+    #[arg(long, default_value = "top-n-random")]
+    load_balancing: node_cache::Strategy,
+    
+    /// Strategy for one method group (light, heavy or transaction) as group:strategy (repeatable)
+    #[arg(long = "group-load-balancing")]
+    group_load_balancing: Vec<node_cache::GroupStrategy>,
+    
+    /// Weight of a node for weighted-round-robin as endpoint=weight, other nodes weigh 1 (repeatable)
+    #[arg(long = "node-weight")]
+    node_weights: Vec<node_cache::NodeWeight>,
+    
+    /// Send slow hedge_methods requests to a second node as well and use whichever answers first
+    #[arg(long)]
+    hedge_requests: bool,
+    
+    /// Methods that are hedged (comma separated)
+    #[arg(long, value_delimiter = ',', default_value = hedge::DEFAULT_HEDGE_METHODS)]
+    hedge_methods: Vec<String>,
+    
+    /// Latency percentile of a method after which its request is hedged
+    #[arg(long, default_value = "95", value_parser = clap::value_parser!(u8).range(1..=99))]
+    hedge_percentile: u8,
+    
+    /// Hedges allowed per 100 hedgeable requests, at most 50 so upstream load never doubles
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u8).range(0..=50))]
+    hedge_budget_percent: u8,
+    
+    /// Pin requests to a node by consistent hash: off, client (API key or IP) or
+    /// params (account or signature in the params, falling back to the client)
+    #[arg(long, default_value = "off")]
+    affinity: affinity::AffinityMode,
+    
+    /// Methods routed by affinity (comma separated, all methods when empty)
+    #[arg(long, value_delimiter = ',', default_value = affinity::DEFAULT_AFFINITY_METHODS)]
+    affinity_methods: Vec<String>,
+    
     /// Enable verbose logging
     #[arg(long)]
     verbose: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+/// Health check and discovery settings that can be replaced while running
+#[derive(Debug, Clone, PartialEq)]
+struct DiscoverySettings {
+    cluster_url: String,
+    expected_cluster: identity::ExpectedCluster,
+    /// Versions a node must run to receive any traffic
+    node_versions: version::VersionRange,
+    gossip_entrypoints: Vec<String>,
+    gossip_timeout: u64,
+    static_nodes: Vec<String>,
+    discovery_interval: u64,
+    evict_after_missed_discoveries: u32,
+    node_health_timeout: u64,
+    capability_check_interval: u64,
+    /// Built-in probes followed by the configured ones
+    probes: Vec<probe::Probe>,
+    max_concurrent_tests: usize,
+}
+
+/// Flags explicitly given on the command line, as opposed to defaulted
+fn command_line_flags(matches: &clap::ArgMatches) -> HashSet<String> {
+    matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        .map(|id| id.to_string())
+        .collect()
+}
+
+/// Overlay config file values on the command line arguments, keeping the
+/// flags that were explicitly given on the command line
+fn apply_config_file(cli_args: &Args, cli_flags: &HashSet<String>, file: ConfigFile) -> Args {
+    let mut args = cli_args.clone();
     
-    // auto-adjust concurrency parameters based on CPU cores
-    let cpu_cores = num_cpus::get();
-    let max_concurrent_tests = args.max_concurrent_tests.unwrap_or_else(|| {
-        match cpu_cores {
-            1 => 5,           // single-core: 5 concurrent tests
-            2..=4 => 20,      // 2-4 cores: 20 concurrent tests
-            5..=8 => 40,      // 5-8 cores: 40 concurrent tests
-            _ => 60,          // 8+ cores: 60 concurrent tests
+    macro_rules! overlay {
+        ($($field:ident),* $(,)?) => {$(
+            if let Some(value) = file.$field {
+                if !cli_flags.contains(stringify!($field)) {
+                    args.$field = value.into();
+                }
+            }
+        )*};
+    }
+    
+    overlay!(
+        port, pubsub_port, cluster_url, expected_genesis_hash, expected_shred_version, min_node_version,
+        max_node_version, method_versions, gossip_entrypoints, gossip_timeout, static_nodes,
+        discovery_interval, evict_after_missed_discoveries, health_check_interval, suspect_check_interval,
+        node_health_timeout, capability_check_interval,
+        builtin_probes, probes, rpc_request_timeout, stream_threshold_kb, max_response_mb, max_transfer_time, upstream_pool_max_idle,
+        upstream_pool_idle_timeout, upstream_connect_timeout, disable_upstream_http2, max_concurrent_tests,
+        max_concurrent_rpc_requests, max_queue_wait_time, batch_chunk_size, max_retry_attempts,
+        retry_budget, never_retry_methods, response_cache_mb, short_cache_ttl_ms,
+        disable_request_coalescing, rate_limit_rps, rate_limit_burst, method_costs, trusted_proxies,
+        api_keys, require_api_key, admin_token, allowed_methods, denied_methods, guarded_programs,
+        max_multiple_accounts, max_signatures_limit, max_block_range, max_slot_lag,
+        quarantine_after_failures, quarantine_backoff, max_quarantine_backoff, load_balancing,
+        group_load_balancing, node_weights, affinity, affinity_methods,
+        hedge_requests, hedge_methods, hedge_percentile, hedge_budget_percent, verbose,
+    );
+    
+    if let Some(policies) = file.method_retry_policies {
+        if !cli_flags.contains("method_retry_policies") {
+            args.method_retry_policies = policies.into_iter().map(Into::into).collect();
         }
+    }
+    
+    args
+}
+
+/// Concurrent node tests and RPC requests, auto-adjusted based on CPU cores when not set
+fn concurrency_limits(args: &Args) -> (usize, usize) {
+    let cpu_cores = num_cpus::get();
+    let max_concurrent_tests = args.max_concurrent_tests.unwrap_or(match cpu_cores {
+        1 => 5,           // single-core: 5 concurrent tests
+        2..=4 => 20,      // 2-4 cores: 20 concurrent tests
+        5..=8 => 40,      // 5-8 cores: 40 concurrent tests
+        _ => 60,          // 8+ cores: 60 concurrent tests
     });
     
-    let max_concurrent_rpc_requests = args.max_concurrent_rpc_requests.unwrap_or_else(|| {
-        match cpu_cores {
-            1 => 3,           // single-core: 3 concurrent RPC requests
-            2..=4 => 20,      // 2-4 cores: 20 concurrent RPC requests
-            5..=8 => 60,      // 5-8 cores: 60 concurrent RPC requests
-            _ => 100,         // 8+ cores: 100 concurrent RPC requests
-        }
+    let max_concurrent_rpc_requests = args.max_concurrent_rpc_requests.unwrap_or(match cpu_cores {
+        1 => 3,           // single-core: 3 concurrent RPC requests
+        2..=4 => 20,      // 2-4 cores: 20 concurrent RPC requests
+        5..=8 => 60,      // 5-8 cores: 60 concurrent RPC requests
+        _ => 100,         // 8+ cores: 100 concurrent RPC requests
     });
     
+    (max_concurrent_tests.max(1), max_concurrent_rpc_requests.max(1))
+}
+
+fn proxy_settings(args: &Args, max_concurrent_rpc_requests: usize) -> ProxySettings {
+    ProxySettings {
+        rpc_request_timeout: args.rpc_request_timeout,
+        max_concurrent_rpc: max_concurrent_rpc_requests,
+        max_queue_wait_time: args.max_queue_wait_time,
+        batch_chunk_size: args.batch_chunk_size,
+        retry_policies: retry::RetryPolicies::new(
+            retry::RetryPolicy {
+                max_attempts: args.max_retry_attempts.max(1),
+                budget: Duration::from_secs(args.retry_budget.unwrap_or(args.rpc_request_timeout)),
+            },
+            args.method_retry_policies.clone(),
+            args.never_retry_methods.clone(),
+        ),
+        response_cache_bytes: args.response_cache_mb * 1024 * 1024,
+        short_cache_ttl: Duration::from_millis(args.short_cache_ttl_ms),
+        coalesce_requests: !args.disable_request_coalescing,
+        rate_limits: rate_limit::RateLimits::new(
+            args.rate_limit_rps,
+            args.rate_limit_burst,
+            args.method_costs.clone(),
+            args.trusted_proxies.clone(),
+        ),
+        api_keys: args.api_keys.clone(),
+        require_api_key: args.require_api_key,
+        admin_token: args.admin_token.clone(),
+        guards: guard::MethodGuards::new(
+            args.allowed_methods.clone(),
+            args.denied_methods.clone(),
+            args.guarded_programs.clone(),
+            args.max_multiple_accounts,
+            args.max_signatures_limit,
+            args.max_block_range,
+        ),
+        body_limits: rpc_client::BodyLimits {
+            buffer_bytes: args.stream_threshold_kb * 1024,
+            max_bytes: args.max_response_mb * 1024 * 1024,
+            transfer_time: Duration::from_secs(args.max_transfer_time),
+        },
+        affinity: affinity::Affinity::new(args.affinity, args.affinity_methods.clone()),
+        hedging: hedge::HedgePolicy::new(
+            args.hedge_requests,
+            args.hedge_methods.clone(),
+            args.hedge_percentile,
+            args.hedge_budget_percent,
+        ),
+        method_versions: version::MethodVersions::new(args.method_versions.clone()),
+    }
+}
+
+fn upstream_client_config(args: &Args) -> UpstreamClientConfig {
+    UpstreamClientConfig {
+        pool_max_idle_per_node: args.upstream_pool_max_idle,
+        pool_idle_timeout: Duration::from_secs(args.upstream_pool_idle_timeout),
+        connect_timeout: Duration::from_secs(args.upstream_connect_timeout),
+        http2: !args.disable_upstream_http2,
+    }
+}
+
+fn quarantine_policy(args: &Args) -> QuarantinePolicy {
+    QuarantinePolicy {
+        failure_threshold: args.quarantine_after_failures.max(1),
+        base_backoff: Duration::from_secs(args.quarantine_backoff),
+        max_backoff: Duration::from_secs(args.max_quarantine_backoff.max(args.quarantine_backoff)),
+    }
+}
+
+fn check_schedule(args: &Args) -> CheckSchedule {
+    CheckSchedule {
+        healthy_interval: Duration::from_secs(args.health_check_interval.max(1)),
+        suspect_interval: Duration::from_secs(args.suspect_check_interval.max(1)),
+    }
+}
+
+fn load_balancing(args: &Args) -> LoadBalancing {
+    LoadBalancing {
+        default: args.load_balancing,
+        per_group: args.group_load_balancing.clone(),
+        weights: args.node_weights.clone(),
+    }
+}
+
+fn discovery_settings(args: &Args, max_concurrent_tests: usize) -> DiscoverySettings {
+    DiscoverySettings {
+        cluster_url: args.cluster_url.clone(),
+        expected_cluster: identity::ExpectedCluster {
+            genesis_hash: args.expected_genesis_hash.clone(),
+            shred_version: args.expected_shred_version,
+        },
+        node_versions: version::VersionRange {
+            min: args.min_node_version,
+            max: args.max_node_version,
+        },
+        gossip_entrypoints: args.gossip_entrypoints.clone(),
+        gossip_timeout: args.gossip_timeout,
+        static_nodes: args.static_nodes.clone(),
+        discovery_interval: args.discovery_interval.max(1),
+        evict_after_missed_discoveries: args.evict_after_missed_discoveries,
+        node_health_timeout: args.node_health_timeout,
+        capability_check_interval: args.capability_check_interval,
+        probes: args.builtin_probes
+            .iter()
+            .map(|builtin| builtin.probe())
+            .chain(args.probes.iter().cloned())
+            .collect(),
+        max_concurrent_tests,
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let cli_args = Args::from_arg_matches(&matches)?;
+    let cli_flags = command_line_flags(&matches);
+    
+    let args = match &cli_args.config {
+        Some(path) => apply_config_file(&cli_args, &cli_flags, ConfigFile::load(path)?),
+        None => cli_args.clone(),
+    };
+    auth::check_names(&args.api_keys)?;
+    
+    let cpu_cores = num_cpus::get();
+    let (max_concurrent_tests, max_concurrent_rpc_requests) = concurrency_limits(&args);
+    
     // Set log level based on verbose flag
     if args.verbose {
         tracing_subscriber::fmt()
@@ -105,121 +561,424 @@ async fn main() -> Result<()> {
     }
     
     info!("🚀 Starting X1 RPC Proxy Server...");
+    if let Some(path) = &args.config {
+        info!("Config file: {}", path.display());
+    }
     info!("Target cluster: {}", args.cluster_url);
     info!("Max concurrent tests: {} (auto-adjusted)", max_concurrent_tests);
     info!("Max concurrent RPC requests: {} (auto-adjusted)", max_concurrent_rpc_requests);
+    info!("Discovery interval: {}s", args.discovery_interval);
+    info!("Healthy node check interval: {}s", args.health_check_interval);
     info!("Node health check timeout: {}s", args.node_health_timeout);
     info!("RPC request timeout: {}s", args.rpc_request_timeout);
     
     // Create shared state
-    let node_cache = Arc::new(NodeCache::new());
-    let gossip_client = Arc::new(GossipClient::new_with_cluster(&args.cluster_url));
+    let node_cache = Arc::new(NodeCache::new(
+        args.max_slot_lag,
+        quarantine_policy(&args),
+        check_schedule(&args),
+        load_balancing(&args),
+    ));
+    let upstream = UpstreamClient::new(&upstream_client_config(&args))?;
+    let (proxy_settings_tx, proxy_settings_rx) =
+        watch::channel(Arc::new(proxy_settings(&args, max_concurrent_rpc_requests)));
+    let (discovery_settings_tx, discovery_settings_rx) =
+        watch::channel(Arc::new(discovery_settings(&args, max_concurrent_tests)));
+    let cluster_identity = Arc::new(identity::ClusterIdentity::new());
     
-    // Start node discovery and health check task
+    // Start node discovery task
     let node_cache_clone = Arc::clone(&node_cache);
-    let gossip_client_clone = Arc::clone(&gossip_client);
+    let upstream_clone = upstream.clone();
+    let discovery_settings_clone = discovery_settings_rx.clone();
+    let cluster_identity_clone = Arc::clone(&cluster_identity);
     tokio::spawn(async move {
-        node_discovery_task(
-            node_cache_clone, 
-            gossip_client_clone, 
-            args.health_check_interval, 
-            args.node_health_timeout,
-            max_concurrent_tests
-        ).await;
+        node_discovery_task(node_cache_clone, upstream_clone, discovery_settings_clone, cluster_identity_clone).await;
     });
     
-    // Wait for node discovery task to run first
+    // Health check nodes as they come due
+    let node_cache_clone = Arc::clone(&node_cache);
+    let upstream_clone = upstream.clone();
+    tokio::spawn(async move {
+        health_check_task(node_cache_clone, upstream_clone, discovery_settings_rx, cluster_identity).await;
+    });
+    
+    if let Some(path) = args.config.clone() {
+        let node_cache_clone = Arc::clone(&node_cache);
+        let current_args = args.clone();
+        tokio::spawn(async move {
+            config_reload_task(
+                path,
+                cli_args,
+                cli_flags,
+                current_args,
+                proxy_settings_tx,
+                discovery_settings_tx,
+                node_cache_clone,
+            ).await;
+        });
+    }
+    
+    // Give the first discovery and health checks a head start
     sleep(Duration::from_secs(2)).await;
     
     // Start proxy server
-    let proxy_server = ProxyServer::new(
-        Arc::clone(&node_cache), 
-        args.rpc_request_timeout,
-        max_concurrent_rpc_requests,
-        args.max_queue_wait_time
-    );
-    proxy_server.start(args.port).await?;
+    let proxy_server = ProxyServer::new(Arc::clone(&node_cache), upstream, proxy_settings_rx);
+    proxy_server.start(args.port, args.pubsub_port).await?;
     
     Ok(())
 }
 
-async fn node_discovery_task(
+/// Re-read the config file on SIGHUP or when it changes and hand the new
+/// settings to the proxy server and discovery task
+async fn config_reload_task(
+    path: PathBuf,
+    cli_args: Args,
+    cli_flags: HashSet<String>,
+    mut current_args: Args,
+    proxy_settings_tx: watch::Sender<Arc<ProxySettings>>,
+    discovery_settings_tx: watch::Sender<Arc<DiscoverySettings>>,
     node_cache: Arc<NodeCache>,
-    gossip_client: Arc<GossipClient>,
-    health_check_interval: u64,
-    node_health_timeout: u64,
-    max_concurrent_tests: usize,
 ) {
-    let mut interval = tokio::time::interval(Duration::from_secs(health_check_interval));
+    let mut trigger = match ReloadTrigger::new(path.clone()) {
+        Ok(trigger) => trigger,
+        Err(e) => {
+            error!("Failed to watch config file {}: {}", path.display(), e);
+            return;
+        }
+    };
     
     loop {
-        interval.tick().await;
+        trigger.wait().await;
         
-        // Use spawn_blocking for potentially blocking gossip operation
-        let gossip_client_clone = Arc::clone(&gossip_client);
-        let nodes_result = tokio::task::spawn_blocking(move || {
-            tokio::runtime::Handle::current().block_on(gossip_client_clone.get_rpc_nodes())
-        }).await;
+        let args = match ConfigFile::load(&path) {
+            Ok(file) => apply_config_file(&cli_args, &cli_flags, file),
+            Err(e) => {
+                error!("❌ Failed to reload config, keeping current settings: {:#}", e);
+                continue;
+            }
+        };
         
-        match nodes_result {
-            Ok(Ok(nodes)) => {
-                info!("📡 Discovered {} potential RPC nodes", nodes.len());
-                
-                //  multi-core optimization: use higher concurrency, no batch processing
-                let semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent_tests));
-                let mut handles = Vec::new();
-                
-                for node in nodes {
-                    let node_cache_clone = Arc::clone(&node_cache);
-                    let semaphore_clone = Arc::clone(&semaphore);
-                    
-                    let handle = tokio::spawn(async move {
-                        let _permit = semaphore_clone.acquire().await.unwrap();
-                        test_and_update_node(node_cache_clone, node, node_health_timeout).await;
-                    });
-                    handles.push(handle);
-                }
-                
-                // wait for all tests to complete
-                for handle in handles {
-                    if let Err(e) = handle.await {
-                        error!("Node test task failed: {}", e);
+        if args.port != current_args.port
+            || args.pubsub_port != current_args.pubsub_port
+            || args.verbose != current_args.verbose
+            || upstream_client_config(&args) != upstream_client_config(&current_args)
+        {
+            warn!("⚠️  Changes to port, pubsub_port, verbose and upstream connection settings only take effect after a restart");
+        }
+        
+        let (max_concurrent_tests, max_concurrent_rpc_requests) = concurrency_limits(&args);
+        proxy_settings_tx.send_replace(Arc::new(proxy_settings(&args, max_concurrent_rpc_requests)));
+        
+        // Only restart the discovery schedule when something it uses changed
+        let new_discovery_settings = discovery_settings(&args, max_concurrent_tests);
+        discovery_settings_tx.send_if_modified(|settings| {
+            if **settings == new_discovery_settings {
+                return false;
+            }
+            *settings = Arc::new(new_discovery_settings);
+            true
+        });
+        
+        node_cache.set_max_slot_lag(args.max_slot_lag);
+        node_cache.set_quarantine_policy(quarantine_policy(&args));
+        node_cache.set_check_schedule(check_schedule(&args));
+        node_cache.set_load_balancing(load_balancing(&args));
+        
+        info!("🔄 Reloaded configuration from {} (discovery interval: {}s, healthy node check interval: {}s, RPC request timeout: {}s, max concurrent RPC requests: {})", 
+              path.display(), args.discovery_interval, args.health_check_interval, args.rpc_request_timeout, max_concurrent_rpc_requests);
+        current_args = args;
+    }
+}
+
+async fn node_discovery_task(
+    node_cache: Arc<NodeCache>,
+    upstream: UpstreamClient,
+    mut settings: watch::Receiver<Arc<DiscoverySettings>>,
+    cluster_identity: Arc<identity::ClusterIdentity>,
+) {
+    loop {
+        let current = Arc::clone(&settings.borrow_and_update());
+        let gossip_client = GossipClient::new_with_cluster(
+            &current.cluster_url,
+            current.gossip_entrypoints.clone(),
+            Duration::from_secs(current.gossip_timeout),
+            upstream.clone(),
+            current.node_health_timeout,
+        )
+        .with_shred_version(current.expected_cluster.shred_version);
+        let mut interval = tokio::time::interval(Duration::from_secs(current.discovery_interval));
+        
+        // Run on the current settings until new ones arrive
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    discover_nodes(&node_cache, &gossip_client, &current).await;
+                    // health checks hold nodes to the shred version gossip adopted
+                    if current.expected_cluster.shred_version.is_none() {
+                        if let Some(shred_version) = gossip_client.shred_version() {
+                            cluster_identity.adopt_shred_version(&current.gossip_entrypoints, shred_version);
+                        }
                     }
                 }
-                
-                let (total, active, min_response, max_response) = node_cache.get_performance_stats().await;
-                info!("📊 Node performance stats - Total: {}, Active: {}", total, active);
-                if let (Some(min), Some(max)) = (min_response, max_response) {
-                    info!("⚡ Health check response time range: {:?} - {:?}", min, max);
-                }
-                
-                if active == 0 {
-                    warn!("⚠️  Warning: No active RPC nodes available!");
+                Ok(()) = settings.changed() => {
+                    info!("🔄 Discovery settings changed, discovery interval: {}s", 
+                          settings.borrow().discovery_interval);
+                    break;
                 }
             }
-            Ok(Err(e)) => {
-                error!("Failed to get RPC nodes: {}", e);
-            }
-            Err(e) => {
-                error!("Gossip task join error: {}", e);
-            }
         }
     }
 }
 
-async fn test_and_update_node(node_cache: Arc<NodeCache>, node: types::RpcNode, node_health_timeout: u64) {
-    let start_time = std::time::Instant::now();
-    
-    // use node health check timeout
-    match rpc_client::test_rpc_node(&node.endpoint, node_health_timeout).await {
-        Ok(_) => {
-            let response_time = start_time.elapsed();
-            info!("✅ RPC node {} is available, health check time: {:?}", node.endpoint, response_time);
-            node_cache.update_node_status(node, true, response_time).await;
+/// Health check nodes as they come due, see `NodeCache::start_due_checks`. At most
+/// `max_concurrent_tests` checks run at once, nodes due beyond that wait for a later tick.
+async fn health_check_task(
+    node_cache: Arc<NodeCache>,
+    upstream: UpstreamClient,
+    settings: watch::Receiver<Arc<DiscoverySettings>>,
+    cluster_identity: Arc<identity::ClusterIdentity>,
+) {
+    let mut max_concurrent_tests = settings.borrow().max_concurrent_tests;
+    let mut semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent_tests));
+    let mut interval = tokio::time::interval(SCHEDULER_TICK);
+    loop {
+        interval.tick().await;
+        
+        let current = Arc::clone(&settings.borrow());
+        // checks already running keep their permits from the old semaphore
+        if current.max_concurrent_tests != max_concurrent_tests {
+            max_concurrent_tests = current.max_concurrent_tests;
+            semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent_tests));
+        }
+        
+        let due = node_cache.start_due_checks(semaphore.available_permits()).await;
+        if due.is_empty() {
+            continue;
+        }
+        
+        let genesis_hash = cluster_genesis_hash(&cluster_identity, &upstream, &current).await;
+        let shred_version = cluster_identity.shred_version(&current.expected_cluster, &current.gossip_entrypoints);
+        debug!("🔬 Health checking {} nodes", due.len());
+        for node in due {
+            let node_cache = Arc::clone(&node_cache);
+            let semaphore = Arc::clone(&semaphore);
+            let upstream = upstream.clone();
+            let current = Arc::clone(&current);
+            let genesis_hash = genesis_hash.clone();
+            tokio::spawn(async move {
+                let _permit = semaphore.acquire().await.unwrap();
+                test_and_update_node(node_cache, &upstream, node, &current, genesis_hash.as_deref(), shred_version).await;
+            });
+        }
+    }
+}
+
+/// Add nodes from gossip and the static list to the pool, new ones are checked
+/// by the health check task right away. Nodes a successful discovery stopped
+/// listing are dropped after `evict_after_missed_discoveries` rounds.
+async fn discover_nodes(
+    node_cache: &NodeCache,
+    gossip_client: &GossipClient,
+    settings: &DiscoverySettings,
+) {
+    let (mut nodes, discovered) = match gossip_client.get_rpc_nodes().await {
+        Ok(nodes) => {
+            info!("📡 Discovered {} potential RPC nodes", nodes.len());
+            (nodes, true)
         }
         Err(e) => {
-            warn!("❌ RPC node {} health check failed: {}", node.endpoint, e);
-            node_cache.update_node_status(node, false, Duration::from_secs(0)).await;
+            error!("Failed to get RPC nodes: {}", e);
+            (gossip_client.default_rpc_nodes(), false)
+        }
+    };
+    
+    for endpoint in &settings.static_nodes {
+        if !nodes.iter().any(|node| &node.endpoint == endpoint) {
+            nodes.push(types::RpcNode::new(endpoint.clone()));
+        }
+    }
+    
+    let listed: HashSet<String> = nodes.iter().map(|node| node.endpoint.clone()).collect();
+    let added = node_cache.add_discovered(nodes).await;
+    if added > 0 {
+        info!("🆕 {} new RPC nodes queued for a health check", added);
+    }
+    
+    // A failed round says nothing about which nodes left the cluster
+    if discovered && settings.evict_after_missed_discoveries > 0 {
+        let listed = listed.iter().map(String::as_str).collect();
+        let evicted = node_cache.evict_missing(&listed, settings.evict_after_missed_discoveries).await;
+        if !evicted.is_empty() {
+            info!("🗑️  Dropped {} RPC nodes no longer listed by discovery: {:?}", evicted.len(), evicted);
+        }
+    }
+    
+    let (total, active, min_response, max_response) = node_cache.get_performance_stats().await;
+    info!("📊 Node performance stats - Total: {}, Active: {}", total, active);
+    if let (Some(min), Some(max)) = (min_response, max_response) {
+        info!("⚡ Health check response time range: {:?} - {:?}", min, max);
+    }
+    
+    if active == 0 {
+        warn!("⚠️  Warning: No active RPC nodes available!");
+    }
+}
+
+/// Genesis hash the cluster being served reports, see `ClusterIdentity::genesis_hash`
+async fn cluster_genesis_hash(
+    cluster_identity: &identity::ClusterIdentity,
+    upstream: &UpstreamClient,
+    settings: &DiscoverySettings,
+) -> Option<String> {
+    cluster_identity
+        .genesis_hash(upstream, &settings.expected_cluster, &settings.cluster_url, settings.node_health_timeout)
+        .await
+}
+
+/// Health check `node` and verify it belongs to the cluster with `expected_genesis_hash`
+/// and `expected_shred_version` and runs an allowed version. A node that fails any
+/// of these receives no traffic.
+async fn test_and_update_node(
+    node_cache: Arc<NodeCache>,
+    upstream: &UpstreamClient,
+    mut node: types::RpcNode,
+    settings: &DiscoverySettings,
+    expected_genesis_hash: Option<&str>,
+    expected_shred_version: Option<u16>,
+) {
+    let start_time = std::time::Instant::now();
+    
+    let mut report = upstream.run_probes(&node.endpoint, &settings.probes, settings.node_health_timeout).await;
+    let response_time = start_time.elapsed();
+    // Keep what gossip advertised when the node doesn't answer getVersion
+    if let Some((version, feature_set)) = report.version.take() {
+        node.version = Some(version);
+        node.feature_set = feature_set.or(node.feature_set);
+    }
+    report.results.push(identity::verify(
+        &node,
+        report.genesis_hash.as_deref(),
+        expected_genesis_hash,
+        expected_shred_version,
+    ));
+    if !settings.node_versions.is_unbounded() {
+        report.results.push(version::verify(node.version, &settings.node_versions));
+    }
+    node.health_score = Some(report.score());
+    node.probes = report.results.clone();
+    
+    if report.passed() {
+        let now = std::time::SystemTime::now();
+        let capability_check_interval = Duration::from_secs(settings.capability_check_interval);
+        let capabilities = async {
+            if node.capabilities.are_stale(node.version, capability_check_interval, now) {
+                Some(upstream.probe_capabilities(&node.endpoint, settings.node_health_timeout).await)
+            } else {
+                None
+            }
+        };
+        let (slots, capabilities) = tokio::join!(
+            upstream.get_node_slots(&node.endpoint, settings.node_health_timeout),
+            capabilities,
+        );
+        node.slots = slots;
+        if let Some(capabilities) = capabilities {
+            node.capabilities = types::NodeCapabilities {
+                checked_at: Some(now),
+                checked_version: node.version,
+                ..capabilities
+            };
         }
+        info!("✅ RPC node {} is available, health check time: {:?}, health score: {:.2}, slot: {:?}", 
+              node.endpoint, response_time, report.score(), node.slots.processed);
+        node_cache.update_node_status(node, CheckOutcome::Passed, Some(response_time)).await;
+    } else {
+        warn!("❌ RPC node {} health check failed: {}", node.endpoint, report.failures());
+        // a node of another cluster or on a disallowed version must never serve, not even as the last one
+        let disqualified = identity::is_foreign(
+            &node,
+            report.genesis_hash.as_deref(),
+            expected_genesis_hash,
+            expected_shred_version,
+        ) || version::is_disallowed(node.version, &settings.node_versions);
+        let outcome = if disqualified {
+            CheckOutcome::Disqualified
+        } else {
+            CheckOutcome::Failed
+        };
+        node_cache.update_node_status(node, outcome, None).await;
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn parse(flags: &[&str]) -> (Args, HashSet<String>) {
+        let matches = Args::command().get_matches_from(std::iter::once("x1-rpc-proxy").chain(flags.iter().copied()));
+        (Args::from_arg_matches(&matches).unwrap(), command_line_flags(&matches))
+    }
+    
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("x1-rpc-proxy-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+    
+    #[test]
+    fn command_line_flags_win_over_the_config_file() {
+        let path = config_file("overlay.toml", r#"
+            port = 8500
+            cluster_url = "http://10.0.0.1:8899"
+            max_slot_lag = 10
+            rpc_request_timeout = 3
+            never_retry_methods = ["getBlock"]
+            
+            [[method_retry_policies]]
+            method = "getProgramAccounts"
+            max_attempts = 1
+            budget_secs = 20
+        "#);
+        // the default lag, given explicitly, still wins
+        let (cli_args, cli_flags) = parse(&["--port", "9000", "--max-slot-lag", "50"]);
+        let args = apply_config_file(&cli_args, &cli_flags, ConfigFile::load(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!(args.port, 9000);
+        assert_eq!(args.max_slot_lag, 50);
+        assert_eq!(args.cluster_url, "http://10.0.0.1:8899");
+        assert_eq!(args.rpc_request_timeout, 3);
+        assert_eq!(args.never_retry_methods, vec!["getBlock".to_string()]);
+        assert_eq!(args.method_retry_policies.len(), 1);
+        assert_eq!(args.method_retry_policies[0].policy.budget, Duration::from_secs(20));
+        // keys the file leaves out keep their defaults
+        assert_eq!(args.batch_chunk_size, cli_args.batch_chunk_size);
+    }
+    
+    #[tokio::test]
+    async fn a_bad_reload_keeps_the_running_settings() {
+        let path = config_file("reload.toml", "rpc_request_timeout = 7\n");
+        let (cli_args, cli_flags) = parse(&[]);
+        let args = apply_config_file(&cli_args, &cli_flags, ConfigFile::load(&path).unwrap());
+        let (max_concurrent_tests, max_concurrent_rpc_requests) = concurrency_limits(&args);
+        let (proxy_settings_tx, mut proxy_settings) = watch::channel(Arc::new(proxy_settings(&args, max_concurrent_rpc_requests)));
+        let (discovery_settings_tx, _) = watch::channel(Arc::new(discovery_settings(&args, max_concurrent_tests)));
+        let reload = tokio::spawn(config_reload_task(
+            path.clone(), cli_args, cli_flags, args, proxy_settings_tx, discovery_settings_tx, Arc::new(NodeCache::default()),
+        ));
+        sleep(Duration::from_millis(200)).await;
+        
+        // an unknown key fails the whole file, nothing of it applies
+        std::fs::write(&path, "rpc_request_timeout = 9\nrpc_request_timeot = 9\n").unwrap();
+        sleep(Duration::from_secs(3)).await;
+        assert!(!proxy_settings.has_changed().unwrap());
+        assert_eq!(proxy_settings.borrow().rpc_request_timeout, 7);
+        
+        std::fs::write(&path, "rpc_request_timeout = 9\n").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), proxy_settings.changed()).await.unwrap().unwrap();
+        assert_eq!(proxy_settings.borrow().rpc_request_timeout, 9);
+        
+        reload.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
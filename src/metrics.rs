@@ -0,0 +1,619 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::auth::ApiKeyUsage;
+use crate::cache::CacheStats;
+use crate::types::{MethodClass, NodeSlots, NodeState, RpcNode};
+use crate::version::Version;
+
+/// Histogram bucket upper bounds in seconds
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
+
+/// Distinct method labels kept before further methods are folded into "other",
+/// so clients sending made-up method names can't grow the label set without bound
+const MAX_METHOD_LABELS: usize = 256;
+
+#[derive(Clone, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+    
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count);
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/// Request, error and latency metrics, rendered in the Prometheus text format
+pub struct Metrics {
+    started: Instant,
+    method_labels: Mutex<HashSet<String>>,
+    requests: Mutex<BTreeMap<String, u64>>,
+    errors: Mutex<BTreeMap<(String, i32), u64>>,
+    coalesced: Mutex<BTreeMap<String, u64>>,
+    /// Hedges sent and hedges that answered first, by method
+    hedged: Mutex<BTreeMap<String, (u64, u64)>>,
+    streamed: Mutex<BTreeMap<String, (u64, u64)>>,
+    upstream_latency: Mutex<BTreeMap<(String, String), Histogram>>,
+    upstream_failures: Mutex<BTreeMap<String, u64>>,
+    queue_wait: Mutex<Histogram>,
+    queue_waiting: AtomicUsize,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps a request counted in the queue depth gauge until dropped
+pub struct QueuedGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.queue_waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            method_labels: Mutex::new(HashSet::new()),
+            requests: Mutex::new(BTreeMap::new()),
+            errors: Mutex::new(BTreeMap::new()),
+            coalesced: Mutex::new(BTreeMap::new()),
+            hedged: Mutex::new(BTreeMap::new()),
+            streamed: Mutex::new(BTreeMap::new()),
+            upstream_latency: Mutex::new(BTreeMap::new()),
+            upstream_failures: Mutex::new(BTreeMap::new()),
+            queue_wait: Mutex::new(Histogram::default()),
+            queue_waiting: AtomicUsize::new(0),
+        }
+    }
+    
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+    
+    fn method_label(&self, method: &str) -> String {
+        let mut labels = self.method_labels.lock().unwrap();
+        if labels.contains(method) {
+            return method.to_string();
+        }
+        if labels.len() >= MAX_METHOD_LABELS {
+            return "other".to_string();
+        }
+        labels.insert(method.to_string());
+        method.to_string()
+    }
+    
+    pub fn record_request(&self, method: &str) {
+        let method = self.method_label(method);
+        *self.requests.lock().unwrap().entry(method).or_insert(0) += 1;
+    }
+    
+    /// Count a JSON-RPC error returned to a client, whether produced by the proxy or an upstream node
+    pub fn record_error(&self, method: &str, code: i32) {
+        let method = self.method_label(method);
+        *self.errors.lock().unwrap().entry((method, code)).or_insert(0) += 1;
+    }
+    
+    /// Count a request answered by an identical request already in flight
+    pub fn record_coalesced(&self, method: &str) {
+        let method = self.method_label(method);
+        *self.coalesced.lock().unwrap().entry(method).or_insert(0) += 1;
+    }
+    
+    /// Count a request sent to a second node because the first was slow, and whether its answer was used
+    pub fn record_hedge(&self, method: &str, won: bool) {
+        let method = self.method_label(method);
+        let mut hedged = self.hedged.lock().unwrap();
+        let (hedges, wins) = hedged.entry(method).or_insert((0, 0));
+        *hedges += 1;
+        if won {
+            *wins += 1;
+        }
+    }
+    
+    /// Count a response too large to buffer that was streamed to the client, and the bytes sent
+    pub fn record_streamed(&self, method: &str, bytes: usize) {
+        let method = self.method_label(method);
+        let mut streamed = self.streamed.lock().unwrap();
+        let (responses, total_bytes) = streamed.entry(method).or_insert((0, 0));
+        *responses += 1;
+        *total_bytes += bytes as u64;
+    }
+    
+    /// Count the error in a raw upstream response body, if it carries one
+    pub fn record_response(&self, method: &str, raw_response: &str) {
+        #[derive(serde::Deserialize)]
+        struct ErrorProbe {
+            error: Option<ErrorCode>,
+        }
+        #[derive(serde::Deserialize)]
+        struct ErrorCode {
+            code: i32,
+        }
+        
+        if let Ok(ErrorProbe { error: Some(error) }) = serde_json::from_str(raw_response) {
+            self.record_error(method, error.code);
+        }
+    }
+    
+    /// Time spent on one upstream attempt, successful or not
+    pub fn observe_upstream(&self, method: &str, node: &str, elapsed: Duration, success: bool) {
+        let method = self.method_label(method);
+        self.upstream_latency
+            .lock()
+            .unwrap()
+            .entry((method, node.to_string()))
+            .or_default()
+            .observe(elapsed);
+        
+        if !success {
+            *self.upstream_failures.lock().unwrap().entry(node.to_string()).or_insert(0) += 1;
+        }
+    }
+    
+    pub fn observe_queue_wait(&self, wait: Duration) {
+        self.queue_wait.lock().unwrap().observe(wait);
+    }
+    
+    pub fn enter_queue(&self) -> QueuedGuard<'_> {
+        self.queue_waiting.fetch_add(1, Ordering::Relaxed);
+        QueuedGuard { metrics: self }
+    }
+    
+    /// Render the proxy's own metrics along with queue occupancy
+    pub fn render(&self, out: &mut String, active_requests: usize, max_concurrent: usize) {
+        out.push_str("# HELP rpc_proxy_uptime_seconds Seconds since the proxy started\n");
+        out.push_str("# TYPE rpc_proxy_uptime_seconds gauge\n");
+        let _ = writeln!(out, "rpc_proxy_uptime_seconds {}", self.uptime().as_secs_f64());
+        
+        out.push_str("# HELP rpc_proxy_requests_total JSON-RPC requests received, batch entries counted individually\n");
+        out.push_str("# TYPE rpc_proxy_requests_total counter\n");
+        for (method, count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(out, "rpc_proxy_requests_total{{method=\"{}\"}} {}", escape_label(method), count);
+        }
+        
+        out.push_str("# HELP rpc_proxy_errors_total JSON-RPC errors returned to clients by error code\n");
+        out.push_str("# TYPE rpc_proxy_errors_total counter\n");
+        for ((method, code), count) in self.errors.lock().unwrap().iter() {
+            let _ = writeln!(out, "rpc_proxy_errors_total{{method=\"{}\",code=\"{}\"}} {}",
+                             escape_label(method), code, count);
+        }
+        
+        out.push_str("# HELP rpc_proxy_coalesced_requests_total Requests answered by an identical request already in flight\n");
+        out.push_str("# TYPE rpc_proxy_coalesced_requests_total counter\n");
+        for (method, count) in self.coalesced.lock().unwrap().iter() {
+            let _ = writeln!(out, "rpc_proxy_coalesced_requests_total{{method=\"{}\"}} {}", escape_label(method), count);
+        }
+        
+        out.push_str("# HELP rpc_proxy_hedged_requests_total Requests also sent to a second node because the first was slow\n");
+        out.push_str("# TYPE rpc_proxy_hedged_requests_total counter\n");
+        let hedged = self.hedged.lock().unwrap();
+        for (method, (hedges, _)) in hedged.iter() {
+            let _ = writeln!(out, "rpc_proxy_hedged_requests_total{{method=\"{}\"}} {}", escape_label(method), hedges);
+        }
+        
+        out.push_str("# HELP rpc_proxy_hedge_wins_total Hedged requests answered by the second node\n");
+        out.push_str("# TYPE rpc_proxy_hedge_wins_total counter\n");
+        for (method, (_, wins)) in hedged.iter() {
+            let _ = writeln!(out, "rpc_proxy_hedge_wins_total{{method=\"{}\"}} {}", escape_label(method), wins);
+        }
+        drop(hedged);
+        
+        out.push_str("# HELP rpc_proxy_streamed_responses_total Responses too large to buffer, streamed to the client\n");
+        out.push_str("# TYPE rpc_proxy_streamed_responses_total counter\n");
+        let streamed = self.streamed.lock().unwrap();
+        for (method, (responses, _)) in streamed.iter() {
+            let _ = writeln!(out, "rpc_proxy_streamed_responses_total{{method=\"{}\"}} {}", escape_label(method), responses);
+        }
+        
+        out.push_str("# HELP rpc_proxy_streamed_bytes_total Bytes sent to clients in streamed responses\n");
+        out.push_str("# TYPE rpc_proxy_streamed_bytes_total counter\n");
+        for (method, (_, bytes)) in streamed.iter() {
+            let _ = writeln!(out, "rpc_proxy_streamed_bytes_total{{method=\"{}\"}} {}", escape_label(method), bytes);
+        }
+        drop(streamed);
+        
+        out.push_str("# HELP rpc_proxy_upstream_request_duration_seconds Time spent on each upstream attempt\n");
+        out.push_str("# TYPE rpc_proxy_upstream_request_duration_seconds histogram\n");
+        for ((method, node), histogram) in self.upstream_latency.lock().unwrap().iter() {
+            let labels = format!("method=\"{}\",node=\"{}\"", escape_label(method), escape_label(node));
+            histogram.render(out, "rpc_proxy_upstream_request_duration_seconds", &labels);
+        }
+        
+        out.push_str("# HELP rpc_proxy_upstream_failures_total Failed upstream attempts by node\n");
+        out.push_str("# TYPE rpc_proxy_upstream_failures_total counter\n");
+        for (node, count) in self.upstream_failures.lock().unwrap().iter() {
+            let _ = writeln!(out, "rpc_proxy_upstream_failures_total{{node=\"{}\"}} {}", escape_label(node), count);
+        }
+        
+        out.push_str("# HELP rpc_proxy_queue_wait_seconds Time requests waited for a queue slot\n");
+        out.push_str("# TYPE rpc_proxy_queue_wait_seconds histogram\n");
+        self.queue_wait.lock().unwrap().render(out, "rpc_proxy_queue_wait_seconds", "");
+        
+        out.push_str("# HELP rpc_proxy_queue_depth Requests currently waiting for a queue slot\n");
+        out.push_str("# TYPE rpc_proxy_queue_depth gauge\n");
+        let _ = writeln!(out, "rpc_proxy_queue_depth {}", self.queue_waiting.load(Ordering::Relaxed));
+        
+        out.push_str("# HELP rpc_proxy_active_requests Queue slots currently held\n");
+        out.push_str("# TYPE rpc_proxy_active_requests gauge\n");
+        let _ = writeln!(out, "rpc_proxy_active_requests {}", active_requests);
+        
+        out.push_str("# HELP rpc_proxy_max_concurrent_requests Size of the request queue\n");
+        out.push_str("# TYPE rpc_proxy_max_concurrent_requests gauge\n");
+        let _ = writeln!(out, "rpc_proxy_max_concurrent_requests {}", max_concurrent);
+    }
+}
+
+/// Render response cache lookups and occupancy
+pub fn render_cache_stats(out: &mut String, stats: &CacheStats) {
+    out.push_str("# HELP rpc_proxy_cache_lookups_total Response cache lookups by cache class and result\n");
+    out.push_str("# TYPE rpc_proxy_cache_lookups_total counter\n");
+    for (class, class_stats) in [("immutable", stats.immutable), ("short_lived", stats.short_lived)] {
+        let _ = writeln!(out, "rpc_proxy_cache_lookups_total{{class=\"{}\",result=\"hit\"}} {}", class, class_stats.hits);
+        let _ = writeln!(out, "rpc_proxy_cache_lookups_total{{class=\"{}\",result=\"miss\"}} {}", class, class_stats.misses);
+    }
+    
+    out.push_str("# HELP rpc_proxy_cache_entries Results held in the response cache\n");
+    out.push_str("# TYPE rpc_proxy_cache_entries gauge\n");
+    let _ = writeln!(out, "rpc_proxy_cache_entries {}", stats.entries);
+    
+    out.push_str("# HELP rpc_proxy_cache_bytes Size of the results held in the response cache\n");
+    out.push_str("# TYPE rpc_proxy_cache_bytes gauge\n");
+    let _ = writeln!(out, "rpc_proxy_cache_bytes {}", stats.bytes);
+}
+
+/// Render per-node health state as tracked by the node cache
+pub fn render_node_health(out: &mut String, cluster_tip: &NodeSlots, nodes: &[(RpcNode, Option<u64>)], max_slot_lag: u64) {
+    let active = nodes.iter().filter(|(node, _)| node.is_active).count();
+    
+    out.push_str("# HELP rpc_proxy_nodes Known RPC nodes by health state\n");
+    out.push_str("# TYPE rpc_proxy_nodes gauge\n");
+    let _ = writeln!(out, "rpc_proxy_nodes{{state=\"active\"}} {}", active);
+    let _ = writeln!(out, "rpc_proxy_nodes{{state=\"inactive\"}} {}", nodes.len() - active);
+    
+    out.push_str("# HELP rpc_proxy_node_states Known RPC nodes by failure handling state\n");
+    out.push_str("# TYPE rpc_proxy_node_states gauge\n");
+    for state in NodeState::ALL {
+        let count = nodes.iter().filter(|(node, _)| node.health.state == state).count();
+        let _ = writeln!(out, "rpc_proxy_node_states{{state=\"{}\"}} {}", state.as_str(), count);
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_versions Known RPC nodes by software version and whether they receive traffic\n");
+    out.push_str("# TYPE rpc_proxy_node_versions gauge\n");
+    let mut versions: BTreeMap<(Option<Version>, bool), usize> = BTreeMap::new();
+    for (node, _) in nodes {
+        *versions.entry((node.version, node.is_active)).or_default() += 1;
+    }
+    for ((version, is_active), count) in versions {
+        let _ = writeln!(out, "rpc_proxy_node_versions{{version=\"{}\",state=\"{}\"}} {}",
+                         version.map_or_else(|| "unknown".to_string(), |version| version.to_string()),
+                         if is_active { "active" } else { "inactive" }, count);
+    }
+    
+    out.push_str("# HELP rpc_proxy_cluster_tip_slot Highest projected slot across active nodes\n");
+    out.push_str("# TYPE rpc_proxy_cluster_tip_slot gauge\n");
+    for commitment in NodeSlots::COMMITMENTS {
+        if let Some(slot) = cluster_tip.get(commitment) {
+            let _ = writeln!(out, "rpc_proxy_cluster_tip_slot{{commitment=\"{}\"}} {}", commitment, slot);
+        }
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_up Whether the node receives traffic (healthy or suspect)\n");
+    out.push_str("# TYPE rpc_proxy_node_up gauge\n");
+    for (node, _) in nodes {
+        let _ = writeln!(out, "rpc_proxy_node_up{{node=\"{}\"}} {}", escape_label(&node.endpoint), node.is_active as u8);
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_consecutive_failures Failures since the node's last success\n");
+    out.push_str("# TYPE rpc_proxy_node_consecutive_failures gauge\n");
+    for (node, _) in nodes {
+        let _ = writeln!(out, "rpc_proxy_node_consecutive_failures{{node=\"{}\"}} {}",
+                         escape_label(&node.endpoint), node.health.consecutive_failures);
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_health_check_seconds Response time of the node's last health check\n");
+    out.push_str("# TYPE rpc_proxy_node_health_check_seconds gauge\n");
+    for (node, _) in nodes {
+        if let Some(response_time) = node.response_time {
+            let _ = writeln!(out, "rpc_proxy_node_health_check_seconds{{node=\"{}\"}} {}",
+                             escape_label(&node.endpoint), response_time.as_secs_f64());
+        }
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_health_score Weighted share of health check probes the node passed at its last check\n");
+    out.push_str("# TYPE rpc_proxy_node_health_score gauge\n");
+    for (node, _) in nodes {
+        if let Some(score) = node.health_score {
+            let _ = writeln!(out, "rpc_proxy_node_health_score{{node=\"{}\"}} {}", escape_label(&node.endpoint), score);
+        }
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_probe_passed Whether the node passed each health check probe at its last check\n");
+    out.push_str("# TYPE rpc_proxy_node_probe_passed gauge\n");
+    for (node, _) in nodes {
+        for probe in &node.probes {
+            let _ = writeln!(out, "rpc_proxy_node_probe_passed{{node=\"{}\",probe=\"{}\"}} {}",
+                             escape_label(&node.endpoint), escape_label(&probe.name), probe.passed as u8);
+        }
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_latency_ewma_seconds Moving average of upstream attempt latency from live traffic\n");
+    out.push_str("# TYPE rpc_proxy_node_latency_ewma_seconds gauge\n");
+    for (node, _) in nodes {
+        for class in MethodClass::ALL {
+            if let Some(latency_ms) = node.traffic.get(class).latency_ms {
+                let _ = writeln!(out, "rpc_proxy_node_latency_ewma_seconds{{node=\"{}\",class=\"{}\"}} {}",
+                                 escape_label(&node.endpoint), class.as_str(), latency_ms / 1000.0);
+            }
+        }
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_error_rate_ewma Moving average of the share of live requests the node couldn't serve\n");
+    out.push_str("# TYPE rpc_proxy_node_error_rate_ewma gauge\n");
+    for (node, _) in nodes {
+        for class in MethodClass::ALL {
+            let traffic = node.traffic.get(class);
+            if traffic.requests > 0 {
+                let _ = writeln!(out, "rpc_proxy_node_error_rate_ewma{{node=\"{}\",class=\"{}\"}} {}",
+                                 escape_label(&node.endpoint), class.as_str(), traffic.error_rate);
+            }
+        }
+    }
+    
+    out.push_str("# HELP rpc_proxy_node_slot_lag Slots the node trails the cluster tip\n");
+    out.push_str("# TYPE rpc_proxy_node_slot_lag gauge\n");
+    for (node, slot_lag) in nodes {
+        if let Some(slot_lag) = slot_lag {
+            let _ = writeln!(out, "rpc_proxy_node_slot_lag{{node=\"{}\"}} {}", escape_label(&node.endpoint), slot_lag);
+        }
+    }
+    
+    out.push_str("# HELP rpc_proxy_max_slot_lag Slot lag beyond which nodes stop receiving traffic\n");
+    out.push_str("# TYPE rpc_proxy_max_slot_lag gauge\n");
+    let _ = writeln!(out, "rpc_proxy_max_slot_lag {}", max_slot_lag);
+}
+
+/// Render requests and rate limit cost per API key, labelled with the key's name and its own labels
+pub fn render_api_key_usage(out: &mut String, usage: &[ApiKeyUsage]) {
+    let labels = |entry: &ApiKeyUsage| {
+        let mut labels = format!("api_key=\"{}\"", escape_label(&entry.name));
+        for (name, value) in &entry.labels {
+            let name = label_name(name);
+            if name != "api_key" {
+                let _ = write!(labels, ",{}=\"{}\"", name, escape_label(value));
+            }
+        }
+        labels
+    };
+    
+    out.push_str("# HELP rpc_proxy_api_key_requests_total JSON-RPC requests and websocket connections per API key\n");
+    out.push_str("# TYPE rpc_proxy_api_key_requests_total counter\n");
+    for entry in usage {
+        let _ = writeln!(out, "rpc_proxy_api_key_requests_total{{{}}} {}", labels(entry), entry.usage.requests);
+    }
+    
+    out.push_str("# HELP rpc_proxy_api_key_cost_total Rate limit cost charged per API key\n");
+    out.push_str("# TYPE rpc_proxy_api_key_cost_total counter\n");
+    for entry in usage {
+        let _ = writeln!(out, "rpc_proxy_api_key_cost_total{{{}}} {}", labels(entry), entry.usage.cost);
+    }
+}
+
+/// Label names may only contain `[a-zA-Z0-9_]` and must not start with a digit
+fn label_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::KeyUsage;
+    use crate::types::TrafficStats;
+    
+    /// Sample lines as (metric name, series with labels, value), checking each
+    /// one belongs to a family declared with HELP and TYPE
+    fn samples(out: &str) -> Vec<(String, String, f64)> {
+        let mut families: Vec<(&str, &str)> = Vec::new();
+        let mut samples = Vec::new();
+        for line in out.lines() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                assert!(help.contains(' '), "HELP without a description: {}", line);
+                continue;
+            }
+            if let Some(declaration) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = declaration.split_once(' ').unwrap();
+                assert!(out.contains(&format!("# HELP {} ", name)), "{} has no HELP", name);
+                assert!(["counter", "gauge", "histogram"].contains(&kind), "{}", line);
+                families.push((name, kind));
+                continue;
+            }
+            
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            let name = series.split('{').next().unwrap();
+            let family = families
+                .iter()
+                .find(|(family, kind)| {
+                    name == *family
+                        || (*kind == "histogram" && ["_bucket", "_sum", "_count"].iter().any(|suffix| name == format!("{}{}", family, suffix)))
+                })
+                .unwrap_or_else(|| panic!("{} isn't declared", name));
+            assert!(name.starts_with("rpc_proxy_"), "{}", name);
+            assert_eq!(family.0, families.last().unwrap().0, "{} is outside its family's block", name);
+            if series.contains('{') {
+                assert!(series.ends_with('}'), "{}", line);
+            }
+            samples.push((name.to_string(), series.to_string(), value.parse().unwrap()));
+        }
+        samples
+    }
+    
+    fn value(samples: &[(String, String, f64)], series: &str) -> f64 {
+        samples
+            .iter()
+            .find(|(_, sample, _)| sample == series)
+            .unwrap_or_else(|| panic!("no sample {}", series))
+            .2
+    }
+    
+    #[test]
+    fn proxy_metrics_render_as_prometheus_text() {
+        let metrics = Metrics::new();
+        metrics.record_request("getSlot");
+        metrics.record_request("getSlot");
+        metrics.record_request("get\"weird\\method\n");
+        metrics.record_error("getSlot", -32005);
+        metrics.record_response("getBlock", r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32009,"message":"skipped"}}"#);
+        metrics.record_response("getBlock", r#"{"jsonrpc":"2.0","id":1,"result":null}"#);
+        metrics.record_hedge("getBlock", true);
+        metrics.record_hedge("getBlock", false);
+        metrics.observe_upstream("getSlot", "http://10.0.0.1:8899", Duration::from_millis(30), true);
+        metrics.observe_upstream("getSlot", "http://10.0.0.1:8899", Duration::from_millis(700), false);
+        let _queued = metrics.enter_queue();
+        
+        let mut out = String::new();
+        metrics.render(&mut out, 3, 10);
+        let samples = samples(&out);
+        
+        assert_eq!(value(&samples, r#"rpc_proxy_requests_total{method="getSlot"}"#), 2.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_requests_total{method="get\"weird\\method\n"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_errors_total{method="getSlot",code="-32005"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_errors_total{method="getBlock",code="-32009"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_hedged_requests_total{method="getBlock"}"#), 2.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_hedge_wins_total{method="getBlock"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_upstream_failures_total{node="http://10.0.0.1:8899"}"#), 1.0);
+        assert_eq!(value(&samples, "rpc_proxy_queue_depth"), 1.0);
+        assert_eq!(value(&samples, "rpc_proxy_active_requests"), 3.0);
+        assert_eq!(value(&samples, "rpc_proxy_max_concurrent_requests"), 10.0);
+        
+        // buckets are cumulative and end with +Inf at the count
+        let latency = r#"rpc_proxy_upstream_request_duration_seconds_bucket{method="getSlot",node="http://10.0.0.1:8899""#;
+        assert_eq!(value(&samples, &format!("{},le=\"0.025\"}}", latency)), 0.0);
+        assert_eq!(value(&samples, &format!("{},le=\"0.05\"}}", latency)), 1.0);
+        assert_eq!(value(&samples, &format!("{},le=\"1\"}}", latency)), 2.0);
+        assert_eq!(value(&samples, &format!("{},le=\"+Inf\"}}", latency)), 2.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_upstream_request_duration_seconds_count{method="getSlot",node="http://10.0.0.1:8899"}"#), 2.0);
+        let sum = value(&samples, r#"rpc_proxy_upstream_request_duration_seconds_sum{method="getSlot",node="http://10.0.0.1:8899"}"#);
+        assert!((sum - 0.73).abs() < 1e-9);
+        assert_eq!(value(&samples, r#"rpc_proxy_queue_wait_seconds_bucket{le="+Inf"}"#), 0.0);
+        assert_eq!(value(&samples, "rpc_proxy_queue_wait_seconds_count"), 0.0);
+    }
+    
+    #[test]
+    fn made_up_methods_fold_into_other() {
+        let metrics = Metrics::new();
+        for index in 0..MAX_METHOD_LABELS + 10 {
+            metrics.record_request(&format!("method{}", index));
+        }
+        metrics.record_request("method0");
+        
+        let mut out = String::new();
+        metrics.render(&mut out, 0, 1);
+        let samples = samples(&out);
+        assert_eq!(value(&samples, r#"rpc_proxy_requests_total{method="method0"}"#), 2.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_requests_total{method="other"}"#), 10.0);
+        let requests = samples.iter().filter(|(name, _, _)| name == "rpc_proxy_requests_total").count();
+        assert_eq!(requests, MAX_METHOD_LABELS + 1);
+    }
+    
+    #[test]
+    fn node_health_renders_per_node_series() {
+        let mut up = RpcNode::new("http://10.0.0.1:8899".to_string());
+        up.is_active = true;
+        up.response_time = Some(Duration::from_millis(250));
+        up.health_score = Some(0.5);
+        up.traffic.heavy = TrafficStats { latency_ms: Some(120.0), error_rate: 0.25, requests: 8 };
+        let mut down = RpcNode::new("http://[::1]:8899/\"x\"".to_string());
+        down.health.state = NodeState::Quarantined;
+        down.health.consecutive_failures = 4;
+        let tip = NodeSlots { processed: Some(1000), confirmed: Some(998), finalized: None };
+        
+        let mut out = String::new();
+        render_node_health(&mut out, &tip, &[(up, Some(3)), (down, None)], 50);
+        let samples = samples(&out);
+        
+        assert_eq!(value(&samples, r#"rpc_proxy_nodes{state="active"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_nodes{state="inactive"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_node_states{state="quarantined"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_node_versions{version="unknown",state="active"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_cluster_tip_slot{commitment="confirmed"}"#), 998.0);
+        assert!(!out.contains(r#"commitment="finalized""#));
+        assert_eq!(value(&samples, r#"rpc_proxy_node_up{node="http://10.0.0.1:8899"}"#), 1.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_node_up{node="http://[::1]:8899/\"x\""}"#), 0.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_node_consecutive_failures{node="http://[::1]:8899/\"x\""}"#), 4.0);
+        assert_eq!(value(&samples, r#"rpc_proxy_node_health_check_seconds{node="http://10.0.0.1:8899"}"#), 0.25);
+        assert_eq!(value(&samples, r#"rpc_proxy_node_latency_ewma_seconds{node="http://10.0.0.1:8899",class="heavy"}"#), 0.12);
+        assert_eq!(value(&samples, r#"rpc_proxy_node_error_rate_ewma{node="http://10.0.0.1:8899",class="heavy"}"#), 0.25);
+        // classes without traffic are left out
+        assert!(!out.contains(r#"class="light""#));
+        assert_eq!(value(&samples, r#"rpc_proxy_node_slot_lag{node="http://10.0.0.1:8899"}"#), 3.0);
+        assert_eq!(value(&samples, "rpc_proxy_max_slot_lag"), 50.0);
+    }
+    
+    #[test]
+    fn api_key_labels_are_sanitized() {
+        let usage = ApiKeyUsage {
+            name: "acme \"prod\"".to_string(),
+            labels: BTreeMap::from([
+                ("customer-id".to_string(), "a\\b".to_string()),
+                ("9tier".to_string(), "gold".to_string()),
+                ("api_key".to_string(), "spoofed".to_string()),
+            ]),
+            usage: KeyUsage { requests: 12, cost: 30 },
+        };
+        
+        let mut out = String::new();
+        render_api_key_usage(&mut out, &[usage]);
+        let samples = samples(&out);
+        let labels = r#"{api_key="acme \"prod\"",_9tier="gold",customer_id="a\\b"}"#;
+        assert_eq!(value(&samples, &format!("rpc_proxy_api_key_requests_total{}", labels)), 12.0);
+        assert_eq!(value(&samples, &format!("rpc_proxy_api_key_cost_total{}", labels)), 30.0);
+        assert!(!out.contains("spoofed"));
+    }
+}
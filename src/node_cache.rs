@@ -1,91 +1,878 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::Duration;
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
-use tracing::debug;
+use tracing::{debug, info, warn};
 use rand::seq::SliceRandom;
+use rand::Rng;
 
-use crate::types::RpcNode;
+use crate::capability::Needs;
+use crate::fault::Fault;
+use crate::types::{MethodClass, NodeSlots, NodeState, NodeTraffic, RpcNode};
+use crate::version::Version;
+
+/// Nominal slot time, used to project slots observed at different times to a common instant
+const SLOT_DURATION: Duration = Duration::from_millis(400);
+
+/// Default number of slots a node may trail the cluster tip before it stops receiving traffic
+pub const DEFAULT_MAX_SLOT_LAG: u64 = 50;
+
+/// Requests of a class a node must have served before its live latency replaces the health check time
+const MIN_TRAFFIC_SAMPLES: u64 = 5;
+
+/// Floor on the success rate used to scale latency, so a failing node's score stays finite
+const MIN_SUCCESS_RATE: f64 = 0.05;
+
+/// Nodes the top-N random strategy picks from
+const TOP_N: usize = 100;
+
+/// Weight of nodes without a configured one, for weighted round-robin
+pub const DEFAULT_NODE_WEIGHT: u32 = 1;
+
+pub const DEFAULT_QUARANTINE_AFTER_FAILURES: u32 = 3;
+pub const DEFAULT_QUARANTINE_BACKOFF_SECS: u64 = 5;
+pub const DEFAULT_MAX_QUARANTINE_BACKOFF_SECS: u64 = 600;
+
+/// Result of a node's health check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed,
+    /// A probe failed, the node may recover
+    Failed,
+    /// The node belongs to another cluster or runs a version it may not, so it
+    /// must not serve traffic even as the last node left
+    Disqualified,
+}
+
+/// When failing nodes are taken out of traffic and how long until they are re-probed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuarantinePolicy {
+    /// Consecutive forwarding failures after which a node is quarantined
+    pub failure_threshold: u32,
+    /// Backoff of the first quarantine, doubled for each further quarantine in a row
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for QuarantinePolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: DEFAULT_QUARANTINE_AFTER_FAILURES,
+            base_backoff: Duration::from_secs(DEFAULT_QUARANTINE_BACKOFF_SECS),
+            max_backoff: Duration::from_secs(DEFAULT_MAX_QUARANTINE_BACKOFF_SECS),
+        }
+    }
+}
+
+pub const DEFAULT_HEALTH_CHECK_INTERVAL_SECS: u64 = 30;
+pub const DEFAULT_SUSPECT_CHECK_INTERVAL_SECS: u64 = 5;
+
+/// How often nodes are health checked depending on their state. New nodes are
+/// checked right away and quarantined ones once their backoff expires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckSchedule {
+    pub healthy_interval: Duration,
+    pub suspect_interval: Duration,
+}
+
+impl Default for CheckSchedule {
+    fn default() -> Self {
+        Self {
+            healthy_interval: Duration::from_secs(DEFAULT_HEALTH_CHECK_INTERVAL_SECS),
+            suspect_interval: Duration::from_secs(DEFAULT_SUSPECT_CHECK_INTERVAL_SECS),
+        }
+    }
+}
+
+impl CheckSchedule {
+    /// When a node in `state` is checked next, `None` for states not checked on an
+    /// interval. Off by up to a tenth of the interval, so nodes found together
+    /// spread out instead of being checked in bursts.
+    fn next_check(&self, state: NodeState, now: SystemTime) -> Option<SystemTime> {
+        let interval = match state {
+            NodeState::Healthy => self.healthy_interval,
+            NodeState::Suspect => self.suspect_interval,
+            NodeState::Unchecked | NodeState::Quarantined | NodeState::Probing => return None,
+        };
+        Some(now + interval.mul_f64(rand::thread_rng().gen_range(0.9..=1.1)))
+    }
+}
+
+/// Node considered for one request, after exclusions and slot lag filtering
+pub struct Candidate<'a> {
+    pub node: &'a RpcNode,
+    /// Expected cost of the request on this node, see `selection_score`.
+    /// `None` before the node's first health check time is known.
+    pub score: Option<f64>,
+    /// Requests currently forwarded to the node
+    pub outstanding: usize,
+    pub weight: u32,
+}
+
+/// Load-balancing strategy choosing the node for each request
+pub trait NodeSelector: Send + Sync {
+    /// Index of the chosen node in `candidates`, which is never empty
+    fn select(&self, candidates: &[Candidate<'_>]) -> usize;
+}
+
+/// Random node among the `n` with the best score
+pub struct TopNRandom {
+    pub n: usize,
+}
+
+impl NodeSelector for TopNRandom {
+    fn select(&self, candidates: &[Candidate<'_>]) -> usize {
+        let mut rng = rand::thread_rng();
+        let mut timed: Vec<(usize, f64)> = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| Some((index, candidate.score?)))
+            .collect();
+        
+        if timed.is_empty() {
+            debug!("No active nodes with response time data, falling back to any active node");
+            return rng.gen_range(0..candidates.len());
+        }
+        
+        // Sort by score (fastest first) and keep the top N
+        timed.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        timed.truncate(self.n.max(1));
+        debug!("Selecting from top {} fastest nodes", timed.len());
+        timed.choose(&mut rng).map_or(0, |(index, _)| *index)
+    }
+}
+
+/// Expected wait on a node: its score scaled by the requests already queued on it
+fn load_cost(candidate: &Candidate<'_>) -> f64 {
+    candidate.score.unwrap_or(f64::MAX) * (candidate.outstanding + 1) as f64
+}
+
+/// The better of two random nodes by score and outstanding requests, which
+/// spreads load almost as well as checking every node without herding onto one
+pub struct PowerOfTwoChoices;
+
+impl NodeSelector for PowerOfTwoChoices {
+    fn select(&self, candidates: &[Candidate<'_>]) -> usize {
+        if candidates.len() == 1 {
+            return 0;
+        }
+        let picks = rand::seq::index::sample(&mut rand::thread_rng(), candidates.len(), 2);
+        let (a, b) = (picks.index(0), picks.index(1));
+        if load_cost(&candidates[a]) <= load_cost(&candidates[b]) { a } else { b }
+    }
+}
+
+/// Node with the fewest outstanding requests, ties broken by score
+pub struct LeastOutstanding;
+
+impl NodeSelector for LeastOutstanding {
+    fn select(&self, candidates: &[Candidate<'_>]) -> usize {
+        candidates
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                a.outstanding
+                    .cmp(&b.outstanding)
+                    .then_with(|| a.score.unwrap_or(f64::MAX).total_cmp(&b.score.unwrap_or(f64::MAX)))
+            })
+            .map_or(0, |(index, _)| index)
+    }
+}
+
+/// Smooth weighted round-robin over the configured node weights
+#[derive(Default)]
+pub struct WeightedRoundRobin {
+    current: Mutex<HashMap<String, i64>>,
+}
+
+impl NodeSelector for WeightedRoundRobin {
+    fn select(&self, candidates: &[Candidate<'_>]) -> usize {
+        let mut current = self.current.lock().unwrap();
+        // forget nodes that left the pool, so a returning one starts over
+        let listed: HashSet<&str> = candidates.iter().map(|candidate| candidate.node.endpoint.as_str()).collect();
+        current.retain(|endpoint, _| listed.contains(endpoint.as_str()));
+        
+        let total: i64 = candidates.iter().map(|candidate| candidate.weight as i64).sum();
+        
+        let mut chosen = 0;
+        let mut chosen_weight = i64::MIN;
+        for (index, candidate) in candidates.iter().enumerate() {
+            let weight = current.entry(candidate.node.endpoint.clone()).or_insert(0);
+            *weight += candidate.weight as i64;
+            if *weight > chosen_weight {
+                chosen = index;
+                chosen_weight = *weight;
+            }
+        }
+        
+        if let Some(weight) = current.get_mut(&candidates[chosen].node.endpoint) {
+            *weight -= total;
+        }
+        chosen
+    }
+}
+
+/// Built-in load-balancing strategies, as named on the command line and in the config file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Random node among the 100 fastest
+    #[default]
+    TopNRandom,
+    /// Power of two choices on outstanding requests and EWMA latency
+    #[serde(rename = "p2c")]
+    PowerOfTwoChoices,
+    /// Round-robin following the static node weights
+    WeightedRoundRobin,
+    LeastOutstanding,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 4] = [
+        Strategy::TopNRandom,
+        Strategy::PowerOfTwoChoices,
+        Strategy::WeightedRoundRobin,
+        Strategy::LeastOutstanding,
+    ];
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Strategy::TopNRandom => "top-n-random",
+            Strategy::PowerOfTwoChoices => "p2c",
+            Strategy::WeightedRoundRobin => "weighted-round-robin",
+            Strategy::LeastOutstanding => "least-outstanding",
+        }
+    }
+    
+    fn selector(self) -> Box<dyn NodeSelector> {
+        match self {
+            Strategy::TopNRandom => Box::new(TopNRandom { n: TOP_N }),
+            Strategy::PowerOfTwoChoices => Box::new(PowerOfTwoChoices),
+            Strategy::WeightedRoundRobin => Box::new(WeightedRoundRobin::default()),
+            Strategy::LeastOutstanding => Box::new(LeastOutstanding),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        Strategy::ALL
+            .into_iter()
+            .find(|strategy| strategy.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!(
+                "unknown load-balancing strategy '{}', expected top-n-random, p2c, weighted-round-robin or least-outstanding", s
+            ))
+    }
+}
+
+/// Strategy for one method group, given on the command line as `group:strategy`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupStrategy {
+    pub group: MethodClass,
+    pub strategy: Strategy,
+}
+
+impl FromStr for GroupStrategy {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((group, strategy)) = s.split_once(':') else {
+            return Err(anyhow::anyhow!("expected group:strategy, got '{}'", s));
+        };
+        Ok(Self {
+            group: group.parse()?,
+            strategy: strategy.parse()?,
+        })
+    }
+}
+
+/// Static weight of a node, given on the command line as `endpoint=weight`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeWeight {
+    pub endpoint: String,
+    pub weight: u32,
+}
+
+impl FromStr for NodeWeight {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((endpoint, weight)) = s.rsplit_once('=') else {
+            return Err(anyhow::anyhow!("expected endpoint=weight, got '{}'", s));
+        };
+        Ok(Self {
+            endpoint: endpoint.to_string(),
+            weight: weight.parse()?,
+        })
+    }
+}
+
+/// Which strategy picks nodes for each method group
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadBalancing {
+    pub default: Strategy,
+    pub per_group: Vec<GroupStrategy>,
+    pub weights: Vec<NodeWeight>,
+}
+
+impl LoadBalancing {
+    pub fn strategy(&self, class: MethodClass) -> Strategy {
+        self.per_group
+            .iter()
+            .rev()
+            .find(|group| group.group == class)
+            .map_or(self.default, |group| group.strategy)
+    }
+}
+
+/// Selectors built from a `LoadBalancing`, one per method group
+struct Selectors {
+    config: LoadBalancing,
+    weights: HashMap<String, u32>,
+    per_group: HashMap<MethodClass, Box<dyn NodeSelector>>,
+}
+
+impl Selectors {
+    fn new(config: LoadBalancing) -> Self {
+        Self {
+            weights: config.weights.iter().map(|weight| (weight.endpoint.clone(), weight.weight)).collect(),
+            per_group: MethodClass::ALL
+                .into_iter()
+                .map(|class| (class, config.strategy(class).selector()))
+                .collect(),
+            config,
+        }
+    }
+}
+
+/// Live load on one node, kept apart from the nodes so forwarded requests
+/// don't queue behind the write lock of health checks and discovery
+#[derive(Default)]
+struct NodeLoad {
+    /// Requests currently forwarded to the node
+    outstanding: AtomicUsize,
+    traffic: Mutex<NodeTraffic>,
+}
 
 pub struct NodeCache {
     nodes: Arc<RwLock<HashMap<String, RpcNode>>>,
+    max_slot_lag: AtomicU64,
+    quarantine_policy: Mutex<QuarantinePolicy>,
+    check_schedule: Mutex<CheckSchedule>,
+    selectors: std::sync::RwLock<Selectors>,
+    /// Per node, only written when a node is first forwarded to or evicted
+    loads: std::sync::RwLock<HashMap<String, Arc<NodeLoad>>>,
+}
+
+impl Default for NodeCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SLOT_LAG, QuarantinePolicy::default(), CheckSchedule::default(), LoadBalancing::default())
+    }
+}
+
+/// Counts a request as outstanding on its node until dropped
+pub struct OutstandingRequest {
+    load: Arc<NodeLoad>,
+}
+
+impl Drop for OutstandingRequest {
+    fn drop(&mut self) {
+        self.load.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Slot a node is expected to be at now, given what it reported at its last health check
+fn projected_slot(node: &RpcNode, commitment: &str, now: std::time::SystemTime) -> Option<u64> {
+    let slot = node.slots.get(commitment)?;
+    let elapsed = now.duration_since(node.last_seen).unwrap_or_default();
+    Some(slot + (elapsed.as_millis() / SLOT_DURATION.as_millis()) as u64)
+}
+
+/// Highest projected slot per commitment across the given nodes
+fn cluster_tip<'a>(nodes: impl Iterator<Item = &'a RpcNode> + Clone, now: std::time::SystemTime) -> NodeSlots {
+    let tip = |commitment: &str| nodes.clone().filter_map(|node| projected_slot(node, commitment, now)).max();
+    NodeSlots {
+        processed: tip("processed"),
+        confirmed: tip("confirmed"),
+        finalized: tip("finalized"),
+    }
+}
+
+/// Worst lag behind the cluster tip across commitments, `None` if the node reported no slots
+fn slot_lag(node: &RpcNode, tip: &NodeSlots, now: std::time::SystemTime) -> Option<u64> {
+    NodeSlots::COMMITMENTS
+        .iter()
+        .filter_map(|commitment| {
+            let slot = projected_slot(node, commitment, now)?;
+            Some(tip.get(commitment)?.saturating_sub(slot))
+        })
+        .max()
+}
+
+/// Expected cost of sending a `class` request to `node`: its live latency for
+/// the class once it has served enough of them, the health check time before
+/// that, scaled up by the share of requests it couldn't serve
+fn selection_score(node: &RpcNode, traffic: &NodeTraffic, class: MethodClass) -> Option<f64> {
+    let traffic = traffic.get(class);
+    let latency_ms = match traffic.latency_ms {
+        Some(latency_ms) if traffic.requests >= MIN_TRAFFIC_SAMPLES => latency_ms,
+        _ => node.response_time?.as_secs_f64() * 1000.0,
+    };
+    Some(latency_ms / (1.0 - traffic.error_rate).max(MIN_SUCCESS_RATE))
+}
+
+/// Rendezvous (highest random weight) hash of `key` on a node. Each key goes
+/// to the node ranking highest for it, so nodes joining or leaving only move
+/// the keys they win or held, and a retry lands on the key's runner-up.
+fn rendezvous_score(key: &str, endpoint: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (key, endpoint).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Take `node` out of traffic until its backoff expires
+fn quarantine(node: &mut RpcNode, policy: &QuarantinePolicy, now: SystemTime) {
+    let health = &mut node.health;
+    
+    // A node that stayed out of quarantine for longer than the longest backoff starts over
+    let stable = health
+        .recovered_at
+        .is_some_and(|recovered_at| now.duration_since(recovered_at).unwrap_or_default() > policy.max_backoff);
+    if stable && health.state != NodeState::Probing {
+        health.quarantines = 0;
+    }
+    
+    let backoff = policy.base_backoff.saturating_mul(1 << health.quarantines.min(16)).min(policy.max_backoff);
+    health.quarantines += 1;
+    health.state = NodeState::Quarantined;
+    health.quarantined_until = Some(now + backoff);
+    node.is_active = false;
+    
+    warn!("🚧 Quarantined node {} for {:?} ({} consecutive failures, quarantine #{})", 
+          node.endpoint, backoff, health.consecutive_failures, health.quarantines);
+}
+
+/// Quarantine the node at `endpoint` after failures, unless it is the last active
+/// node: a struggling node serves more than an empty pool, so it stays in as
+/// suspect. Returns whether the node was quarantined.
+fn quarantine_unless_last(
+    nodes: &mut HashMap<String, RpcNode>,
+    endpoint: &str,
+    policy: &QuarantinePolicy,
+    now: SystemTime,
+) -> bool {
+    let others_active = nodes.values().any(|other| other.is_active && other.endpoint != endpoint);
+    let Some(node) = nodes.get_mut(endpoint) else {
+        return false;
+    };
+    if node.is_active && !others_active {
+        warn!("⚠️  Node {} keeps failing but is the last active node, keeping it as suspect", endpoint);
+        node.health.state = NodeState::Suspect;
+        return false;
+    }
+    quarantine(node, policy, now);
+    true
+}
+
+/// Return `node` to traffic after a passed health check
+fn recover(node: &mut RpcNode, now: SystemTime) {
+    let health = &mut node.health;
+    if matches!(health.state, NodeState::Quarantined | NodeState::Probing) {
+        info!("💚 Node {} recovered after {} quarantines", node.endpoint, health.quarantines);
+        health.recovered_at = Some(now);
+    }
+    health.state = NodeState::Healthy;
+    health.consecutive_failures = 0;
+    health.quarantined_until = None;
+    node.is_active = true;
 }
 
 impl NodeCache {
-    pub fn new() -> Self {
+    pub fn new(
+        max_slot_lag: u64,
+        quarantine_policy: QuarantinePolicy,
+        check_schedule: CheckSchedule,
+        load_balancing: LoadBalancing,
+    ) -> Self {
         Self {
             nodes: Arc::new(RwLock::new(HashMap::new())),
+            max_slot_lag: AtomicU64::new(max_slot_lag),
+            quarantine_policy: Mutex::new(quarantine_policy),
+            check_schedule: Mutex::new(check_schedule),
+            selectors: std::sync::RwLock::new(Selectors::new(load_balancing)),
+            loads: std::sync::RwLock::new(HashMap::new()),
+        }
+    }
+    
+    /// Load on `endpoint`, created on first use
+    fn load(&self, endpoint: &str) -> Arc<NodeLoad> {
+        if let Some(load) = self.loads.read().unwrap().get(endpoint) {
+            return Arc::clone(load);
         }
+        Arc::clone(self.loads.write().unwrap().entry(endpoint.to_string()).or_default())
     }
     
+    /// Live traffic stats of `endpoint`
+    pub fn traffic(&self, endpoint: &str) -> NodeTraffic {
+        self.loads
+            .read()
+            .unwrap()
+            .get(endpoint)
+            .map(|load| *load.traffic.lock().unwrap())
+            .unwrap_or_default()
+    }
+    
+    /// Record a health check of `node` and schedule its next one. A passed check
+    /// makes the node healthy, a failed one quarantines it with the next backoff
+    /// step, unless it is the last node receiving traffic: that one stays in as
+    /// suspect so the pool never goes empty. A disqualified node is always
+    /// quarantined, even if that empties the pool. `response_time` is only given
+    /// for a passed check, a failed one keeps the last measured time.
     pub async fn update_node_status(
         &self,
         mut node: RpcNode,
-        is_active: bool,
-        response_time: Duration,
+        outcome: CheckOutcome,
+        response_time: Option<Duration>,
     ) {
-        node.is_active = is_active;
-        node.response_time = Some(response_time);
-        node.last_seen = std::time::SystemTime::now();
+        let now = SystemTime::now();
+        node.last_seen = now;
         
         let endpoint = node.endpoint.clone();
         let mut nodes = self.nodes.write().await;
+        node.is_active = false;
+        if let Some(existing) = nodes.get(&endpoint) {
+            node.health = existing.health.clone();
+            // discovery may have refreshed what gossip advertises while the check ran
+            node.pubsub = existing.pubsub.clone();
+            node.shred_version = existing.shred_version;
+            node.is_active = existing.is_active;
+            node.response_time = existing.response_time;
+        }
+        if response_time.is_some() {
+            node.response_time = response_time;
+        }
+        node.health.checking = false;
+        
+        match outcome {
+            CheckOutcome::Passed => recover(&mut node, now),
+            CheckOutcome::Failed => node.health.consecutive_failures += 1,
+            CheckOutcome::Disqualified => {
+                node.health.consecutive_failures += 1;
+                quarantine(&mut node, &self.quarantine_policy(), now);
+            }
+        }
         nodes.insert(endpoint.clone(), node);
+        if outcome == CheckOutcome::Failed {
+            quarantine_unless_last(&mut nodes, &endpoint, &self.quarantine_policy(), now);
+        }
+        if let Some(node) = nodes.get_mut(&endpoint) {
+            node.health.next_check_at = self.check_schedule().next_check(node.health.state, now);
+        }
         
-        debug!("Updated node status: {} -> {}, response time: {:?}", endpoint, is_active, response_time);
+        debug!("Updated node status: {} -> {:?}, response time: {:?}", endpoint, outcome, response_time);
     }
     
-    pub async fn get_active_nodes(&self) -> Vec<RpcNode> {
-        let nodes = self.nodes.read().await;
-        nodes
-            .values()
-            .filter(|node| node.is_active)
-            .cloned()
+    /// Record one forwarded attempt on a node: its latency and whether the node
+    /// served it, `fault` being `None` on success. Node faults make the node
+    /// suspect and quarantine it once they reach the policy's threshold, unless
+    /// it is the last active node. A served request clears them.
+    pub async fn record_request(&self, endpoint: &str, class: MethodClass, latency: Duration, fault: Option<Fault>) {
+        // a request the node refused on its merits was still served
+        let fault = fault.filter(|fault| fault.is_retryable());
+        self.load(endpoint).traffic.lock().unwrap().get_mut(class).record(latency, fault.is_some());
+        
+        // most requests succeed on a healthy node and change nothing, which the read lock can tell
+        let changes_health = match fault {
+            None => self.nodes.read().await.get(endpoint).is_some_and(|node| {
+                node.is_active && (node.health.state == NodeState::Suspect || node.health.consecutive_failures > 0)
+            }),
+            Some(Fault::Node) => true,
+            Some(_) => false,
+        };
+        if !changes_health {
+            return;
+        }
+        
+        let policy = self.quarantine_policy();
+        let mut nodes = self.nodes.write().await;
+        let Some(node) = nodes.get_mut(endpoint) else {
+            return;
+        };
+        
+        // requests started before the node was quarantined don't count against it again
+        if !node.is_active {
+            return;
+        }
+        
+        match fault {
+            None => {
+                if node.health.state == NodeState::Suspect {
+                    debug!("💚 Node {} is healthy again", endpoint);
+                }
+                node.health.state = NodeState::Healthy;
+                node.health.consecutive_failures = 0;
+            }
+            Some(Fault::Node) => {
+                node.health.consecutive_failures += 1;
+                if node.health.consecutive_failures >= policy.failure_threshold
+                    && quarantine_unless_last(&mut nodes, endpoint, &policy, SystemTime::now())
+                {
+                    return;
+                }
+                let Some(node) = nodes.get_mut(endpoint) else {
+                    return;
+                };
+                // check a suspect node sooner than a healthy one
+                let suspect_check = self.check_schedule().next_check(NodeState::Suspect, SystemTime::now());
+                node.health.next_check_at = node.health.next_check_at.min(suspect_check);
+                node.health.state = NodeState::Suspect;
+                debug!("⚠️  Node {} is suspect ({} consecutive failures)", endpoint, node.health.consecutive_failures);
+            }
+            // the node is fine, it just couldn't answer this one
+            Some(_) => {}
+        }
+    }
+    
+    /// Mark up to `limit` nodes due for a health check as being checked and return
+    /// them, longest overdue first with new nodes ahead of all others. Quarantined
+    /// nodes are due once their backoff expires and move to probing.
+    pub async fn start_due_checks(&self, limit: usize) -> Vec<RpcNode> {
+        let now = SystemTime::now();
+        let mut nodes = self.nodes.write().await;
+        let mut due: Vec<(SystemTime, &mut RpcNode)> = nodes
+            .values_mut()
+            .filter(|node| !node.health.checking)
+            .filter_map(|node| {
+                let due_at = match node.health.state {
+                    NodeState::Unchecked => SystemTime::UNIX_EPOCH,
+                    NodeState::Healthy | NodeState::Suspect => {
+                        node.health.next_check_at.unwrap_or(SystemTime::UNIX_EPOCH)
+                    }
+                    NodeState::Quarantined => node.health.quarantined_until?,
+                    NodeState::Probing => return None,
+                };
+                (due_at <= now).then_some((due_at, node))
+            })
+            .collect();
+        due.sort_by_key(|(due_at, _)| *due_at);
+        
+        due.into_iter()
+            .take(limit)
+            .map(|(_, node)| {
+                node.health.checking = true;
+                if node.health.state == NodeState::Quarantined {
+                    node.health.state = NodeState::Probing;
+                }
+                node.clone()
+            })
             .collect()
     }
     
-    /// Get a random node from the top 100 fastest active nodes
-    pub async fn get_random_fast_node(&self) -> Option<RpcNode> {
+    /// Add discovered nodes that aren't known yet, unchecked so they are checked
+    /// right away, and refresh what gossip advertises for known ones. Returns how
+    /// many nodes were new.
+    pub async fn add_discovered(&self, discovered: Vec<RpcNode>) -> usize {
+        let mut nodes = self.nodes.write().await;
+        let mut added = 0;
+        for mut node in discovered {
+            match nodes.get_mut(&node.endpoint) {
+                Some(existing) => {
+                    existing.missed_discoveries = 0;
+                    existing.pubsub = node.pubsub.or_else(|| existing.pubsub.take());
+                    existing.shred_version = node.shred_version.or(existing.shred_version);
+                    // getVersion answers take precedence over gossip
+                    if existing.version.is_none() {
+                        existing.version = node.version;
+                        existing.feature_set = node.feature_set;
+                    }
+                }
+                None => {
+                    node.health.state = NodeState::Unchecked;
+                    nodes.insert(node.endpoint.clone(), node);
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+    
+    /// Count a discovery round against the nodes it didn't list, those not in
+    /// `listed`, and drop the ones missing for `max_missed` rounds in a row. The
+    /// last active node is kept. Returns the endpoints dropped.
+    pub async fn evict_missing(&self, listed: &HashSet<&str>, max_missed: u32) -> Vec<String> {
+        let mut nodes = self.nodes.write().await;
+        for node in nodes.values_mut().filter(|node| !listed.contains(node.endpoint.as_str())) {
+            node.missed_discoveries += 1;
+        }
+        
+        let mut evicted = Vec::new();
+        let mut active = nodes.values().filter(|node| node.is_active).count();
+        nodes.retain(|endpoint, node| {
+            if node.missed_discoveries < max_missed || (node.is_active && active == 1) {
+                return true;
+            }
+            if node.is_active {
+                active -= 1;
+            }
+            evicted.push(endpoint.clone());
+            false
+        });
+        
+        let mut loads = self.loads.write().unwrap();
+        for endpoint in &evicted {
+            loads.remove(endpoint);
+        }
+        evicted
+    }
+    
+    /// Pick a node for a `class` request with the group's load-balancing strategy,
+    /// skipping the `exclude` endpoints and nodes lagging the cluster tip by more
+    /// than `max_slot_lag` slots. Speed is judged by live traffic where there is
+    /// enough of it and by health checks otherwise, see `selection_score`.
+    /// Only nodes running a version the request's `needs` allow are considered, and
+    /// of those the ones whose capabilities meet them, or all of them when none do.
+    /// Requests with an `affinity_key` go to the node it hashes to instead.
+    pub async fn select_node_excluding(
+        &self,
+        exclude: &[String],
+        class: MethodClass,
+        needs: &Needs,
+        affinity_key: Option<&str>,
+    ) -> Option<RpcNode> {
         let nodes = self.nodes.read().await;
+        let now = std::time::SystemTime::now();
+        let tip = cluster_tip(nodes.values().filter(|node| node.is_active), now);
         
-        // Filter active nodes with response time data
-        let mut active_nodes_with_timing: Vec<RpcNode> = nodes
+        // Nodes that never reported a slot are kept, a failed getSlot alone shouldn't sideline them
+        let eligible: Vec<&RpcNode> = nodes
             .values()
-            .filter(|node| node.is_active && node.response_time.is_some())
-            .cloned()
+            .filter(|node| node.is_active && !exclude.contains(&node.endpoint))
+            .filter(|node| needs.version_allows(node))
+            .filter(|node| slot_lag(node, &tip, now).is_none_or(|lag| lag <= self.max_slot_lag()))
             .collect();
         
-        if active_nodes_with_timing.is_empty() {
-            debug!("No active nodes with response time data, falling back to any active node");
-            // Drop the read lock before calling get_active_nodes
-            drop(nodes);
-            
-            // Use the get_active_nodes method as fallback
-            let fallback_nodes = self.get_active_nodes().await;
-            
-            if fallback_nodes.is_empty() {
-                return None;
-            }
-            
-            let mut rng = rand::thread_rng();
-            return fallback_nodes.choose(&mut rng).cloned();
+        if eligible.is_empty() {
+            return None;
         }
         
-        // Sort by response time (fastest first)
-        active_nodes_with_timing.sort_by(|a, b| {
-            let time_a = a.response_time.unwrap_or(Duration::from_secs(999));
-            let time_b = b.response_time.unwrap_or(Duration::from_secs(999));
-            time_a.cmp(&time_b)
-        });
+        // Capabilities are as of each node's last probe, one that seemed to lack them may still answer
+        let capable: Vec<&RpcNode> = eligible.iter().copied().filter(|node| needs.capabilities_met_by(node)).collect();
+        let eligible = if capable.is_empty() {
+            debug!("No node known to meet {:?}, trying any of {} nodes", needs, eligible.len());
+            eligible
+        } else {
+            capable
+        };
         
-        // Take top 100 fastest nodes (or all if less than 100)
-        let top_nodes: Vec<RpcNode> = active_nodes_with_timing.into_iter().take(100).collect();
+        if let Some(key) = affinity_key {
+            return eligible
+                .into_iter()
+                .max_by_key(|node| rendezvous_score(key, &node.endpoint))
+                .cloned();
+        }
         
-        debug!("Selecting from top {} fastest nodes", top_nodes.len());
+        let selectors = self.selectors.read().unwrap();
+        let loads = self.loads.read().unwrap();
+        let candidates: Vec<Candidate<'_>> = eligible
+            .into_iter()
+            .map(|node| {
+                let load = loads.get(&node.endpoint);
+                let traffic = load.map(|load| *load.traffic.lock().unwrap()).unwrap_or_default();
+                Candidate {
+                    node,
+                    score: selection_score(node, &traffic, class),
+                    outstanding: load.map_or(0, |load| load.outstanding.load(Ordering::Relaxed)),
+                    weight: selectors.weights.get(&node.endpoint).copied().unwrap_or(DEFAULT_NODE_WEIGHT),
+                }
+            })
+            .collect();
+        drop(loads);
         
-        // Randomly select from top nodes
-        let mut rng = rand::thread_rng();
-        top_nodes.choose(&mut rng).cloned()
+        let chosen = selectors.per_group[&class].select(&candidates);
+        candidates.get(chosen).map(|candidate| candidate.node.clone())
+    }
+    
+    /// Count a request as outstanding on `endpoint` for as long as the returned guard lives
+    pub fn track_request(&self, endpoint: &str) -> OutstandingRequest {
+        let load = self.load(endpoint);
+        load.outstanding.fetch_add(1, Ordering::Relaxed);
+        OutstandingRequest { load }
+    }
+    
+    /// Requests currently forwarded to `endpoint`
+    pub fn outstanding_requests(&self, endpoint: &str) -> usize {
+        self.loads
+            .read()
+            .unwrap()
+            .get(endpoint)
+            .map_or(0, |load| load.outstanding.load(Ordering::Relaxed))
+    }
+    
+    pub fn load_balancing(&self) -> LoadBalancing {
+        self.selectors.read().unwrap().config.clone()
+    }
+    
+    /// Switch strategies, keeping the current selectors (and their round-robin
+    /// positions) when nothing changed
+    pub fn set_load_balancing(&self, load_balancing: LoadBalancing) {
+        let mut selectors = self.selectors.write().unwrap();
+        if selectors.config != load_balancing {
+            *selectors = Selectors::new(load_balancing);
+        }
+    }
+    
+    /// Cluster tip and each node, with its live traffic stats, and its slot lag behind it
+    pub async fn get_slot_lag_stats(&self) -> (NodeSlots, Vec<(RpcNode, Option<u64>)>) {
+        let nodes = self.nodes.read().await;
+        let now = std::time::SystemTime::now();
+        let tip = cluster_tip(nodes.values().filter(|node| node.is_active), now);
+        
+        let lags = nodes
+            .values()
+            .map(|node| {
+                let mut node = node.clone();
+                node.traffic = self.traffic(&node.endpoint);
+                let lag = slot_lag(&node, &tip, now);
+                (node, lag)
+            })
+            .collect();
+        
+        (tip, lags)
+    }
+    
+    pub fn max_slot_lag(&self) -> u64 {
+        self.max_slot_lag.load(Ordering::Relaxed)
+    }
+    
+    pub fn set_max_slot_lag(&self, max_slot_lag: u64) {
+        self.max_slot_lag.store(max_slot_lag, Ordering::Relaxed);
+    }
+    
+    pub fn quarantine_policy(&self) -> QuarantinePolicy {
+        *self.quarantine_policy.lock().unwrap()
+    }
+    
+    pub fn set_quarantine_policy(&self, policy: QuarantinePolicy) {
+        *self.quarantine_policy.lock().unwrap() = policy;
+    }
+    
+    pub fn check_schedule(&self) -> CheckSchedule {
+        *self.check_schedule.lock().unwrap()
+    }
+    
+    /// Takes effect for each node at its next check
+    pub fn set_check_schedule(&self, schedule: CheckSchedule) {
+        *self.check_schedule.lock().unwrap() = schedule;
     }
     
     /// Get statistics about node performance
@@ -118,11 +905,351 @@ impl NodeCache {
         (total, active)
     }
     
-    /// Remove a node from the cache (typically due to RPC failures or timeouts)
-    pub async fn remove_node(&self, endpoint: &str) {
-        let mut nodes = self.nodes.write().await;
-        if nodes.remove(endpoint).is_some() {
-            debug!("🗑️  Removed failed node from cache: {}", endpoint);
+    /// Average health score of the nodes that have been health checked
+    pub async fn average_health_score(&self) -> Option<f64> {
+        let nodes = self.nodes.read().await;
+        let scores: Vec<f64> = nodes.values().filter_map(|node| node.health_score).collect();
+        if scores.is_empty() {
+            return None;
+        }
+        Some(scores.iter().sum::<f64>() / scores.len() as f64)
+    }
+    
+    /// Total and active nodes on each software version, newest first and unknown versions last
+    pub async fn get_version_counts(&self) -> Vec<(Option<Version>, usize, usize)> {
+        let nodes = self.nodes.read().await;
+        let mut counts: HashMap<Option<Version>, (usize, usize)> = HashMap::new();
+        for node in nodes.values() {
+            let (total, active) = counts.entry(node.version).or_default();
+            *total += 1;
+            *active += node.is_active as usize;
+        }
+        
+        let mut counts: Vec<(Option<Version>, usize, usize)> = counts
+            .into_iter()
+            .map(|(version, (total, active))| (version, total, active))
+            .collect();
+        // `None` sorts first, so reversing puts it last
+        counts.sort_by_key(|(version, _, _)| std::cmp::Reverse(*version));
+        counts
+    }
+    
+    /// Number of nodes in each state
+    pub async fn get_state_counts(&self) -> Vec<(NodeState, usize)> {
+        let nodes = self.nodes.read().await;
+        NodeState::ALL
+            .iter()
+            .map(|state| (*state, nodes.values().filter(|node| node.health.state == *state).count()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::VersionRange;
+    
+    fn endpoints(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("http://10.0.0.{}:8899", i)).collect()
+    }
+    
+    fn winner<'a>(key: &str, endpoints: &'a [String]) -> &'a str {
+        endpoints.iter().max_by_key(|endpoint| rendezvous_score(key, endpoint)).unwrap()
+    }
+    
+    async fn cache_with(nodes: Vec<RpcNode>) -> NodeCache {
+        let cache = NodeCache::default();
+        for node in nodes {
+            cache.update_node_status(node, CheckOutcome::Passed, Some(Duration::from_millis(10))).await;
+        }
+        cache
+    }
+    
+    fn node(endpoint: &str, program_id_index: bool) -> RpcNode {
+        let mut node = RpcNode::new(endpoint.to_string());
+        node.capabilities.program_id_index = program_id_index;
+        node
+    }
+    
+    #[tokio::test]
+    async fn capable_nodes_are_preferred() {
+        let cache = cache_with(vec![node("http://a", false), node("http://b", true)]).await;
+        let needs = Needs { program_id_index: true, ..Needs::default() };
+        for _ in 0..20 {
+            let chosen = cache.select_node_excluding(&[], MethodClass::Heavy, &needs, None).await.unwrap();
+            assert_eq!(chosen.endpoint, "http://b");
         }
     }
-} 
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn any_node_serves_when_none_is_known_capable() {
+        let cache = cache_with(vec![node("http://a", false), node("http://b", false)]).await;
+        let needs = Needs { program_id_index: true, ..Needs::default() };
+        assert!(cache.select_node_excluding(&[], MethodClass::Heavy, &needs, None).await.is_some());
+        
+        // Version requirements are not relaxed
+        let needs = Needs { version: VersionRange { min: Some("9.0.0".parse().unwrap()), max: None }, ..needs };
+        assert!(cache.select_node_excluding(&[], MethodClass::Heavy, &needs, None).await.is_none());
+    }
+    
+    fn is_active(nodes: &[RpcNode], endpoint: &str) -> bool {
+        nodes.iter().any(|node| node.endpoint == endpoint && node.is_active)
+    }
+    
+    #[tokio::test]
+    async fn failing_requests_quarantine_all_but_the_last_active_node() {
+        let cache = cache_with(vec![node("http://a", false), node("http://b", false)]).await;
+        let threshold = cache.quarantine_policy().failure_threshold;
+        
+        for endpoint in ["http://a", "http://b"] {
+            for _ in 0..threshold {
+                cache.record_request(endpoint, MethodClass::Light, Duration::from_millis(5), Some(Fault::Node)).await;
+            }
+        }
+        
+        let nodes: Vec<RpcNode> = cache.nodes.read().await.values().cloned().collect();
+        assert!(!is_active(&nodes, "http://a"));
+        assert!(is_active(&nodes, "http://b"));
+        let b = nodes.iter().find(|node| node.endpoint == "http://b").unwrap();
+        assert_eq!(b.health.state, NodeState::Suspect);
+    }
+    
+    #[tokio::test]
+    async fn failed_health_checks_keep_the_last_active_node() {
+        let cache = cache_with(vec![node("http://a", false), node("http://b", false)]).await;
+        
+        cache.update_node_status(node("http://a", false), CheckOutcome::Failed, None).await;
+        cache.update_node_status(node("http://b", false), CheckOutcome::Failed, None).await;
+        
+        let nodes: Vec<RpcNode> = cache.nodes.read().await.values().cloned().collect();
+        assert!(!is_active(&nodes, "http://a"));
+        assert!(is_active(&nodes, "http://b"));
+    }
+    
+    #[tokio::test]
+    async fn failed_health_checks_keep_the_measured_response_time() {
+        let cache = cache_with(vec![node("http://a", false)]).await;
+        
+        cache.update_node_status(node("http://a", false), CheckOutcome::Failed, None).await;
+        
+        let nodes: Vec<RpcNode> = cache.nodes.read().await.values().cloned().collect();
+        assert!(is_active(&nodes, "http://a"));
+        assert_eq!(nodes[0].response_time, Some(Duration::from_millis(10)));
+    }
+    
+    #[tokio::test]
+    async fn disqualified_nodes_leave_even_as_the_last_active_node() {
+        let cache = cache_with(vec![node("http://a", false)]).await;
+        
+        cache.update_node_status(node("http://a", false), CheckOutcome::Disqualified, None).await;
+        
+        let nodes: Vec<RpcNode> = cache.nodes.read().await.values().cloned().collect();
+        assert!(!is_active(&nodes, "http://a"));
+        assert_eq!(nodes[0].health.state, NodeState::Quarantined);
+    }
+    
+    #[tokio::test]
+    async fn nodes_missing_from_discovery_are_evicted_after_max_rounds() {
+        let cache = cache_with(vec![node("http://a", false), node("http://b", false)]).await;
+        let listed: HashSet<&str> = ["http://a"].into_iter().collect();
+        
+        assert!(cache.evict_missing(&listed, 2).await.is_empty());
+        assert_eq!(cache.evict_missing(&listed, 2).await, vec!["http://b".to_string()]);
+        
+        let nodes: Vec<RpcNode> = cache.nodes.read().await.values().cloned().collect();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].missed_discoveries, 0);
+    }
+    
+    #[tokio::test]
+    async fn listing_a_node_again_resets_its_missed_rounds() {
+        let cache = cache_with(vec![node("http://a", false), node("http://b", false)]).await;
+        let listed: HashSet<&str> = ["http://a"].into_iter().collect();
+        
+        cache.evict_missing(&listed, 2).await;
+        cache.add_discovered(vec![node("http://b", false)]).await;
+        
+        assert!(cache.evict_missing(&listed, 2).await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn the_last_active_node_is_never_evicted() {
+        let cache = cache_with(vec![node("http://a", false), node("http://b", false)]).await;
+        cache.update_node_status(node("http://a", false), CheckOutcome::Disqualified, None).await;
+        
+        let evicted = cache.evict_missing(&HashSet::new(), 1).await;
+        
+        assert_eq!(evicted, vec!["http://a".to_string()]);
+        let nodes: Vec<RpcNode> = cache.nodes.read().await.values().cloned().collect();
+        assert!(is_active(&nodes, "http://b"));
+    }
+    
+    #[test]
+    fn rendezvous_spreads_keys_across_nodes() {
+        let endpoints = endpoints(4);
+        let mut wins: HashMap<&str, usize> = HashMap::new();
+        for i in 0..4000 {
+            *wins.entry(winner(&format!("key-{}", i), &endpoints)).or_default() += 1;
+        }
+        assert_eq!(wins.len(), 4);
+        assert!(wins.values().all(|count| (700..1300).contains(count)), "{:?}", wins);
+    }
+    
+    #[test]
+    fn rendezvous_only_moves_keys_of_a_departed_node() {
+        let endpoints = endpoints(5);
+        let remaining: Vec<String> = endpoints[1..].to_vec();
+        for i in 0..1000 {
+            let key = format!("key-{}", i);
+            let before = winner(&key, &endpoints);
+            let after = winner(&key, &remaining);
+            if before != endpoints[0] {
+                assert_eq!(before, after);
+            }
+        }
+    }
+    
+    async fn score(cache: &NodeCache, endpoint: &str, class: MethodClass) -> f64 {
+        let node = cache.nodes.read().await[endpoint].clone();
+        selection_score(&node, &cache.traffic(endpoint), class).unwrap()
+    }
+    
+    async fn record(cache: &NodeCache, count: usize, latency_ms: u64, fault: Option<Fault>) {
+        for _ in 0..count {
+            cache.record_request("http://a", MethodClass::Heavy, Duration::from_millis(latency_ms), fault).await;
+        }
+    }
+    
+    #[tokio::test]
+    async fn live_latency_replaces_the_health_check_time_and_decays() {
+        let cache = cache_with(vec![node("http://a", false)]).await;
+        assert_eq!(score(&cache, "http://a", MethodClass::Heavy).await, 10.0);
+        
+        // too few requests to go by yet
+        record(&cache, MIN_TRAFFIC_SAMPLES as usize - 1, 100, None).await;
+        assert_eq!(score(&cache, "http://a", MethodClass::Heavy).await, 10.0);
+        record(&cache, 1, 100, None).await;
+        assert_eq!(score(&cache, "http://a", MethodClass::Heavy).await, 100.0);
+        // other classes are tracked apart
+        assert_eq!(score(&cache, "http://a", MethodClass::Light).await, 10.0);
+        
+        // each request moves the average a tenth of the way
+        record(&cache, 1, 20, None).await;
+        assert!((score(&cache, "http://a", MethodClass::Heavy).await - 92.0).abs() < 1e-9);
+        record(&cache, 50, 20, None).await;
+        let settled = score(&cache, "http://a", MethodClass::Heavy).await;
+        assert!(settled > 20.0 && settled < 21.0, "{}", settled);
+    }
+    
+    #[tokio::test]
+    async fn failed_requests_raise_the_score() {
+        let cache = cache_with(vec![node("http://a", false)]).await;
+        record(&cache, 10, 50, None).await;
+        assert_eq!(score(&cache, "http://a", MethodClass::Heavy).await, 50.0);
+        
+        // a tenth of requests failing costs a tenth of the success rate
+        record(&cache, 1, 50, Some(Fault::Unavailable)).await;
+        assert!((score(&cache, "http://a", MethodClass::Heavy).await - 50.0 / 0.9).abs() < 1e-9);
+        
+        // refused requests were still served
+        let before = cache.traffic("http://a").heavy.error_rate;
+        record(&cache, 1, 50, Some(Fault::Request)).await;
+        assert!(cache.traffic("http://a").heavy.error_rate < before);
+        
+        // a node failing everything stays finite
+        record(&cache, 200, 50, Some(Fault::Unavailable)).await;
+        assert_eq!(score(&cache, "http://a", MethodClass::Heavy).await, 50.0 / MIN_SUCCESS_RATE);
+    }
+    
+    #[tokio::test]
+    async fn served_requests_leave_the_node_map_unlocked() {
+        let cache = cache_with(vec![node("http://a", false)]).await;
+        let _reader = cache.nodes.read().await;
+        let recorded = tokio::time::timeout(Duration::from_secs(1), record(&cache, 3, 5, None));
+        assert!(recorded.await.is_ok(), "a served request waited for the node write lock");
+        assert_eq!(cache.traffic("http://a").heavy.requests, 3);
+        
+        let request = cache.track_request("http://a");
+        assert_eq!(cache.outstanding_requests("http://a"), 1);
+        drop(request);
+        assert_eq!(cache.outstanding_requests("http://a"), 0);
+    }
+    
+    /// Candidates scored 10, 20, 30... in order
+    fn candidates<'a>(nodes: &'a [RpcNode], outstanding: &[usize], weights: &[u32]) -> Vec<Candidate<'a>> {
+        nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| Candidate {
+                node,
+                score: Some(10.0 * (index + 1) as f64),
+                outstanding: outstanding[index],
+                weight: weights[index],
+            })
+            .collect()
+    }
+    
+    fn pick_counts(selector: &dyn NodeSelector, candidates: &[Candidate<'_>], picks: usize) -> Vec<usize> {
+        let mut counts = vec![0; candidates.len()];
+        for _ in 0..picks {
+            counts[selector.select(candidates)] += 1;
+        }
+        counts
+    }
+    
+    #[test]
+    fn p2c_favours_cheaper_nodes_without_starving_the_others() {
+        let nodes: Vec<RpcNode> = endpoints(3).into_iter().map(RpcNode::new).collect();
+        let idle = candidates(&nodes, &[0, 0, 0], &[1, 1, 1]);
+        
+        // the fastest node wins every pair it's drawn into, the slowest none
+        let counts = pick_counts(&PowerOfTwoChoices, &idle, 3000);
+        assert!(counts[0] > 1800 && counts[0] < 2200, "{:?}", counts);
+        assert!(counts[1] > 800 && counts[1] < 1200, "{:?}", counts);
+        assert_eq!(counts[2], 0);
+        
+        // queued requests outweigh a faster score
+        let busy = candidates(&nodes, &[5, 0, 0], &[1, 1, 1]);
+        let counts = pick_counts(&PowerOfTwoChoices, &busy, 3000);
+        assert_eq!(counts[0], 0, "{:?}", counts);
+        
+        assert_eq!(PowerOfTwoChoices.select(&busy[..1]), 0);
+    }
+    
+    #[test]
+    fn least_outstanding_picks_the_idlest_node_then_the_fastest() {
+        let nodes: Vec<RpcNode> = endpoints(3).into_iter().map(RpcNode::new).collect();
+        assert_eq!(LeastOutstanding.select(&candidates(&nodes, &[2, 1, 3], &[1, 1, 1])), 1);
+        assert_eq!(LeastOutstanding.select(&candidates(&nodes, &[1, 1, 0], &[1, 1, 1])), 2);
+        
+        // equally loaded nodes go to the best score, always
+        let tied = candidates(&nodes, &[1, 0, 0], &[1, 1, 1]);
+        assert_eq!(pick_counts(&LeastOutstanding, &tied, 100), vec![0, 100, 0]);
+    }
+    
+    #[test]
+    fn weighted_round_robin_follows_the_weights_smoothly() {
+        let nodes: Vec<RpcNode> = endpoints(3).into_iter().map(RpcNode::new).collect();
+        let weighted = candidates(&nodes, &[0, 0, 0], &[5, 1, 1]);
+        let selector = WeightedRoundRobin::default();
+        
+        let picks: Vec<usize> = (0..7).map(|_| selector.select(&weighted)).collect();
+        assert_eq!(picks, vec![0, 0, 1, 0, 2, 0, 0]);
+        assert_eq!(pick_counts(&selector, &weighted, 700), vec![500, 100, 100]);
+    }
+    
+    #[test]
+    fn weighted_round_robin_forgets_departed_nodes() {
+        let nodes: Vec<RpcNode> = endpoints(3).into_iter().map(RpcNode::new).collect();
+        let all = candidates(&nodes, &[0, 0, 0], &[1, 1, 1]);
+        let selector = WeightedRoundRobin::default();
+        assert_eq!(pick_counts(&selector, &all, 3), vec![1, 1, 1]);
+        assert_eq!(selector.current.lock().unwrap().len(), 3);
+        
+        let remaining = candidates(&nodes[..2], &[0, 0], &[1, 1]);
+        assert_eq!(pick_counts(&selector, &remaining, 10), vec![5, 5]);
+        let current = selector.current.lock().unwrap();
+        assert_eq!(current.len(), 2);
+        assert!(!current.contains_key(&nodes[2].endpoint));
+    }
+}
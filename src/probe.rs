@@ -0,0 +1,374 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::types::{ProbeResult, RpcResponse};
+use crate::version::Version;
+
+/// Built-in probes run unless configured otherwise
+pub const DEFAULT_BUILTIN_PROBES: &str = "getHealth,getSlot,getVersion,getGenesisHash";
+
+/// One health check call and what its response must look like to pass
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Probe {
+    /// Shown in the stats endpoints, defaults to the method
+    #[serde(default)]
+    pub name: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Option<serde_json::Value>,
+    /// Checks on the result, which only has to be present and non-null when empty
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+    /// JSON-RPC error codes that still pass, e.g. -32602 from a node that processed invalid params
+    #[serde(default)]
+    pub accept_errors: Vec<i32>,
+    /// A failed required probe fails the whole health check, others only lower the health score
+    #[serde(default = "default_required")]
+    pub required: bool,
+    /// Share of the health score
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+impl Probe {
+    pub fn new(method: &str, params: Option<serde_json::Value>) -> Self {
+        Self {
+            name: method.to_string(),
+            method: method.to_string(),
+            params,
+            assertions: Vec::new(),
+            accept_errors: Vec::new(),
+            required: true,
+            weight: default_weight(),
+        }
+    }
+    
+    pub fn name(&self) -> &str {
+        if self.name.is_empty() { &self.method } else { &self.name }
+    }
+    
+    /// Why `response` fails this probe, `None` when it passes
+    pub fn violation(&self, response: &RpcResponse) -> Option<String> {
+        if let Some(error) = &response.error {
+            if self.accept_errors.contains(&error.code) {
+                return None;
+            }
+            return Some(format!("error {}: {}", error.code, error.message));
+        }
+        
+        let result = response.result.as_ref().unwrap_or(&serde_json::Value::Null);
+        if self.assertions.is_empty() {
+            return result.is_null().then(|| "empty result".to_string());
+        }
+        self.assertions.iter().find_map(|assertion| assertion.violation(result))
+    }
+}
+
+impl FromStr for Probe {
+    type Err = anyhow::Error;
+    
+    /// Required probe given on the command line as `method` or `method:<params JSON>`,
+    /// passing on any non-null result. Probes with assertions go in the config file.
+    fn from_str(s: &str) -> Result<Self> {
+        let (method, params) = match s.split_once(':') {
+            Some((method, params)) => (method, Some(serde_json::from_str(params)?)),
+            None => (s, None),
+        };
+        if method.is_empty() {
+            return Err(anyhow::anyhow!("expected method or method:<params JSON>, got '{}'", s));
+        }
+        Ok(Probe::new(method, params))
+    }
+}
+
+/// Check on one value in a probe's result
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Assertion {
+    /// JSON pointer into the result, e.g. `/solana-core`, the whole result when empty
+    #[serde(default)]
+    pub pointer: String,
+    /// The value must equal this
+    #[serde(default)]
+    pub equals: Option<serde_json::Value>,
+    /// The value must be a number of at least this
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// The value must be a number of at most this
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+impl Assertion {
+    fn at(pointer: &str) -> Self {
+        Self {
+            pointer: pointer.to_string(),
+            equals: None,
+            min: None,
+            max: None,
+        }
+    }
+    
+    /// Why `result` fails this assertion, `None` when it passes
+    fn violation(&self, result: &serde_json::Value) -> Option<String> {
+        let value = match result.pointer(&self.pointer) {
+            Some(value) if !value.is_null() => value,
+            _ => return Some(format!("no value at '{}'", self.pointer)),
+        };
+        
+        if let Some(expected) = &self.equals {
+            if value != expected {
+                return Some(format!("'{}' is {}, expected {}", self.pointer, value, expected));
+            }
+        }
+        if self.min.is_some() || self.max.is_some() {
+            let Some(number) = value.as_f64() else {
+                return Some(format!("'{}' is {}, expected a number", self.pointer, value));
+            };
+            if self.min.is_some_and(|min| number < min) || self.max.is_some_and(|max| number > max) {
+                return Some(format!("'{}' is {}, outside {:?}..={:?}", self.pointer, number, self.min, self.max));
+            }
+        }
+        None
+    }
+}
+
+/// Probes that come with the proxy, named after their method
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum BuiltinProbe {
+    /// Scored only, a node catching up still serves what it has
+    #[serde(rename = "getHealth")]
+    Health,
+    #[serde(rename = "getSlot")]
+    Slot,
+    #[serde(rename = "getVersion")]
+    Version,
+    #[serde(rename = "getGenesisHash")]
+    GenesisHash,
+}
+
+impl FromStr for BuiltinProbe {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "getHealth" => Ok(BuiltinProbe::Health),
+            "getSlot" => Ok(BuiltinProbe::Slot),
+            "getVersion" => Ok(BuiltinProbe::Version),
+            "getGenesisHash" => Ok(BuiltinProbe::GenesisHash),
+            _ => Err(anyhow::anyhow!(
+                "unknown built-in probe '{}', expected getHealth, getSlot, getVersion or getGenesisHash", s
+            )),
+        }
+    }
+}
+
+impl BuiltinProbe {
+    pub fn probe(self) -> Probe {
+        match self {
+            BuiltinProbe::Health => Probe {
+                assertions: vec![Assertion {
+                    equals: Some(serde_json::json!("ok")),
+                    ..Assertion::at("")
+                }],
+                required: false,
+                ..Probe::new("getHealth", None)
+            },
+            BuiltinProbe::Slot => Probe {
+                assertions: vec![Assertion {
+                    min: Some(0.0),
+                    ..Assertion::at("")
+                }],
+                ..Probe::new("getSlot", None)
+            },
+            BuiltinProbe::Version => Probe {
+                assertions: vec![Assertion::at("/solana-core")],
+                ..Probe::new("getVersion", None)
+            },
+            BuiltinProbe::GenesisHash => Probe::new("getGenesisHash", None),
+        }
+    }
+}
+
+/// Outcome of running every probe against a node
+#[derive(Debug, Clone)]
+pub struct ProbeReport {
+    pub results: Vec<ProbeResult>,
+    /// Genesis hash the node reported, `None` if it didn't answer
+    pub genesis_hash: Option<String>,
+    /// Version and feature set the node reported, `None` if it didn't answer
+    pub version: Option<(Version, Option<u32>)>,
+}
+
+impl ProbeReport {
+    /// Whether every required probe passed
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed || !result.required)
+    }
+    
+    /// Weighted share of passed probes, from 0 to 1
+    pub fn score(&self) -> f64 {
+        let total: f64 = self.results.iter().map(|result| result.weight).sum();
+        if total <= 0.0 {
+            return if self.passed() { 1.0 } else { 0.0 };
+        }
+        let passed = self.results
+            .iter()
+            .filter(|result| result.passed)
+            .fold(0.0, |passed, result| passed + result.weight);
+        passed / total
+    }
+    
+    /// Failures of required probes, for logging
+    pub fn failures(&self) -> String {
+        self.results
+            .iter()
+            .filter(|result| !result.passed && result.required)
+            .map(|result| format!("{} ({})", result.name, result.error.as_deref().unwrap_or("failed")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::types::RpcError;
+    
+    fn answer(result: serde_json::Value) -> RpcResponse {
+        serde_json::from_value(json!({ "jsonrpc": "2.0", "id": 1, "result": result })).unwrap()
+    }
+    
+    fn error(code: i32) -> RpcResponse {
+        RpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: json!(1),
+            result: None,
+            error: Some(RpcError { code, message: "Invalid params".to_string(), data: None }),
+        }
+    }
+    
+    fn checking(assertions: Vec<Assertion>) -> Probe {
+        Probe { assertions, ..Probe::new("getThing", None) }
+    }
+    
+    fn result(name: &str, passed: bool, required: bool, weight: f64) -> ProbeResult {
+        ProbeResult {
+            name: name.to_string(),
+            passed,
+            required,
+            weight,
+            latency_ms: 5,
+            error: (!passed).then(|| "failed".to_string()),
+        }
+    }
+    
+    fn report(results: Vec<ProbeResult>) -> ProbeReport {
+        ProbeReport { results, genesis_hash: None, version: None }
+    }
+    
+    #[test]
+    fn probes_without_assertions_pass_on_any_result() {
+        let probe = checking(vec![]);
+        assert_eq!(probe.violation(&answer(json!(0))), None);
+        assert_eq!(probe.violation(&answer(json!(false))), None);
+        assert_eq!(probe.violation(&answer(json!(null))).as_deref(), Some("empty result"));
+    }
+    
+    #[test]
+    fn errors_fail_unless_accepted() {
+        let mut probe = checking(vec![]);
+        assert_eq!(probe.violation(&error(-32602)).as_deref(), Some("error -32602: Invalid params"));
+        probe.accept_errors = vec![-32602];
+        assert_eq!(probe.violation(&error(-32602)), None);
+        assert!(probe.violation(&error(-32005)).is_some());
+    }
+    
+    #[test]
+    fn pointers_must_lead_to_a_value() {
+        let probe = checking(vec![Assertion::at("/solana-core")]);
+        assert_eq!(probe.violation(&answer(json!({ "solana-core": "2.0.15" }))), None);
+        assert_eq!(
+            probe.violation(&answer(json!({ "feature-set": 1 }))).as_deref(),
+            Some("no value at '/solana-core'")
+        );
+        assert!(probe.violation(&answer(json!({ "solana-core": null }))).is_some());
+    }
+    
+    #[test]
+    fn equals_compares_whole_values() {
+        let probe = checking(vec![Assertion { equals: Some(json!("ok")), ..Assertion::at("") }]);
+        assert_eq!(probe.violation(&answer(json!("ok"))), None);
+        assert_eq!(probe.violation(&answer(json!("behind"))).as_deref(), Some("'' is \"behind\", expected \"ok\""));
+        
+        let probe = checking(vec![Assertion { equals: Some(json!(3)), ..Assertion::at("/context/slot") }]);
+        assert_eq!(probe.violation(&answer(json!({ "context": { "slot": 3 } }))), None);
+        assert!(probe.violation(&answer(json!({ "context": { "slot": "3" } }))).is_some());
+    }
+    
+    #[test]
+    fn min_and_max_bound_numbers() {
+        let probe = checking(vec![Assertion { min: Some(10.0), max: Some(20.0), ..Assertion::at("") }]);
+        assert_eq!(probe.violation(&answer(json!(10))), None);
+        assert_eq!(probe.violation(&answer(json!(20.0))), None);
+        assert_eq!(probe.violation(&answer(json!(9))).as_deref(), Some("'' is 9, outside Some(10.0)..=Some(20.0)"));
+        assert!(probe.violation(&answer(json!(21))).is_some());
+        assert_eq!(probe.violation(&answer(json!("15"))).as_deref(), Some("'' is \"15\", expected a number"));
+        
+        let at_least = checking(vec![Assertion { min: Some(0.0), ..Assertion::at("") }]);
+        assert_eq!(at_least.violation(&answer(json!(u64::MAX))), None);
+        assert!(at_least.violation(&answer(json!(-1))).is_some());
+    }
+    
+    #[test]
+    fn every_assertion_must_hold() {
+        let probe = checking(vec![
+            Assertion { equals: Some(json!("ok")), ..Assertion::at("/status") },
+            Assertion { max: Some(5.0), ..Assertion::at("/lag") },
+        ]);
+        assert_eq!(probe.violation(&answer(json!({ "status": "ok", "lag": 2 }))), None);
+        assert_eq!(
+            probe.violation(&answer(json!({ "status": "ok", "lag": 8 }))).as_deref(),
+            Some("'/lag' is 8, outside None..=Some(5.0)")
+        );
+        assert!(probe.violation(&answer(json!({ "status": "slow", "lag": 2 }))).is_some());
+    }
+    
+    #[test]
+    fn only_required_probes_fail_the_check() {
+        assert!(report(vec![]).passed());
+        assert!(report(vec![result("getSlot", true, true, 1.0), result("getHealth", false, false, 1.0)]).passed());
+        assert!(!report(vec![result("getSlot", false, true, 1.0), result("getHealth", true, false, 1.0)]).passed());
+    }
+    
+    #[test]
+    fn score_is_the_weighted_share_passed() {
+        let mixed = report(vec![
+            result("getSlot", true, true, 3.0),
+            result("getHealth", false, false, 1.0),
+        ]);
+        assert_eq!(mixed.score(), 0.75);
+        
+        // a failed required probe still counts only its weight
+        let failed = report(vec![result("getSlot", false, true, 1.0), result("getVersion", true, true, 1.0)]);
+        assert!(!failed.passed());
+        assert_eq!(failed.score(), 0.5);
+        assert_eq!(failed.failures(), "getSlot (failed)");
+        
+        // without any weight the check itself decides
+        assert_eq!(report(vec![result("getSlot", true, true, 0.0)]).score(), 1.0);
+        assert_eq!(report(vec![result("getSlot", false, true, 0.0)]).score(), 0.0);
+        assert_eq!(report(vec![]).score(), 1.0);
+    }
+}
@@ -1,58 +1,134 @@
 use anyhow::Result;
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{ws::WebSocketUpgrade, ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::{Json, Response},
-    routing::post,
+    routing::{get, post},
     Router,
     serve,
-    body::Body,
+    body::{Body, Bytes},
 };
+use futures_util::StreamExt;
 use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::Semaphore;
+use std::time::Duration;
+use tokio::sync::{watch, Semaphore, SemaphorePermit};
 use tracing::{error, info, warn, debug};
 use tower_http::cors::{CorsLayer, Any};
-use num_cpus;
 
+use crate::affinity::Affinity;
+use crate::auth::{self, ApiKey, ApiKeys, AuthError};
+use crate::cache::{self, ResponseCache};
+use crate::capability::Needs;
+use crate::coalesce::{self, Flight, InFlightRequests, Outcome};
+use crate::fault::rpc_error_fault;
+use crate::guard::{MethodGuards, Rejection};
+use crate::hedge::{HedgePolicy, Hedger};
+use crate::metrics::{self, Metrics};
 use crate::node_cache::NodeCache;
-use crate::rpc_client::{forward_rpc_request_raw};
-use crate::types::{RpcRequest, RpcResponse, RpcError};
+use crate::rate_limit::{RateLimiter, RateLimits};
+use crate::retry::{RetryPolicies, RETRY_OPT_IN_HEADER};
+use crate::rpc_client::{BodyLimits, ForwardError, StreamingBody, UpstreamClient, UpstreamResponse};
+use crate::types::{MethodClass, RpcRequest, RpcResponse, RpcError};
+use crate::version::MethodVersions;
+
+/// Request handling settings that can be replaced while the server is running.
+/// Requests already in flight keep the settings they started with.
+#[derive(Debug, Clone)]
+pub struct ProxySettings {
+    pub rpc_request_timeout: u64,
+    pub max_concurrent_rpc: usize,
+    pub max_queue_wait_time: u64,
+    pub batch_chunk_size: usize,
+    pub retry_policies: RetryPolicies,
+    /// Size limit for cached results, 0 disables the response cache
+    pub response_cache_bytes: usize,
+    /// How long results that change every slot are served from the cache
+    pub short_cache_ttl: Duration,
+    /// Share one upstream call between identical requests in flight at the same time
+    pub coalesce_requests: bool,
+    pub rate_limits: RateLimits,
+    /// Keys from the configuration, keys managed through the admin API are kept separately
+    pub api_keys: Vec<ApiKey>,
+    /// Reject requests without a valid API key instead of serving them anonymously
+    pub require_api_key: bool,
+    /// Bearer token for the admin API, which is disabled when unset
+    pub admin_token: Option<String>,
+    pub guards: MethodGuards,
+    pub body_limits: BodyLimits,
+    pub affinity: Affinity,
+    pub hedging: HedgePolicy,
+    pub method_versions: MethodVersions,
+}
+
+impl ProxySettings {
+    fn cache_policy(&self, request: &RpcRequest) -> Option<cache::CachePolicy> {
+        if self.response_cache_bytes == 0 {
+            return None;
+        }
+        cache::cache_policy(request, self.short_cache_ttl)
+    }
+}
 
 pub struct ProxyServer {
     node_cache: Arc<NodeCache>,
-    rpc_request_timeout: u64,
+    upstream: UpstreamClient,
+    settings: watch::Receiver<Arc<ProxySettings>>,
     rpc_semaphore: Arc<Semaphore>,
-    max_concurrent: usize,
-    max_queue_wait_time: u64,
+    metrics: Arc<Metrics>,
+    cache: Arc<ResponseCache>,
+    in_flight: Arc<InFlightRequests>,
+    rate_limiter: Arc<RateLimiter>,
+    api_keys: Arc<ApiKeys>,
+    hedger: Arc<Hedger>,
 }
 
 impl ProxyServer {
     pub fn new(
         node_cache: Arc<NodeCache>, 
-        rpc_request_timeout: u64,
-        max_concurrent_rpc: usize,
-        max_queue_wait_time: u64
+        upstream: UpstreamClient,
+        settings: watch::Receiver<Arc<ProxySettings>>,
     ) -> Self {
+        let max_concurrent_rpc = settings.borrow().max_concurrent_rpc;
         info!("🚀 Setting up RPC request queue with max {} concurrent requests (Multi-Core Mode)", max_concurrent_rpc);
-        info!("⏱️  RPC request timeout: {}s", rpc_request_timeout);
+        info!("⏱️  RPC request timeout: {}s", settings.borrow().rpc_request_timeout);
         info!("⚡ CPU cores available: {}", num_cpus::get());
+        let api_keys = ApiKeys::new(settings.borrow().api_keys.clone());
+        if settings.borrow().require_api_key {
+            info!("🔑 API key required for all requests ({} keys configured)", api_keys.count());
+        } else if api_keys.count() > 0 {
+            info!("🔑 {} API keys configured, requests without a key are served anonymously", api_keys.count());
+        }
         Self {
             node_cache,
-            rpc_request_timeout,
+            upstream,
+            settings,
             rpc_semaphore: Arc::new(Semaphore::new(max_concurrent_rpc)),
-            max_concurrent: max_concurrent_rpc,
-            max_queue_wait_time,
+            metrics: Arc::new(Metrics::new()),
+            cache: Arc::new(ResponseCache::new()),
+            in_flight: Arc::new(InFlightRequests::new()),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            api_keys: Arc::new(api_keys),
+            hedger: Arc::new(Hedger::new()),
         }
     }
     
-    pub async fn start(&self, port: u16) -> Result<()> {
+    /// Serve JSON-RPC and PubSub on `port`. PubSub is additionally served on
+    /// `pubsub_port` when given, for clients that derive it as RPC port + 1.
+    pub async fn start(&self, port: u16, pubsub_port: Option<u16>) -> Result<()> {
         let app = Router::new()
-            .route("/", post(rpc_handler))
-            .route("/health", axum::routing::get(health_handler))
-            .route("/stats", axum::routing::get(stats_handler))
-            .route("/performance", axum::routing::get(performance_handler))
-            .route("/queue", axum::routing::get(queue_stats_handler))
+            .route("/", post(rpc_handler).get(pubsub_handler))
+            // API key as a path segment, as commercial RPC providers do
+            .route("/:api_key", post(rpc_handler).get(pubsub_handler))
+            .route("/health", get(health_handler))
+            .route("/stats", get(stats_handler))
+            .route("/performance", get(performance_handler))
+            .route("/queue", get(queue_stats_handler))
+            .route("/metrics", get(metrics_handler))
+            .route("/admin/api-keys", get(list_api_keys_handler))
+            .route("/admin/api-keys/:name", axum::routing::put(put_api_key_handler).delete(delete_api_key_handler))
             .layer(
                 CorsLayer::new()
                     .allow_origin(Any)
@@ -61,17 +137,63 @@ impl ProxyServer {
             )
             .with_state(AppState {
                 node_cache: Arc::clone(&self.node_cache),
-                rpc_request_timeout: self.rpc_request_timeout,
+                upstream: self.upstream.clone(),
+                settings: self.settings.clone(),
                 rpc_semaphore: Arc::clone(&self.rpc_semaphore),
-                max_concurrent: self.max_concurrent,
-                max_queue_wait_time: self.max_queue_wait_time,
+                metrics: Arc::clone(&self.metrics),
+                cache: Arc::clone(&self.cache),
+                in_flight: Arc::clone(&self.in_flight),
+                rate_limiter: Arc::clone(&self.rate_limiter),
+                api_keys: Arc::clone(&self.api_keys),
+                hedger: Arc::clone(&self.hedger),
             });
         
+        let rate_limiter = Arc::clone(&self.rate_limiter);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                rate_limiter.prune();
+            }
+        });
+        
+        // Resize the request queue and swap in the configured API keys whenever new settings arrive
+        let mut settings = self.settings.clone();
+        let rpc_semaphore = Arc::clone(&self.rpc_semaphore);
+        let api_keys = Arc::clone(&self.api_keys);
+        tokio::spawn(async move {
+            let mut max_concurrent = settings.borrow_and_update().max_concurrent_rpc;
+            while settings.changed().await.is_ok() {
+                let new_settings = Arc::clone(&settings.borrow_and_update());
+                api_keys.set_configured(new_settings.api_keys.clone());
+                let new_max_concurrent = new_settings.max_concurrent_rpc;
+                if new_max_concurrent != max_concurrent {
+                    info!("🔧 Resizing RPC request queue: {} -> {} concurrent requests", max_concurrent, new_max_concurrent);
+                    resize_semaphore(&rpc_semaphore, max_concurrent, new_max_concurrent);
+                    max_concurrent = new_max_concurrent;
+                }
+            }
+        });
+        
+        if let Some(pubsub_port) = pubsub_port {
+            let pubsub_addr = format!("0.0.0.0:{}", pubsub_port);
+            info!("📡 PubSub websocket server starting on: {}", pubsub_addr);
+            
+            let pubsub_listener = tokio::net::TcpListener::bind(&pubsub_addr).await?;
+            let pubsub_app = app.clone();
+            tokio::spawn(async move {
+                let pubsub_service = pubsub_app.into_make_service_with_connect_info::<SocketAddr>();
+                if let Err(e) = serve(pubsub_listener, pubsub_service).await {
+                    error!("PubSub websocket server failed: {}", e);
+                }
+            });
+        }
+        
         let addr = format!("0.0.0.0:{}", port);
         info!("🌐 RPC proxy server starting on: {}", addr);
         
         let listener = tokio::net::TcpListener::bind(&addr).await?;
-        serve(listener, app).await?;
+        serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
         
         Ok(())
     }
@@ -80,10 +202,41 @@ impl ProxyServer {
 #[derive(Clone)]
 struct AppState {
     node_cache: Arc<NodeCache>,
-    rpc_request_timeout: u64,
+    upstream: UpstreamClient,
+    settings: watch::Receiver<Arc<ProxySettings>>,
     rpc_semaphore: Arc<Semaphore>,
-    max_concurrent: usize,
-    max_queue_wait_time: u64,
+    metrics: Arc<Metrics>,
+    cache: Arc<ResponseCache>,
+    in_flight: Arc<InFlightRequests>,
+    rate_limiter: Arc<RateLimiter>,
+    api_keys: Arc<ApiKeys>,
+    hedger: Arc<Hedger>,
+}
+
+impl AppState {
+    /// Settings snapshot for one request
+    fn settings(&self) -> Arc<ProxySettings> {
+        Arc::clone(&self.settings.borrow())
+    }
+}
+
+/// Grow or shrink the request queue. Permits held by in-flight requests are
+/// retired as those requests finish, so nothing already running is cut off.
+fn resize_semaphore(semaphore: &Arc<Semaphore>, from: usize, to: usize) {
+    if to > from {
+        semaphore.add_permits(to - from);
+    } else if to < from {
+        let excess = from - to;
+        let forgotten = semaphore.forget_permits(excess);
+        if forgotten < excess {
+            let semaphore = Arc::clone(semaphore);
+            tokio::spawn(async move {
+                if let Ok(permit) = semaphore.acquire_many_owned((excess - forgotten) as u32).await {
+                    permit.forget();
+                }
+            });
+        }
+    }
 }
 
 fn format_rpc_request_info(request: &RpcRequest) -> String {
@@ -100,7 +253,7 @@ fn format_rpc_request_info(request: &RpcRequest) -> String {
         "getTokenAccountsByOwner" => {
             if let Some(params) = &request.params {
                 if let Some(params_array) = params.as_array() {
-                    if let Some(owner) = params_array.get(0) {
+                    if let Some(owner) = params_array.first() {
                         if let Some(owner_str) = owner.as_str() {
                             return format!("getTokenAccountsByOwner(owner={}..., id={})", 
                                          &owner_str[..8.min(owner_str.len())], id_str);
@@ -113,7 +266,7 @@ fn format_rpc_request_info(request: &RpcRequest) -> String {
         "getAccountInfo" => {
             if let Some(params) = &request.params {
                 if let Some(params_array) = params.as_array() {
-                    if let Some(account) = params_array.get(0) {
+                    if let Some(account) = params_array.first() {
                         if let Some(account_str) = account.as_str() {
                             return format!("getAccountInfo(account={}..., id={})", 
                                          &account_str[..8.min(account_str.len())], id_str);
@@ -137,153 +290,1005 @@ fn format_rpc_request_info(request: &RpcRequest) -> String {
     }
 }
 
-async fn rpc_handler(
-    State(state): State<AppState>,
-    Json(request): Json<RpcRequest>,
-) -> Result<Response<Body>, (StatusCode, Json<RpcResponse>)> {
-    let start_time = std::time::Instant::now();
-    let request_info = format_rpc_request_info(&request);
-    let available_permits = state.rpc_semaphore.available_permits();
-    
-    let request_id_str = match &request.id {
-        serde_json::Value::String(s) => s.clone(),
-        serde_json::Value::Number(n) => n.to_string(),
-        _ => request.id.to_string(),
-    };
-    
-    info!("📨 [ID:{}] Incoming RPC request: {} (active: {}/{})", 
-          request_id_str, request_info, state.max_concurrent - available_permits, state.max_concurrent);
-    
-    let _permit = match state.rpc_semaphore.try_acquire() {
+/// Wait for `permits` slots in the RPC queue, giving up after `max_queue_wait_time`
+async fn acquire_rpc_permits<'a>(
+    state: &'a AppState,
+    settings: &ProxySettings,
+    permits: u32,
+    request_id_str: &str,
+    method: &str,
+    start_time: std::time::Instant,
+) -> Result<SemaphorePermit<'a>, (StatusCode, RpcError)> {
+    match state.rpc_semaphore.try_acquire_many(permits) {
         Ok(permit) => {
-            debug!("⚡ [ID:{}] Acquired RPC permit immediately for [{}]", request_id_str, request.method);
-            permit
+            debug!("⚡ [ID:{}] Acquired RPC permit immediately for [{}]", request_id_str, method);
+            state.metrics.observe_queue_wait(start_time.elapsed());
+            Ok(permit)
         },
         Err(_) => {
-            debug!("⏳ [ID:{}] RPC request [{}] waiting for permit...", request_id_str, request.method);
+            debug!("⏳ [ID:{}] RPC request [{}] waiting for permit...", request_id_str, method);
+            let _queued = state.metrics.enter_queue();
             
-            match tokio::time::timeout(
-                std::time::Duration::from_secs(state.max_queue_wait_time), 
-                state.rpc_semaphore.acquire()
-            ).await {
+            let acquired = tokio::time::timeout(
+                std::time::Duration::from_secs(settings.max_queue_wait_time), 
+                state.rpc_semaphore.acquire_many(permits)
+            ).await;
+            state.metrics.observe_queue_wait(start_time.elapsed());
+            
+            match acquired {
                 Ok(Ok(permit)) => {
                     let wait_time = start_time.elapsed();
                     debug!("⚡ [ID:{}] RPC request [{}] acquired permit after {:?}", 
-                          request_id_str, request.method, wait_time);
-                    permit
+                          request_id_str, method, wait_time);
+                    Ok(permit)
                 },
                 Ok(Err(_)) => {
-                    error!("💀 [ID:{}] RPC semaphore was closed for [{}]", request_id_str, request.method);
-                    let error_response = RpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id: request.id,
-                        result: None,
-                        error: Some(RpcError {
-                            code: -32000,
-                            message: "Server shutting down".to_string(),
-                            data: None,
-                        }),
-                    };
-                    return Err((StatusCode::SERVICE_UNAVAILABLE, Json(error_response)));
+                    error!("💀 [ID:{}] RPC semaphore was closed for [{}]", request_id_str, method);
+                    Err((StatusCode::SERVICE_UNAVAILABLE, RpcError {
+                        code: -32000,
+                        message: "Server shutting down".to_string(),
+                        data: None,
+                    }))
                 },
                 Err(_) => {
                     let wait_time = start_time.elapsed();
                     warn!("⏰ [ID:{}] RPC request [{}] queue timeout after {:?}", 
-                          request_id_str, request.method, wait_time);
-                    let error_response = RpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id: request.id,
-                        result: None,
-                        error: Some(RpcError {
-                            code: -32000,
-                            message: "Server overloaded, request queue full".to_string(),
-                            data: Some(json!({
-                                "queue_wait_time_ms": wait_time.as_millis(),
-                                "max_queue_wait_ms": state.max_queue_wait_time * 1000
-                            })),
-                        }),
-                    };
-                    return Err((StatusCode::SERVICE_UNAVAILABLE, Json(error_response)));
+                          request_id_str, method, wait_time);
+                    Err((StatusCode::SERVICE_UNAVAILABLE, RpcError {
+                        code: -32000,
+                        message: "Server overloaded, request queue full".to_string(),
+                        data: Some(json!({
+                            "queue_wait_time_ms": wait_time.as_millis(),
+                            "max_queue_wait_ms": settings.max_queue_wait_time * 1000
+                        })),
+                    }))
                 }
             }
         }
+    }
+}
+
+/// Check the request's API key against the configured keys
+fn authenticate(
+    state: &AppState,
+    settings: &ProxySettings,
+    path_key: Option<&str>,
+    headers: &HeaderMap,
+    query: &HashMap<String, String>,
+) -> Result<Option<Arc<ApiKey>>, AuthError> {
+    let presented = auth::presented_key(path_key, headers, query);
+    let origin = auth::request_origin(headers);
+    state.api_keys.authenticate(presented, origin, settings.require_api_key).inspect_err(|e| {
+        warn!("🔒 Rejected request from origin {:?}: {}", origin.unwrap_or("-"), e.rpc_error().message);
+    })
+}
+
+/// Refuse requests the client's API key may not make, or that the method
+/// guards consider too expensive for nodes, before they take a queue slot
+fn check_request(settings: &ProxySettings, api_key: Option<&ApiKey>, request: &RpcRequest) -> Result<(), (StatusCode, RpcError)> {
+    if let Some(api_key) = api_key.filter(|api_key| !api_key.allows_method(&request.method)) {
+        warn!("🔒 API key '{}' may not call [{}]", api_key.name, request.method);
+        return Err((StatusCode::FORBIDDEN, auth::method_not_allowed(&request.method)));
+    }
+    
+    settings.guards.check(request).map_err(|rejection| {
+        let status = match &rejection {
+            Rejection::MethodNotAllowed => StatusCode::FORBIDDEN,
+            Rejection::TooExpensive(_) => StatusCode::BAD_REQUEST,
+        };
+        let error = rejection.rpc_error(&request.method);
+        warn!("🛡️  Rejected RPC request [{}]: {}", request.method, error.message);
+        (status, error)
+    })
+}
+
+fn auth_error_response(id: serde_json::Value, error: AuthError) -> (StatusCode, Json<RpcResponse>) {
+    (error.status(), Json(RpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(error.rpc_error()),
+    }))
+}
+
+/// Charge the client's rate limit bucket, returning how long to wait when it is empty
+fn check_rate_limit(
+    state: &AppState,
+    settings: &ProxySettings,
+    peer: SocketAddr,
+    headers: &HeaderMap,
+    api_key: Option<&ApiKey>,
+    cost: u32,
+) -> Result<(), Duration> {
+    let client_ip = settings.rate_limits.client_ip(peer.ip(), headers);
+    state.rate_limiter.check(&settings.rate_limits, client_ip, api_key, cost).inspect_err(|retry_after| {
+        let key_info = api_key.map(|api_key| format!(" (API key: {})", api_key.name)).unwrap_or_default();
+        warn!("🚦 Rate limited client {}{} (cost: {}), retry after {:?}", 
+              client_ip, key_info, cost, retry_after);
+    })
+}
+
+fn rate_limited_response(id: serde_json::Value, retry_after: Duration) -> Response<Body> {
+    let error_response = RpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(RpcError {
+            code: -32005,
+            message: "Rate limit exceeded".to_string(),
+            data: Some(json!({ "retry_after_ms": retry_after.as_millis() })),
+        }),
     };
+    
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("Content-Type", "application/json")
+        .header("Retry-After", retry_after.as_secs_f64().ceil().max(1.0).to_string())
+        .body(Body::from(serde_json::to_string(&error_response).unwrap()))
+        .unwrap()
+}
 
-    let processing_start = std::time::Instant::now();
+async fn rpc_handler(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    path_key: Option<Path<String>>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response<Body>, (StatusCode, Json<RpcResponse>)> {
+    let settings = state.settings();
+    // Parsed here rather than by the Json extractor, whose rejections aren't JSON-RPC errors
+    let body: serde_json::Value = serde_json::from_slice(&body).map_err(|e| {
+        state.metrics.record_request("invalid");
+        state.metrics.record_error("invalid", -32700);
+        (StatusCode::BAD_REQUEST, Json(RpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: serde_json::Value::Null,
+            result: None,
+            error: Some(RpcError {
+                code: -32700,
+                message: "Parse error".to_string(),
+                data: Some(json!({ "details": e.to_string() })),
+            }),
+        }))
+    })?;
+    let method = match &body {
+        serde_json::Value::Array(_) => "batch",
+        request => request.get("method").and_then(|method| method.as_str()).unwrap_or("invalid"),
+    };
+    let id = body.get("id").cloned().unwrap_or(serde_json::Value::Null);
     
-    match state.node_cache.get_random_fast_node().await {
-        Some(node) => {
-            let response_time_info = node.response_time
-                .map(|t| format!(" (avg health check: {:?})", t))
-                .unwrap_or_else(|| " (no health check data)".to_string());
-            
-            info!("🚀 [ID:{}] Processing RPC request [{}] to node: {}{} (timeout: {}s)", 
-                  request_id_str, request.method, node.endpoint, response_time_info, state.rpc_request_timeout);
+    let api_key = match authenticate(&state, &settings, path_key.as_ref().map(|Path(key)| key.as_str()), &headers, &query) {
+        Ok(api_key) => api_key,
+        Err(e) => {
+            state.metrics.record_error(method, e.rpc_error().code);
+            return Err(auth_error_response(id, e));
+        }
+    };
+    
+    // Heavier methods take more of the client's budget, a batch costs the sum of its entries
+    let method_cost = |request: &serde_json::Value| {
+        request.get("method").and_then(|method| method.as_str()).map_or(1, |method| settings.rate_limits.method_cost(method))
+    };
+    let (cost, requests) = match &body {
+        serde_json::Value::Array(items) => (items.iter().map(method_cost).sum::<u32>().max(1), items.len() as u64),
+        request => (method_cost(request), 1),
+    };
+    
+    if let Err(retry_after) = check_rate_limit(&state, &settings, peer, &headers, api_key.as_deref(), cost) {
+        state.metrics.record_error(method, -32005);
+        return Ok(rate_limited_response(id, retry_after));
+    }
+    
+    if let Some(api_key) = &api_key {
+        state.api_keys.record_usage(api_key, requests, cost);
+    }
+    
+    // Identifies the client for affinity routing
+    let client = match &api_key {
+        Some(api_key) => format!("key:{}", api_key.name),
+        None => settings.rate_limits.client_ip(peer.ip(), &headers).to_string(),
+    };
+    
+    let allow_retry = headers
+        .get(RETRY_OPT_IN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    
+    match body {
+        serde_json::Value::Array(items) => batch_rpc_handler(state, items, api_key, &client, allow_retry).await,
+        body => {
+            let request: RpcRequest = serde_json::from_value(body).map_err(|e| {
+                let error_response = RpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: serde_json::Value::Null,
+                    result: None,
+                    error: Some(RpcError {
+                        code: -32600,
+                        message: "Invalid Request".to_string(),
+                        data: Some(json!({ "details": e.to_string() })),
+                    }),
+                };
+                state.metrics.record_request("invalid");
+                state.metrics.record_error("invalid", -32600);
+                (StatusCode::BAD_REQUEST, Json(error_response))
+            })?;
             
-            let node_endpoint = node.endpoint.clone(); // Clone the endpoint for error handling
+            if let Err((status, error)) = check_request(&settings, api_key.as_deref(), &request) {
+                state.metrics.record_request(&request.method);
+                state.metrics.record_error(&request.method, error.code);
+                return Err((status, Json(RpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: request.id,
+                    result: None,
+                    error: Some(error),
+                })));
+            }
             
-            match forward_rpc_request_raw(&node.endpoint, &request, state.rpc_request_timeout).await {
-                Ok(raw_response) => {
-                    let processing_time = processing_start.elapsed();
-                    let total_time = start_time.elapsed();
-                    
-                    info!("✅ [ID:{}] RPC request [{}] completed - processing: {:?}, total: {:?}", 
-                          request_id_str, request.method, processing_time, total_time);
-                    
-                    // return raw json response
-                    Ok(Response::builder()
-                        .status(StatusCode::OK)
-                        .header("Content-Type", "application/json")
-                        .body(Body::from(raw_response))
-                        .unwrap())
-                },
-                Err(e) => {
-                    let processing_time = processing_start.elapsed();
-                    let total_time = start_time.elapsed();
-                    error!("❌ [ID:{}] RPC request [{}] failed after {:?} (timeout: {}s) - error: {}", 
-                           request_id_str, request.method, processing_time, state.rpc_request_timeout, e);
-                    
-                    // Remove the failed node from cache to prevent future requests to it
-                    warn!("🗑️  [ID:{}] Removing failed node {} from active nodes list", request_id_str, node_endpoint);
-                    state.node_cache.remove_node(&node_endpoint).await;
-                    
-                    let error_response = RpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id: request.id,
-                        result: None,
-                        error: Some(RpcError {
-                            code: -32603,
-                            message: "Internal error".to_string(),
-                            data: Some(json!({
-                                "details": e.to_string(),
-                                "processing_time_ms": processing_time.as_millis(),
-                                "total_time_ms": total_time.as_millis()
-                            })),
-                        }),
-                    };
-                    Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)))
+            single_rpc_handler(state, request, &client, allow_retry).await
+        }
+    }
+}
+
+async fn single_rpc_handler(
+    state: AppState,
+    request: RpcRequest,
+    client: &str,
+    allow_retry: bool,
+) -> Result<Response<Body>, (StatusCode, Json<RpcResponse>)> {
+    let start_time = std::time::Instant::now();
+    let settings = state.settings();
+    let request_info = format_rpc_request_info(&request);
+    let available_permits = state.rpc_semaphore.available_permits();
+    
+    let request_id_str = match &request.id {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => request.id.to_string(),
+    };
+    
+    info!("📨 [ID:{}] Incoming RPC request: {} (active: {}/{})", 
+          request_id_str, request_info, settings.max_concurrent_rpc.saturating_sub(available_permits), settings.max_concurrent_rpc);
+    state.metrics.record_request(&request.method);
+    
+    // Cache hits don't need a queue slot
+    let cache_policy = settings.cache_policy(&request);
+    if let Some(policy) = cache_policy {
+        if let Some(result) = state.cache.get(&request, policy) {
+            info!("💾 [ID:{}] RPC request [{}] served from cache in {:?}", 
+                  request_id_str, request.method, start_time.elapsed());
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(cache::cached_response(&result, &request.id)))
+                .unwrap());
+        }
+    }
+    
+    let affinity_key = settings.affinity.key(&request, client);
+    let forward = || forward_single_request(
+        &state, &settings, &request, &request_id_str, allow_retry, start_time, cache_policy, affinity_key.as_deref(),
+    );
+    
+    // Identical requests already in flight share one upstream call
+    let forwarded = loop {
+        if !settings.coalesce_requests || !coalesce::is_coalescable(&request) {
+            break forward().await;
+        }
+        
+        match state.in_flight.join(&request) {
+            Flight::Leader(leader) => {
+                let forwarded = forward().await;
+                // A streamed body can't be shared, followers forward on their own once the leader is dropped
+                if let Some(outcome) = shareable_outcome(&forwarded) {
+                    leader.complete(&outcome);
+                }
+                break forwarded;
+            }
+            Flight::Follower(follower) => {
+                debug!("🔗 [ID:{}] Joining identical in-flight RPC request [{}]", request_id_str, request.method);
+                if let Some(outcome) = follower.wait().await {
+                    info!("🔗 [ID:{}] RPC request [{}] answered by identical in-flight request in {:?}", 
+                          request_id_str, request.method, start_time.elapsed());
+                    state.metrics.record_coalesced(&request.method);
+                    break outcome.map(|body| Forwarded::Buffered(coalesce::with_id(&body, &request.id)));
                 }
+                debug!("🔗 [ID:{}] In-flight RPC request [{}] went away, forwarding on its own", request_id_str, request.method);
             }
         }
-        None => {
-            let total_time = start_time.elapsed();
-            warn!("💥 [ID:{}] No available RPC nodes for [{}] after {:?}", 
-                  request_id_str, request.method, total_time);
+    };
+    
+    match forwarded {
+        Ok(Forwarded::Buffered(body)) => {
+            state.metrics.record_response(&request.method, std::str::from_utf8(&body).unwrap_or_default());
+            
+            // return raw json response
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap())
+        }
+        Ok(Forwarded::Streaming(body)) => {
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap())
+        }
+        Err((status, error)) => {
+            state.metrics.record_error(&request.method, error.code);
             let error_response = RpcResponse {
                 jsonrpc: "2.0".to_string(),
                 id: request.id,
                 result: None,
-                error: Some(RpcError {
+                error: Some(error),
+            };
+            Err((status, Json(error_response)))
+        }
+    }
+}
+
+/// Response to a single request, held in memory or passed through from the node as it arrives
+enum Forwarded {
+    Buffered(Bytes),
+    Streaming(Body),
+}
+
+/// Outcome to hand to coalesced followers, `None` for a streamed body
+fn shareable_outcome(forwarded: &Result<Forwarded, (StatusCode, RpcError)>) -> Option<Outcome> {
+    match forwarded {
+        Ok(Forwarded::Buffered(body)) => Some(Ok(body.clone())),
+        Ok(Forwarded::Streaming(_)) => None,
+        Err(error) => Some(Err(error.clone())),
+    }
+}
+
+/// Logs and counts a streamed response once its body has been sent, or
+/// dropped because the client went away
+struct StreamedResponse {
+    metrics: Arc<Metrics>,
+    request_id_str: String,
+    method: String,
+    node: String,
+    start_time: std::time::Instant,
+    bytes: usize,
+    error: Option<String>,
+    finished: bool,
+}
+
+impl Drop for StreamedResponse {
+    fn drop(&mut self) {
+        self.metrics.record_streamed(&self.method, self.bytes);
+        match (&self.error, self.finished) {
+            (Some(e), _) => error!("❌ [ID:{}] Streaming [{}] from {} failed after {} bytes in {:?} - error: {}", 
+                                   self.request_id_str, self.method, self.node, self.bytes, self.start_time.elapsed(), e),
+            (None, true) => info!("✅ [ID:{}] RPC request [{}] streamed {} bytes - total: {:?}", 
+                                  self.request_id_str, self.method, self.bytes, self.start_time.elapsed()),
+            (None, false) => warn!("🔌 [ID:{}] Client went away while streaming [{}] after {} bytes", 
+                                   self.request_id_str, self.method, self.bytes),
+        }
+    }
+}
+
+fn streaming_body(body: StreamingBody, tracker: StreamedResponse) -> Body {
+    let stream = futures_util::stream::unfold((body.into_stream().boxed(), tracker), |(mut inner, mut tracker)| async move {
+        match inner.next().await {
+            Some(Ok(chunk)) => {
+                tracker.bytes += chunk.len();
+                Some((Ok(chunk), (inner, tracker)))
+            }
+            Some(Err(e)) => {
+                tracker.error = Some(e.to_string());
+                Some((Err(e), (inner, tracker)))
+            }
+            None => {
+                tracker.finished = true;
+                drop(tracker);
+                None
+            }
+        }
+    });
+    Body::from_stream(stream)
+}
+
+/// Queue and forward a single request, retrying on other nodes as its retry policy allows.
+/// Requests with an `affinity_key` go to the node it hashes to, and to the next one on retries.
+#[allow(clippy::too_many_arguments)]
+async fn forward_single_request(
+    state: &AppState,
+    settings: &ProxySettings,
+    request: &RpcRequest,
+    request_id_str: &str,
+    allow_retry: bool,
+    start_time: std::time::Instant,
+    cache_policy: Option<cache::CachePolicy>,
+    affinity_key: Option<&str>,
+) -> Result<Forwarded, (StatusCode, RpcError)> {
+    let _permit = acquire_rpc_permits(state, settings, 1, request_id_str, &request.method, start_time).await?;
+
+    let processing_start = std::time::Instant::now();
+    let policy = settings.retry_policies.for_method(&request.method, allow_retry);
+    let class = MethodClass::of(&request.method);
+    let needs = Needs::of(request, &settings.method_versions);
+    let mut tried_nodes: Vec<String> = Vec::new();
+    let mut attempt = 0;
+    
+    // Pinned requests stay on their node
+    let hedged = affinity_key.is_none() && settings.hedging.is_hedged(&request.method);
+    if hedged {
+        state.hedger.earn(&settings.hedging);
+    }
+    
+    loop {
+        attempt += 1;
+        
+        let node = match state.node_cache.select_node_excluding(&tried_nodes, class, &needs, affinity_key).await {
+            Some(node) => node,
+            None => {
+                let total_time = start_time.elapsed();
+                warn!("💥 [ID:{}] No available RPC nodes for [{}] after {:?} ({} nodes tried)", 
+                      request_id_str, request.method, total_time, tried_nodes.len());
+                return Err((StatusCode::SERVICE_UNAVAILABLE, RpcError {
                     code: -32000,
                     message: "No available RPC nodes".to_string(),
                     data: Some(json!({
-                        "total_time_ms": total_time.as_millis()
+                        "total_time_ms": total_time.as_millis(),
+                        "attempts": tried_nodes.len()
                     })),
-                }),
-            };
-            Err((StatusCode::SERVICE_UNAVAILABLE, Json(error_response)))
+                }));
+            }
+        };
+        
+        let response_time_info = node.response_time
+            .map(|t| format!(" (avg health check: {:?})", t))
+            .unwrap_or_else(|| " (no health check data)".to_string());
+        
+        // Never let an attempt outlive the method's overall budget
+        let attempt_timeout = policy.attempt_timeout(Duration::from_secs(settings.rpc_request_timeout), processing_start.elapsed());
+        
+        info!("🚀 [ID:{}] Processing RPC request [{}] to node: {}{} (attempt {}/{}, timeout: {:?})", 
+              request_id_str, request.method, node.endpoint, response_time_info, 
+              attempt, policy.max_attempts, attempt_timeout);
+        
+        let attempt_start = std::time::Instant::now();
+        
+        let hedge_delay = if hedged { state.hedger.delay(&request.method, &settings.hedging) } else { None };
+        let (node, result) = match hedge_delay {
+            Some(delay) => attempt_with_hedge(state, settings, request, request_id_str, &node.endpoint, class, &needs, attempt_timeout, delay, &mut tried_nodes).await,
+            None => {
+                let result = attempt_request(state, settings, request, &node.endpoint, class, attempt_timeout).await;
+                (node.endpoint, result)
+            }
+        };
+        
+        match result {
+            Ok(UpstreamResponse::Streaming(body)) => {
+                info!("📡 [ID:{}] RPC request [{}] answered by {} with more than {} KiB, streaming it to the client", 
+                      request_id_str, request.method, node, settings.body_limits.buffer_bytes / 1024);
+                return Ok(Forwarded::Streaming(streaming_body(body, StreamedResponse {
+                    metrics: Arc::clone(&state.metrics),
+                    request_id_str: request_id_str.to_string(),
+                    method: request.method.clone(),
+                    node,
+                    start_time,
+                    bytes: 0,
+                    error: None,
+                    finished: false,
+                })));
+            },
+            Ok(UpstreamResponse::Buffered(raw_response)) => {
+                if let Some(policy) = cache_policy {
+                    if let Ok(response) = serde_json::from_slice::<serde_json::Value>(&raw_response) {
+                        state.cache.insert(request, policy, &response, settings.response_cache_bytes);
+                    }
+                }
+                let processing_time = processing_start.elapsed();
+                let total_time = start_time.elapsed();
+                
+                info!("✅ [ID:{}] RPC request [{}] completed - processing: {:?}, total: {:?}, attempts: {}", 
+                      request_id_str, request.method, processing_time, total_time, attempt);
+                
+                return Ok(Forwarded::Buffered(raw_response));
+            },
+            Err(e) => {
+                let processing_time = processing_start.elapsed();
+                let total_time = start_time.elapsed();
+                error!("❌ [ID:{}] RPC request [{}] failed on {} after {:?} (attempt {}/{}) - error: {}", 
+                       request_id_str, request.method, node, attempt_start.elapsed(), 
+                       attempt, policy.max_attempts, e);
+                
+                if !tried_nodes.contains(&node) {
+                    tried_nodes.push(node);
+                }
+                
+                if e.is_retryable() && policy.allows_retry(attempt, processing_start.elapsed())
+                    && can_retry_elsewhere(state, &tried_nodes, class, &needs, affinity_key).await
+                {
+                    info!("🔁 [ID:{}] Retrying RPC request [{}] on another node", request_id_str, request.method);
+                    continue;
+                }
+                
+                return Err(forward_error_response(&e, json!({
+                    "details": e.to_string(),
+                    "attempts": attempt,
+                    "processing_time_ms": processing_time.as_millis(),
+                    "total_time_ms": total_time.as_millis()
+                })));
+            }
+        }
+    }
+}
+
+/// Whether a node not tried yet could take a retry. Without one, the last node's
+/// own answer goes back to the client rather than a lack of nodes.
+async fn can_retry_elsewhere(
+    state: &AppState,
+    tried_nodes: &[String],
+    class: MethodClass,
+    needs: &Needs,
+    affinity_key: Option<&str>,
+) -> bool {
+    state.node_cache.select_node_excluding(tried_nodes, class, needs, affinity_key).await.is_some()
+}
+
+/// One upstream attempt at `request` on `node`, recorded against the node's health and the metrics
+async fn attempt_request(
+    state: &AppState,
+    settings: &ProxySettings,
+    request: &RpcRequest,
+    node: &str,
+    class: MethodClass,
+    timeout: Duration,
+) -> Result<UpstreamResponse, ForwardError> {
+    let attempt_start = std::time::Instant::now();
+    
+    let outstanding = state.node_cache.track_request(node);
+    let result = state.upstream.forward_rpc_request_raw(node, request, timeout, &settings.body_limits).await;
+    drop(outstanding);
+    state.metrics.observe_upstream(&request.method, node, attempt_start.elapsed(), result.is_ok());
+    let fault = result.as_ref().err().map(|e| e.fault());
+    state.node_cache.record_request(node, class, attempt_start.elapsed(), fault).await;
+    
+    if result.is_ok() && settings.hedging.is_hedged(&request.method) {
+        state.hedger.observe(&request.method, attempt_start.elapsed());
+    }
+    result
+}
+
+/// Attempt `request` on `node`, and also on a second node if `node` hasn't answered
+/// after `delay` and the hedge budget allows it. The first successful answer wins and
+/// the other attempt is cancelled. Returns the node that answered.
+#[allow(clippy::too_many_arguments)]
+async fn attempt_with_hedge(
+    state: &AppState,
+    settings: &ProxySettings,
+    request: &RpcRequest,
+    request_id_str: &str,
+    node: &str,
+    class: MethodClass,
+    needs: &Needs,
+    timeout: Duration,
+    delay: Duration,
+    tried_nodes: &mut Vec<String>,
+) -> (String, Result<UpstreamResponse, ForwardError>) {
+    let first = attempt_request(state, settings, request, node, class, timeout);
+    tokio::pin!(first);
+    
+    tokio::select! {
+        result = &mut first => return (node.to_string(), result),
+        _ = tokio::time::sleep(delay.min(timeout)) => {}
+    }
+    
+    let mut exclude = tried_nodes.clone();
+    exclude.push(node.to_string());
+    let hedge_node = match state.node_cache.select_node_excluding(&exclude, class, needs, None).await {
+        Some(hedge_node) if state.hedger.try_spend() => hedge_node.endpoint,
+        _ => return (node.to_string(), first.await),
+    };
+    
+    debug!("🐇 [ID:{}] No answer to [{}] from {} after {:?}, hedging on {}", 
+           request_id_str, request.method, node, delay, hedge_node);
+    tried_nodes.push(hedge_node.clone());
+    
+    let second = attempt_request(state, settings, request, &hedge_node, class, timeout.saturating_sub(delay));
+    tokio::pin!(second);
+    
+    // A failure only wins if the other node fails too
+    let (hedge_won, result) = tokio::select! {
+        result = &mut first => match result {
+            Ok(response) => (false, Ok(response)),
+            Err(e) => match second.await {
+                Ok(response) => (true, Ok(response)),
+                Err(_) => (false, Err(e)),
+            },
+        },
+        result = &mut second => match result {
+            Ok(response) => (true, Ok(response)),
+            Err(e) => match first.await {
+                Ok(response) => (false, Ok(response)),
+                Err(_) => (true, Err(e)),
+            },
+        },
+    };
+    
+    state.metrics.record_hedge(&request.method, hedge_won && result.is_ok());
+    (if hedge_won { hedge_node.clone() } else { node.to_string() }, result)
+}
+
+/// Status and JSON-RPC error returned to the client once forwarding gave up. A
+/// JSON-RPC error the node answered with is passed on unchanged along with its
+/// HTTP status, anything else becomes an internal error described by `data`.
+fn forward_error_response(e: &ForwardError, data: serde_json::Value) -> (StatusCode, RpcError) {
+    match e {
+        ForwardError::Upstream { status, error } => {
+            let status = StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+            match error {
+                Some(error) => (status, error.clone()),
+                None => {
+                    // a 404 from an endpoint that isn't an RPC node would mislead the client
+                    let status = if e.is_node_failure() && status.is_client_error() { StatusCode::BAD_GATEWAY } else { status };
+                    (status, RpcError {
+                        code: -32603,
+                        message: "Internal error".to_string(),
+                        data: Some(data),
+                    })
+                }
+            }
+        }
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, RpcError {
+            code: -32603,
+            message: "Internal error".to_string(),
+            data: Some(data),
+        }),
+    }
+}
+
+/// Handle a JSON-RPC 2.0 batch. Valid entries are split into chunks of
+/// `batch_chunk_size` and each chunk is forwarded to its own node in parallel.
+async fn batch_rpc_handler(
+    state: AppState,
+    items: Vec<serde_json::Value>,
+    api_key: Option<Arc<ApiKey>>,
+    client: &str,
+    allow_retry: bool,
+) -> Result<Response<Body>, (StatusCode, Json<RpcResponse>)> {
+    let start_time = std::time::Instant::now();
+    let settings = state.settings();
+    let batch_id = uuid::Uuid::new_v4().to_string()[..8].to_string();
+    
+    if items.is_empty() {
+        warn!("📦 [Batch:{}] Rejecting empty RPC batch", batch_id);
+        state.metrics.record_request("invalid");
+        state.metrics.record_error("invalid", -32600);
+        let error_response = RpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: serde_json::Value::Null,
+            result: None,
+            error: Some(RpcError {
+                code: -32600,
+                message: "Invalid Request".to_string(),
+                data: Some(json!({ "details": "empty batch" })),
+            }),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+    
+    let mut responses: Vec<Option<serde_json::Value>> = vec![None; items.len()];
+    let mut methods: Vec<String> = vec!["invalid".to_string(); items.len()];
+    // Notifications (requests without an id) are forwarded but get no entry in the reply
+    let mut notifications: Vec<bool> = vec![false; items.len()];
+    let mut pending = Vec::new();
+    
+    for (index, mut item) in items.into_iter().enumerate() {
+        if let Some(object) = item.as_object_mut() {
+            if !object.contains_key("id") {
+                notifications[index] = true;
+                object.insert("id".to_string(), serde_json::Value::Null);
+            }
+        }
+        let id = item.get("id").cloned().unwrap_or(serde_json::Value::Null);
+        match serde_json::from_value::<RpcRequest>(item) {
+            Ok(request) => {
+                methods[index] = request.method.clone();
+                if let Err((_, error)) = check_request(&settings, api_key.as_deref(), &request) {
+                    responses[index] = Some(batch_error_entry(request.id, error.code, &error.message, error.data.unwrap_or_default()));
+                    continue;
+                }
+                match cached_batch_entry(&state, &settings, &request) {
+                    Some(entry) => responses[index] = Some(entry),
+                    None => pending.push((index, request)),
+                }
+            }
+            Err(e) => {
+                responses[index] = Some(batch_error_entry(id, -32600, "Invalid Request", json!({
+                    "details": e.to_string()
+                })));
+            }
+        }
+    }
+    
+    let chunks: Vec<Vec<(usize, RpcRequest)>> = pending
+        .chunks(settings.batch_chunk_size.max(1))
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    
+    let invalid = responses.iter().flatten().filter(|entry| entry.get("error").is_some()).count();
+    info!("📦 [Batch:{}] Incoming RPC batch: {} requests ({} rejected, {} cached) in {} chunks (active: {}/{})", 
+          batch_id, responses.len(), invalid, responses.len() - pending.len() - invalid, chunks.len(),
+          settings.max_concurrent_rpc.saturating_sub(state.rpc_semaphore.available_permits()), settings.max_concurrent_rpc);
+    for method in &methods {
+        state.metrics.record_request(method);
+    }
+    
+    if !chunks.is_empty() {
+        // Each chunk occupies one queue slot, capped so a large batch can never wait forever
+        let permits = chunks.len().min(settings.max_concurrent_rpc).max(1) as u32;
+        let _permit = match acquire_rpc_permits(&state, &settings, permits, &batch_id, "batch", start_time).await {
+            Ok(permit) => permit,
+            Err((status, error)) => {
+                for method in &methods {
+                    state.metrics.record_error(method, error.code);
+                }
+                let error_response = RpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: serde_json::Value::Null,
+                    result: None,
+                    error: Some(error),
+                };
+                return Err((status, Json(error_response)));
+            }
+        };
+        
+        let mut tasks = tokio::task::JoinSet::new();
+        for chunk in chunks {
+            // A chunk follows the first of its requests that has an affinity key
+            let affinity_key = chunk.iter().find_map(|(_, request)| settings.affinity.key(request, client));
+            tasks.spawn(forward_batch_chunk(state.clone(), Arc::clone(&settings), batch_id.clone(), chunk, affinity_key, allow_retry));
+        }
+        
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(entries) => {
+                    for (index, entry) in entries {
+                        responses[index] = Some(entry);
+                    }
+                }
+                Err(e) => error!("📦 [Batch:{}] Batch chunk task failed: {}", batch_id, e),
+            }
         }
     }
+    
+    let responses: Vec<serde_json::Value> = responses
+        .into_iter()
+        .map(|entry| entry.unwrap_or_else(|| batch_error_entry(
+            serde_json::Value::Null, -32603, "Internal error", json!({ "details": "batch chunk aborted" })
+        )))
+        .collect();
+    
+    for (method, entry) in methods.iter().zip(responses.iter()) {
+        if let Some(code) = entry.pointer("/error/code").and_then(|code| code.as_i64()) {
+            state.metrics.record_error(method, code as i32);
+        }
+    }
+    
+    info!("✅ [Batch:{}] RPC batch of {} requests completed in {:?}", 
+          batch_id, responses.len(), start_time.elapsed());
+    
+    let responses: Vec<serde_json::Value> = responses
+        .into_iter()
+        .zip(notifications)
+        .filter(|(_, notification)| !notification)
+        .map(|(entry, _)| entry)
+        .collect();
+    // A batch of only notifications is answered with nothing at all
+    if responses.is_empty() {
+        return Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap());
+    }
+    
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::Value::Array(responses).to_string()))
+        .unwrap())
+}
+
+/// Forward one chunk of a batch to a single node and map the responses back to
+/// their batch positions and original ids.
+async fn forward_batch_chunk(
+    state: AppState,
+    settings: Arc<ProxySettings>,
+    batch_id: String,
+    chunk: Vec<(usize, RpcRequest)>,
+    affinity_key: Option<String>,
+    allow_retry: bool,
+) -> Vec<(usize, serde_json::Value)> {
+    let processing_start = std::time::Instant::now();
+    
+    // Upstream ids are replaced by the batch position so duplicate or null client ids can't collide
+    let (original_ids, upstream_requests): (Vec<(usize, serde_json::Value)>, Vec<RpcRequest>) = chunk
+        .into_iter()
+        .map(|(index, mut request)| {
+            let id = std::mem::replace(&mut request.id, json!(index));
+            ((index, id), request)
+        })
+        .unzip();
+    
+    let policy = settings.retry_policies.for_methods(
+        upstream_requests.iter().map(|request| request.method.as_str()),
+        allow_retry,
+    );
+    let class = MethodClass::of_all(upstream_requests.iter().map(|request| request.method.as_str()));
+    let needs = Needs::of_all(&upstream_requests, &settings.method_versions);
+    let mut tried_nodes: Vec<String> = Vec::new();
+    
+    loop {
+        let attempt = tried_nodes.len() as u32 + 1;
+        
+        let node = match state.node_cache.select_node_excluding(&tried_nodes, class, &needs, affinity_key.as_deref()).await {
+            Some(node) => node,
+            None => {
+                warn!("💥 [Batch:{}] No available RPC nodes for {} batched requests", batch_id, original_ids.len());
+                return original_ids
+                    .into_iter()
+                    .map(|(index, id)| (index, batch_error_entry(id, -32000, "No available RPC nodes", json!({
+                        "total_time_ms": processing_start.elapsed().as_millis(),
+                        "attempts": tried_nodes.len()
+                    }))))
+                    .collect();
+            }
+        };
+        
+        let attempt_timeout = policy.attempt_timeout(Duration::from_secs(settings.rpc_request_timeout), processing_start.elapsed());
+        
+        info!("🚀 [Batch:{}] Processing {} batched requests to node: {} (attempt {}/{}, timeout: {:?})", 
+              batch_id, upstream_requests.len(), node.endpoint, attempt, policy.max_attempts, attempt_timeout);
+        
+        let attempt_start = std::time::Instant::now();
+        let outstanding = state.node_cache.track_request(&node.endpoint);
+        let result = state.upstream.forward_rpc_batch_raw(&node.endpoint, &upstream_requests, attempt_timeout, &settings.body_limits).await;
+        drop(outstanding);
+        state.metrics.observe_upstream("batch", &node.endpoint, attempt_start.elapsed(), result.is_ok());
+        let fault = match &result {
+            // the worst error among the entries
+            Ok(upstream_responses) => upstream_responses
+                .iter()
+                .filter_map(|response| response.pointer("/error/code")?.as_i64())
+                .map(|code| rpc_error_fault(code as i32))
+                .max(),
+            Err(e) => Some(e.fault()),
+        };
+        state.node_cache.record_request(&node.endpoint, class, attempt_start.elapsed(), fault).await;
+        
+        match result {
+            Ok(upstream_responses) => {
+                // Entries another node may answer differently send the whole chunk there while attempts remain
+                let processing_time = processing_start.elapsed();
+                let retryable = fault.is_some_and(|fault| fault.is_retryable());
+                tried_nodes.push(node.endpoint.clone());
+                if retryable && policy.allows_retry(attempt, processing_time)
+                    && can_retry_elsewhere(&state, &tried_nodes, class, &needs, affinity_key.as_deref()).await
+                {
+                    warn!("⚠️  [Batch:{}] {} batched requests answered by {} with errors another node may not give", 
+                          batch_id, upstream_requests.len(), node.endpoint);
+                    info!("🔁 [Batch:{}] Retrying {} batched requests on another node", batch_id, upstream_requests.len());
+                    continue;
+                }
+                
+                let mut by_index: HashMap<u64, serde_json::Value> = upstream_responses
+                    .into_iter()
+                    .filter_map(|response| Some((response.get("id")?.as_u64()?, response)))
+                    .collect();
+                
+                debug!("✅ [Batch:{}] {} batched requests completed on {} in {:?}", 
+                       batch_id, upstream_requests.len(), node.endpoint, processing_start.elapsed());
+                
+                return original_ids
+                    .into_iter()
+                    .zip(upstream_requests.iter())
+                    .map(|((index, id), request)| {
+                        let entry = match by_index.remove(&(index as u64)) {
+                            Some(mut response) => {
+                                if let Some(policy) = settings.cache_policy(request) {
+                                    state.cache.insert(request, policy, &response, settings.response_cache_bytes);
+                                }
+                                response["id"] = id;
+                                response
+                            }
+                            None => batch_error_entry(id, -32603, "Internal error", json!({
+                                "details": "upstream node returned no response for this request"
+                            })),
+                        };
+                        (index, entry)
+                    })
+                    .collect();
+            }
+            Err(e) => {
+                let processing_time = processing_start.elapsed();
+                error!("❌ [Batch:{}] {} batched requests failed on {} after {:?} (attempt {}/{}) - error: {}", 
+                       batch_id, upstream_requests.len(), node.endpoint, processing_time, 
+                       attempt, policy.max_attempts, e);
+                
+                tried_nodes.push(node.endpoint);
+                
+                if e.is_retryable() && policy.allows_retry(attempt, processing_time)
+                    && can_retry_elsewhere(&state, &tried_nodes, class, &needs, affinity_key.as_deref()).await
+                {
+                    info!("🔁 [Batch:{}] Retrying {} batched requests on another node", batch_id, upstream_requests.len());
+                    continue;
+                }
+                
+                let (_, error) = forward_error_response(&e, json!({
+                    "details": e.to_string(),
+                    "attempts": attempt,
+                    "processing_time_ms": processing_time.as_millis()
+                }));
+                return original_ids
+                    .into_iter()
+                    .map(|(index, id)| (index, batch_error_entry(id, error.code, &error.message, error.data.clone().unwrap_or_default())))
+                    .collect();
+            }
+        }
+    }
+}
+
+/// Batch response entry for `request` served from the cache
+fn cached_batch_entry(state: &AppState, settings: &ProxySettings, request: &RpcRequest) -> Option<serde_json::Value> {
+    let result = state.cache.get(request, settings.cache_policy(request)?)?;
+    Some(json!({
+        "jsonrpc": "2.0",
+        "result": serde_json::from_str::<serde_json::Value>(&result).ok()?,
+        "id": request.id
+    }))
+}
+
+fn batch_error_entry(id: serde_json::Value, code: i32, message: &str, data: serde_json::Value) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message,
+            "data": data
+        }
+    })
+}
+
+async fn pubsub_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    path_key: Option<Path<String>>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let settings = state.settings();
+    let api_key = match authenticate(&state, &settings, path_key.as_ref().map(|Path(key)| key.as_str()), &headers, &query) {
+        Ok(api_key) => api_key,
+        Err(e) => return axum::response::IntoResponse::into_response(auth_error_response(serde_json::Value::Null, e)),
+    };
+    
+    if let Err(retry_after) = check_rate_limit(&state, &settings, peer, &headers, api_key.as_deref(), 1) {
+        return rate_limited_response(serde_json::Value::Null, retry_after);
+    }
+    
+    if let Some(api_key) = &api_key {
+        state.api_keys.record_usage(api_key, 1, 1);
+    }
+    
+    let node_cache = Arc::clone(&state.node_cache);
+    let guards = settings.guards.clone();
+    let limits = crate::pubsub::ClientLimits {
+        client_ip: settings.rate_limits.client_ip(peer.ip(), &headers),
+        rate_limits: settings.rate_limits.clone(),
+        rate_limiter: Arc::clone(&state.rate_limiter),
+        api_keys: Arc::clone(&state.api_keys),
+    };
+    ws.on_upgrade(move |socket| crate::pubsub::run_session(socket, node_cache, api_key, guards, limits))
 }
 
 async fn health_handler() -> Json<serde_json::Value> {
@@ -295,40 +1300,143 @@ async fn health_handler() -> Json<serde_json::Value> {
     }))
 }
 
-async fn stats_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+async fn stats_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_monitoring_access(&state, &headers)?;
+    
     let (total, active) = state.node_cache.get_node_stats().await;
+    let node_states: serde_json::Map<String, serde_json::Value> = state.node_cache
+        .get_state_counts()
+        .await
+        .into_iter()
+        .map(|(node_state, count)| (node_state.as_str().to_string(), json!(count)))
+        .collect();
+    let versions: Vec<serde_json::Value> = state.node_cache
+        .get_version_counts()
+        .await
+        .into_iter()
+        .map(|(version, total, active)| json!({
+            "version": version.map_or_else(|| "unknown".to_string(), |version| version.to_string()),
+            "total": total,
+            "active": active
+        }))
+        .collect();
+    let cache_stats = state.cache.stats();
+    let hit_rate = |stats: cache::CacheClassStats| {
+        let lookups = stats.hits + stats.misses;
+        if lookups == 0 { 0.0 } else { stats.hits as f64 / lookups as f64 }
+    };
     
-    Json(json!({
+    Ok(Json(json!({
         "total_nodes": total,
         "active_nodes": active,
-        "uptime": "running",
+        "node_states": node_states,
+        "average_health_score": state.node_cache.average_health_score().await,
+        "versions": versions,
+        "uptime_seconds": state.metrics.uptime().as_secs(),
+        "cache": {
+            "entries": cache_stats.entries,
+            "bytes": cache_stats.bytes,
+            "max_bytes": state.settings().response_cache_bytes,
+            "immutable": cache_stats.immutable,
+            "immutable_hit_rate": hit_rate(cache_stats.immutable),
+            "short_lived": cache_stats.short_lived,
+            "short_lived_hit_rate": hit_rate(cache_stats.short_lived)
+        },
         "mode": "multi-core",
         "cpu_cores": num_cpus::get()
-    }))
+    })))
 }
 
-async fn performance_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+async fn performance_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_monitoring_access(&state, &headers)?;
+    
     let (total, active, min_response, max_response) = state.node_cache.get_performance_stats().await;
+    let (cluster_tip, node_lags) = state.node_cache.get_slot_lag_stats().await;
+    let quarantine_policy = state.node_cache.quarantine_policy();
+    let load_balancing = state.node_cache.load_balancing();
+    let strategies: serde_json::Map<String, serde_json::Value> = MethodClass::ALL
+        .into_iter()
+        .map(|class| (class.as_str().to_string(), json!(load_balancing.strategy(class).as_str())))
+        .collect();
+    let settings = state.settings();
+    let hedge_delays: serde_json::Map<String, serde_json::Value> = state.hedger
+        .delays(&settings.hedging)
+        .into_iter()
+        .map(|(method, delay)| (method, json!(delay.as_millis())))
+        .collect();
+    let method_versions: serde_json::Map<String, serde_json::Value> = settings.method_versions
+        .ranges()
+        .map(|(method, range)| (method.to_string(), json!(range.to_string())))
+        .collect();
+    let now = std::time::SystemTime::now();
     
-    Json(json!({
+    let nodes: Vec<serde_json::Value> = node_lags
+        .into_iter()
+        .map(|(node, slot_lag)| json!({
+            "endpoint": node.endpoint,
+            "is_active": node.is_active,
+            "state": node.health.state,
+            "consecutive_failures": node.health.consecutive_failures,
+            "quarantines": node.health.quarantines,
+            "quarantine_remaining_ms": node.health.quarantined_until
+                .map(|until| until.duration_since(now).unwrap_or_default().as_millis()),
+            "health_check_time_ms": node.response_time.map(|t| t.as_millis()),
+            "health_score": node.health_score,
+            "probes": node.probes,
+            "traffic": node.traffic,
+            "outstanding_requests": state.node_cache.outstanding_requests(&node.endpoint),
+            "shred_version": node.shred_version,
+            "version": node.version,
+            "feature_set": node.feature_set,
+            "slots": node.slots,
+            "capabilities": node.capabilities,
+            "slot_lag": slot_lag,
+            "lagging": slot_lag.is_some_and(|lag| lag > state.node_cache.max_slot_lag())
+        }))
+        .collect();
+    
+    Ok(Json(json!({
         "total_nodes": total,
         "active_nodes": active,
         "min_health_check_time_ms": min_response.map(|t| t.as_millis()),
         "max_health_check_time_ms": max_response.map(|t| t.as_millis()),
-        "rpc_request_timeout_ms": state.rpc_request_timeout * 1000,
-        "performance_optimization": "top_100_fastest_nodes",
+        "cluster_tip": cluster_tip,
+        "max_slot_lag": state.node_cache.max_slot_lag(),
+        "quarantine_policy": {
+            "failure_threshold": quarantine_policy.failure_threshold,
+            "base_backoff_ms": quarantine_policy.base_backoff.as_millis(),
+            "max_backoff_ms": quarantine_policy.max_backoff.as_millis()
+        },
+        "nodes": nodes,
+        "rpc_request_timeout_ms": settings.rpc_request_timeout * 1000,
+        "load_balancing": strategies,
+        "affinity": settings.affinity.mode(),
+        "method_versions": method_versions,
+        "hedging": {
+            "enabled": settings.hedging.enabled(),
+            "percentile": settings.hedging.percentile(),
+            "budget_percent": settings.hedging.budget() * 100.0,
+            "delay_ms": hedge_delays
+        },
         "mode": "multi-core",
         "cpu_cores": num_cpus::get()
-    }))
+    })))
 }
 
 async fn queue_stats_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let max_concurrent = state.settings().max_concurrent_rpc;
     let available_permits = state.rpc_semaphore.available_permits();
-    let active_requests = state.max_concurrent - available_permits;
+    let active_requests = max_concurrent.saturating_sub(available_permits);
     
     Json(json!({
         "queue_status": {
-            "max_concurrent_requests": state.max_concurrent,
+            "max_concurrent_requests": max_concurrent,
             "active_requests": active_requests,
             "available_slots": available_permits,
             "queue_full": available_permits == 0
@@ -338,4 +1446,307 @@ async fn queue_stats_handler(State(state): State<AppState>) -> Json<serde_json::
             "cpu_cores": num_cpus::get()
         }
     }))
-} 
\ No newline at end of file
+} 
+
+async fn metrics_handler(State(state): State<AppState>, headers: HeaderMap) -> Response<Body> {
+    let admin = match check_monitoring_access(&state, &headers) {
+        Ok(admin) => admin,
+        Err(status) => return Response::builder().status(status).body(Body::empty()).unwrap(),
+    };
+    let (cluster_tip, node_lags) = state.node_cache.get_slot_lag_stats().await;
+    let max_concurrent = state.settings().max_concurrent_rpc;
+    let active_requests = max_concurrent.saturating_sub(state.rpc_semaphore.available_permits());
+    
+    let mut body = String::new();
+    state.metrics.render(&mut body, active_requests, max_concurrent);
+    metrics::render_cache_stats(&mut body, &state.cache.stats());
+    metrics::render_node_health(&mut body, &cluster_tip, &node_lags, state.node_cache.max_slot_lag());
+    // Key names and labels identify customers, never shown without the admin token
+    if admin {
+        metrics::render_api_key_usage(&mut body, &state.api_keys.usage());
+    }
+    
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Admin requests must carry `Authorization: Bearer <admin_token>`. Without a
+/// configured token the admin API doesn't exist.
+fn check_admin_token(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(admin_token) = state.settings().admin_token.clone() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if auth::token_matches(presented, &admin_token) => Ok(()),
+        _ => {
+            warn!("🔒 Rejected admin request with missing or wrong token");
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// /stats, /performance and /metrics need the admin token when one is configured.
+/// Returns whether the request carried it.
+fn check_monitoring_access(state: &AppState, headers: &HeaderMap) -> Result<bool, StatusCode> {
+    match check_admin_token(state, headers) {
+        Ok(()) => Ok(true),
+        Err(StatusCode::NOT_FOUND) => Ok(false),
+        Err(status) => Err(status),
+    }
+}
+
+async fn list_api_keys_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    
+    let usage: HashMap<String, auth::KeyUsage> = state.api_keys
+        .usage()
+        .into_iter()
+        .map(|entry| (entry.name, entry.usage))
+        .collect();
+    let keys: Vec<serde_json::Value> = state.api_keys
+        .list()
+        .into_iter()
+        .map(|api_key| json!({
+            "policy": &*api_key,
+            "usage": usage.get(&api_key.name).copied().unwrap_or_default()
+        }))
+        .collect();
+    
+    Ok(Json(json!({
+        "require_api_key": state.settings().require_api_key,
+        "keys": keys
+    })))
+}
+
+/// Add or replace a key. Keys managed here outlive config reloads but not a restart.
+async fn put_api_key_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(mut api_key): Json<ApiKey>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    check_admin_token(&state, &headers).map_err(|status| (status, String::new()))?;
+    if api_key.key.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "key must not be empty".to_string()));
+    }
+    
+    api_key.name = name;
+    info!("🔑 API key '{}' added through the admin API", api_key.name);
+    state.api_keys.upsert(api_key);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_api_key_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    
+    if state.api_keys.revoke(&name) {
+        info!("🔑 API key '{}' revoked through the admin API", name);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::affinity::AffinityMode;
+    use crate::node_cache::CheckOutcome;
+    use crate::rpc_client::UpstreamClientConfig;
+    use crate::types::RpcNode;
+    use std::sync::Mutex;
+    
+    /// Node stand-in answering every request of a batch with its method name.
+    /// Returns its endpoint and the size of each batch it received.
+    async fn upstream_node() -> (String, Arc<Mutex<Vec<usize>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new().route("/", post(|State(received): State<Arc<Mutex<Vec<usize>>>>, Json(batch): Json<Vec<serde_json::Value>>| async move {
+            received.lock().unwrap().push(batch.len());
+            let replies: Vec<serde_json::Value> = batch
+                .iter()
+                .map(|request| json!({ "jsonrpc": "2.0", "id": request["id"], "result": request["method"] }))
+                .collect();
+            Json(replies)
+        })).with_state(Arc::clone(&received));
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { serve(listener, app).await.unwrap() });
+        (endpoint, received)
+    }
+    
+    fn settings(batch_chunk_size: usize) -> ProxySettings {
+        ProxySettings {
+            rpc_request_timeout: 5,
+            max_concurrent_rpc: 10,
+            max_queue_wait_time: 5,
+            batch_chunk_size,
+            retry_policies: RetryPolicies::new(crate::retry::RetryPolicy::single_attempt(Duration::from_secs(5)), vec![], vec![]),
+            response_cache_bytes: 0,
+            short_cache_ttl: Duration::ZERO,
+            coalesce_requests: false,
+            rate_limits: RateLimits::new(0.0, None, vec![], vec![]),
+            api_keys: vec![],
+            require_api_key: false,
+            admin_token: None,
+            guards: MethodGuards::new(vec![], vec![], vec![], 0, 0, 0),
+            body_limits: BodyLimits {
+                buffer_bytes: 1024 * 1024,
+                max_bytes: 0,
+                transfer_time: Duration::from_secs(5),
+            },
+            affinity: Affinity::new(AffinityMode::Off, vec![]),
+            hedging: HedgePolicy::new(false, vec![], 95, 10),
+            method_versions: MethodVersions::default(),
+        }
+    }
+    
+    async fn app_state(batch_chunk_size: usize) -> (AppState, Arc<Mutex<Vec<usize>>>) {
+        let (endpoint, received) = upstream_node().await;
+        let node_cache = Arc::new(NodeCache::default());
+        node_cache.update_node_status(RpcNode::new(endpoint), CheckOutcome::Passed, Some(Duration::from_millis(5))).await;
+        
+        let upstream = UpstreamClient::new(&UpstreamClientConfig {
+            pool_max_idle_per_node: 1,
+            pool_idle_timeout: Duration::from_secs(1),
+            connect_timeout: Duration::from_secs(1),
+            http2: false,
+        })
+        .unwrap();
+        let (_, settings) = watch::channel(Arc::new(settings(batch_chunk_size)));
+        let state = AppState {
+            node_cache,
+            upstream,
+            settings,
+            rpc_semaphore: Arc::new(Semaphore::new(10)),
+            metrics: Arc::new(Metrics::new()),
+            cache: Arc::new(ResponseCache::new()),
+            in_flight: Arc::new(InFlightRequests::new()),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            api_keys: Arc::new(ApiKeys::new(vec![])),
+            hedger: Arc::new(Hedger::new()),
+        };
+        (state, received)
+    }
+    
+    /// Post `body` to the JSON-RPC endpoint, returning the status and the reply
+    async fn post_rpc(state: &AppState, body: &str) -> (StatusCode, serde_json::Value) {
+        let response = rpc_handler(
+            State(state.clone()),
+            ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))),
+            None,
+            Query(HashMap::new()),
+            HeaderMap::new(),
+            Bytes::from(body.to_string()),
+        )
+        .await;
+        
+        match response {
+            Ok(response) => {
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+            }
+            Err((status, Json(error))) => (status, serde_json::to_value(error).unwrap()),
+        }
+    }
+    
+    #[tokio::test]
+    async fn malformed_json_is_a_parse_error() {
+        let (state, _) = app_state(10).await;
+        let (status, reply) = post_rpc(&state, r#"{"jsonrpc": "2.0", "id": 1,"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(reply["error"]["code"], json!(-32700));
+        assert_eq!(reply["id"], serde_json::Value::Null);
+    }
+    
+    #[tokio::test]
+    async fn empty_batch_is_a_single_invalid_request() {
+        let (state, received) = app_state(10).await;
+        let (status, reply) = post_rpc(&state, "[]").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(reply.is_object());
+        assert_eq!(reply["error"]["code"], json!(-32600));
+        assert!(received.lock().unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn batch_ids_are_kept_across_chunks() {
+        let (state, received) = app_state(2).await;
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": "a", "method": "getSlot" },
+            { "jsonrpc": "2.0", "id": 7, "method": "getHealth" },
+            { "jsonrpc": "2.0", "id": 7, "method": "getEpochInfo" },
+            { "jsonrpc": "2.0", "id": null, "method": "getBlockHeight" },
+            { "jsonrpc": "2.0", "id": "e", "method": "getVersion" },
+        ]);
+        
+        let (status, reply) = post_rpc(&state, &batch.to_string()).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        let expected: Vec<serde_json::Value> = batch
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|request| json!({ "jsonrpc": "2.0", "id": request["id"], "result": request["method"] }))
+            .collect();
+        assert_eq!(reply, json!(expected));
+        let mut chunks = received.lock().unwrap().clone();
+        chunks.sort();
+        assert_eq!(chunks, vec![1, 2, 2]);
+    }
+    
+    #[tokio::test]
+    async fn notifications_get_no_reply_entry() {
+        let (state, received) = app_state(10).await;
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "getSlot" },
+            { "jsonrpc": "2.0", "method": "getHealth" },
+        ]);
+        let (status, reply) = post_rpc(&state, &batch.to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reply, json!([{ "jsonrpc": "2.0", "id": 1, "result": "getSlot" }]));
+        
+        // Notifications are still forwarded, a batch of only notifications gets no reply at all
+        let (status, reply) = post_rpc(&state, &json!([{ "jsonrpc": "2.0", "method": "getHealth" }]).to_string()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(reply, serde_json::Value::Null);
+        assert_eq!(received.lock().unwrap().iter().sum::<usize>(), 3);
+    }
+    
+    #[tokio::test]
+    async fn invalid_batch_entries_are_answered_in_place() {
+        let (state, received) = app_state(10).await;
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "getSlot" },
+            { "jsonrpc": "2.0", "id": 2 },
+            5,
+            { "jsonrpc": "2.0", "id": 4, "method": "getHealth" },
+        ]);
+        
+        let (status, reply) = post_rpc(&state, &batch.to_string()).await;
+        
+        assert_eq!(status, StatusCode::OK);
+        let entries = reply.as_array().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0]["result"], json!("getSlot"));
+        assert_eq!((entries[1]["id"].clone(), entries[1]["error"]["code"].clone()), (json!(2), json!(-32600)));
+        assert_eq!((entries[2]["id"].clone(), entries[2]["error"]["code"].clone()), (serde_json::Value::Null, json!(-32600)));
+        assert_eq!(entries[3]["result"], json!("getHealth"));
+        assert_eq!(*received.lock().unwrap(), vec![2]);
+    }
+}
@@ -0,0 +1,719 @@
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
+
+use crate::auth::{self, ApiKey, ApiKeys};
+use crate::capability::Needs;
+use crate::guard::MethodGuards;
+use crate::node_cache::NodeCache;
+use crate::rate_limit::{RateLimiter, RateLimits};
+use crate::types::{MethodClass, RpcRequest};
+
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const UPSTREAM_CONNECT_ATTEMPTS: usize = 10;
+
+/// Request sent upstream on behalf of the client, keyed by the proxy-assigned request id
+enum PendingRequest {
+    Subscribe { client_id: Value, method: String, params: Option<Value> },
+    Resubscribe { subscription_id: u64, method: String },
+    Unsubscribe { client_id: Value },
+    Passthrough { client_id: Value },
+}
+
+/// What each client request is charged against, rate limits as of when the client connected
+pub struct ClientLimits {
+    pub client_ip: IpAddr,
+    pub rate_limits: RateLimits,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub api_keys: Arc<ApiKeys>,
+}
+
+struct Subscription {
+    method: String,
+    params: Option<Value>,
+    upstream_id: Option<u64>,
+}
+
+/// One client websocket connection. Subscription ids handed to the client are
+/// assigned by the proxy, so the upstream node can be replaced without the
+/// client noticing.
+struct PubsubSession {
+    session_id: String,
+    node_cache: Arc<NodeCache>,
+    /// Key the client connected with, limits the methods it may call
+    api_key: Option<Arc<ApiKey>>,
+    /// Method guards as of when the client connected
+    guards: MethodGuards,
+    limits: ClientLimits,
+    next_request_id: u64,
+    next_subscription_id: u64,
+    pending: HashMap<u64, PendingRequest>,
+    subscriptions: HashMap<u64, Subscription>,
+    upstream_subscriptions: HashMap<u64, u64>,
+}
+
+pub async fn run_session(
+    client: WebSocket,
+    node_cache: Arc<NodeCache>,
+    api_key: Option<Arc<ApiKey>>,
+    guards: MethodGuards,
+    limits: ClientLimits,
+) {
+    let mut session = PubsubSession::new(node_cache, api_key, guards, limits);
+    let (mut client_tx, mut client_rx) = client.split();
+    
+    let (mut upstream_endpoint, mut upstream) = match session.connect_upstream(&[]).await {
+        Some(connection) => connection,
+        None => {
+            warn!("💥 [WS:{}] No PubSub node available, closing client connection", session.session_id);
+            let _ = client_tx.send(Message::Text(error_message(Value::Null, -32000, "No available PubSub nodes"))).await;
+            return;
+        }
+    };
+    
+    loop {
+        let upstream_lost = tokio::select! {
+            message = client_rx.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let (to_client, to_upstream) = session.handle_client_message(&text);
+                    let upstream_lost = match to_upstream {
+                        Some(text) => send_upstream(&mut upstream, text, &session.session_id, &upstream_endpoint).await,
+                        None => false,
+                    };
+                    if let Some(text) = to_client {
+                        if client_tx.send(Message::Text(text)).await.is_err() {
+                            break;
+                        }
+                    }
+                    upstream_lost
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => false,
+                Some(Err(e)) => {
+                    debug!("[WS:{}] Client connection error: {}", session.session_id, e);
+                    break;
+                }
+            },
+            message = upstream.next() => match message {
+                Some(Ok(tungstenite::Message::Text(text))) => {
+                    let (to_client, to_upstream) = session.handle_upstream_message(&text);
+                    let upstream_lost = match to_upstream {
+                        Some(text) => send_upstream(&mut upstream, text, &session.session_id, &upstream_endpoint).await,
+                        None => false,
+                    };
+                    if let Some(text) = to_client {
+                        if client_tx.send(Message::Text(text)).await.is_err() {
+                            break;
+                        }
+                    }
+                    upstream_lost
+                }
+                Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => true,
+                Some(Ok(_)) => false,
+            },
+        };
+        
+        if !upstream_lost {
+            continue;
+        }
+        
+        warn!("🔌 [WS:{}] Upstream {} disconnected, failing over", session.session_id, upstream_endpoint);
+        let (to_client, connection) = session.failover(&upstream_endpoint).await;
+        let mut client_gone = false;
+        for text in to_client {
+            if client_tx.send(Message::Text(text)).await.is_err() {
+                client_gone = true;
+                break;
+            }
+        }
+        
+        match connection {
+            Some((endpoint, socket)) => {
+                upstream_endpoint = endpoint;
+                upstream = socket;
+            }
+            None => {
+                warn!("💥 [WS:{}] No PubSub node left to fail over to, closing client connection", session.session_id);
+                let _ = client_tx.send(Message::Close(None)).await;
+                break;
+            }
+        }
+        
+        if client_gone {
+            break;
+        }
+    }
+    
+    let _ = upstream.close(None).await;
+    info!("👋 [WS:{}] PubSub session closed ({} active subscriptions dropped)",
+          session.session_id, session.subscriptions.len());
+}
+
+impl PubsubSession {
+    fn new(node_cache: Arc<NodeCache>, api_key: Option<Arc<ApiKey>>, guards: MethodGuards, limits: ClientLimits) -> Self {
+        Self {
+            session_id: uuid::Uuid::new_v4().to_string()[..8].to_string(),
+            node_cache,
+            api_key,
+            guards,
+            limits,
+            next_request_id: 1,
+            next_subscription_id: 1,
+            pending: HashMap::new(),
+            subscriptions: HashMap::new(),
+            upstream_subscriptions: HashMap::new(),
+        }
+    }
+    
+    /// Connect to a PubSub node, skipping nodes whose RPC endpoint is in `exclude`.
+    /// Returns the upstream node's RPC endpoint along with the socket.
+    async fn connect_upstream(&self, exclude: &[String]) -> Option<(String, UpstreamSocket)> {
+        let mut tried: Vec<String> = exclude.to_vec();
+        
+        for _ in 0..UPSTREAM_CONNECT_ATTEMPTS {
+            let node = self.node_cache.select_node_excluding(&tried, MethodClass::Light, &Needs::default(), None).await?;
+            tried.push(node.endpoint.clone());
+            
+            let Some(pubsub_endpoint) = node.pubsub_endpoint() else {
+                continue;
+            };
+            
+            match tokio::time::timeout(UPSTREAM_CONNECT_TIMEOUT, connect_async(pubsub_endpoint.as_str())).await {
+                Ok(Ok((socket, _))) => {
+                    info!("🔗 [WS:{}] Connected to upstream PubSub {}", self.session_id, pubsub_endpoint);
+                    return Some((node.endpoint, socket));
+                }
+                Ok(Err(e)) => warn!("❌ [WS:{}] PubSub connection to {} failed: {}", self.session_id, pubsub_endpoint, e),
+                Err(_) => warn!("⏰ [WS:{}] PubSub connection to {} timed out", self.session_id, pubsub_endpoint),
+            }
+        }
+        
+        None
+    }
+    
+    /// Connect to another node and replay every active subscription on it.
+    /// Requests in flight on the failed connection are re-sent when they are
+    /// subscribes and answered otherwise; the returned messages are for the client.
+    async fn failover(&mut self, failed_endpoint: &str) -> (Vec<String>, Option<(String, UpstreamSocket)>) {
+        let connection = self.connect_upstream(&[failed_endpoint.to_string()]).await;
+        
+        let mut to_client = Vec::new();
+        let mut replay: Vec<(PendingRequest, RpcRequest)> = Vec::new();
+        
+        for (_, pending) in std::mem::take(&mut self.pending) {
+            match pending {
+                PendingRequest::Subscribe { client_id, method, params } if connection.is_some() => {
+                    let request = RpcRequest {
+                        jsonrpc: "2.0".to_string(),
+                        id: Value::Null,
+                        method: method.clone(),
+                        params: params.clone(),
+                    };
+                    replay.push((PendingRequest::Subscribe { client_id, method, params }, request));
+                }
+                PendingRequest::Subscribe { client_id, .. } | PendingRequest::Passthrough { client_id } => {
+                    to_client.push(error_message(client_id, -32000, "Upstream PubSub connection lost, please retry"));
+                }
+                // The upstream subscription went away with the connection
+                PendingRequest::Unsubscribe { client_id } => {
+                    to_client.push(json!({ "jsonrpc": "2.0", "id": client_id, "result": true }).to_string());
+                }
+                // Replayed below along with the other subscriptions
+                PendingRequest::Resubscribe { .. } => {}
+            }
+        }
+        self.upstream_subscriptions.clear();
+        
+        for (subscription_id, subscription) in self.subscriptions.iter_mut() {
+            subscription.upstream_id = None;
+            let request = RpcRequest {
+                jsonrpc: "2.0".to_string(),
+                id: Value::Null,
+                method: subscription.method.clone(),
+                params: subscription.params.clone(),
+            };
+            replay.push((PendingRequest::Resubscribe { subscription_id: *subscription_id, method: subscription.method.clone() }, request));
+        }
+        
+        let Some((endpoint, mut socket)) = connection else {
+            return (to_client, None);
+        };
+        
+        let total = replay.len();
+        let mut sent = 0;
+        let mut socket_failed = false;
+        for (pending, mut request) in replay {
+            let request_id = self.next_request_id();
+            request.id = json!(request_id);
+            // Kept pending even if it is never sent, the next failover replays it
+            self.pending.insert(request_id, pending);
+            if socket_failed {
+                continue;
+            }
+            
+            let text = match serde_json::to_string(&request) {
+                Ok(text) => text,
+                Err(e) => {
+                    warn!("⚠️  [WS:{}] Failed to encode {}: {}", self.session_id, request.method, e);
+                    continue;
+                }
+            };
+            // A dead socket ends the upstream stream, which triggers another failover
+            socket_failed = send_upstream(&mut socket, text, &self.session_id, &endpoint).await;
+            if !socket_failed {
+                sent += 1;
+            }
+        }
+        
+        info!("♻️  [WS:{}] Moved to {} and replayed {}/{} subscriptions",
+              self.session_id, endpoint, sent, total);
+        (to_client, Some((endpoint, socket)))
+    }
+    
+    /// Charge a request's method cost against the client's rate limit and its key's usage
+    fn charge(&self, method: &str) -> Result<(), Duration> {
+        let limits = &self.limits;
+        let cost = limits.rate_limits.method_cost(method);
+        limits.rate_limiter
+            .check(&limits.rate_limits, limits.client_ip, self.api_key.as_deref(), cost)
+            .inspect_err(|retry_after| {
+                warn!("🚦 [WS:{}] Rate limited client {} on {} (cost: {}), retry after {:?}",
+                      self.session_id, limits.client_ip, method, cost, retry_after);
+            })?;
+        
+        if let Some(api_key) = &self.api_key {
+            limits.api_keys.record_usage(api_key, 1, cost);
+        }
+        Ok(())
+    }
+    
+    fn next_request_id(&mut self) -> u64 {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        id
+    }
+    
+    /// Returns (message for the client, message for the upstream node)
+    fn handle_client_message(&mut self, text: &str) -> (Option<String>, Option<String>) {
+        let mut request: RpcRequest = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                debug!("[WS:{}] Invalid client message: {}", self.session_id, e);
+                return (Some(error_message(Value::Null, -32600, "Invalid Request")), None);
+            }
+        };
+        
+        let client_id = std::mem::replace(&mut request.id, Value::Null);
+        
+        // Unsubscribing is always allowed, the subscription itself was checked
+        if !request.method.ends_with("Unsubscribe") {
+            let rejected = match &self.api_key {
+                Some(api_key) if !api_key.allows_method(&request.method) => Some(auth::method_not_allowed(&request.method)),
+                _ => self.guards.check(&request).err().map(|rejection| rejection.rpc_error(&request.method)),
+            };
+            if let Some(error) = rejected {
+                debug!("[WS:{}] Rejected {}: {}", self.session_id, request.method, error.message);
+                return (Some(error_message(client_id, error.code, &error.message)), None);
+            }
+            
+            if let Err(retry_after) = self.charge(&request.method) {
+                return (Some(json!({
+                    "jsonrpc": "2.0",
+                    "id": client_id,
+                    "error": {
+                        "code": -32005,
+                        "message": "Rate limit exceeded",
+                        "data": { "retry_after_ms": retry_after.as_millis() }
+                    }
+                }).to_string()), None);
+            }
+        }
+        
+        if request.method.ends_with("Unsubscribe") {
+            let subscription_id = request.params
+                .as_ref()
+                .and_then(|params| params.get(0))
+                .and_then(|id| id.as_u64());
+            
+            let Some(subscription) = subscription_id.and_then(|id| self.subscriptions.remove(&id)) else {
+                return (Some(error_message(client_id, -32602, "Invalid subscription id")), None);
+            };
+            
+            debug!("[WS:{}] {} for subscription {:?}", self.session_id, request.method, subscription_id);
+            
+            match subscription.upstream_id {
+                Some(upstream_id) => {
+                    self.upstream_subscriptions.remove(&upstream_id);
+                    request.params = Some(json!([upstream_id]));
+                }
+                // Not yet resubscribed after a failover, nothing to cancel upstream
+                None => return (Some(json!({ "jsonrpc": "2.0", "id": client_id, "result": true }).to_string()), None),
+            }
+            
+            let request_id = self.next_request_id();
+            request.id = json!(request_id);
+            self.pending.insert(request_id, PendingRequest::Unsubscribe { client_id });
+            return (None, serde_json::to_string(&request).ok());
+        }
+        
+        let request_id = self.next_request_id();
+        request.id = json!(request_id);
+        
+        let pending = if request.method.ends_with("Subscribe") {
+            PendingRequest::Subscribe {
+                client_id,
+                method: request.method.clone(),
+                params: request.params.clone(),
+            }
+        } else {
+            PendingRequest::Passthrough { client_id }
+        };
+        self.pending.insert(request_id, pending);
+        
+        (None, serde_json::to_string(&request).ok())
+    }
+    
+    /// Returns (message for the client, message for the upstream node)
+    fn handle_upstream_message(&mut self, text: &str) -> (Option<String>, Option<String>) {
+        let mut message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("⚠️  [WS:{}] Invalid upstream message: {}", self.session_id, e);
+                return (None, None);
+            }
+        };
+        
+        // Notification: translate the upstream subscription id to ours
+        if let Some(method) = message.get("method").and_then(|m| m.as_str()).map(|m| m.to_string()) {
+            let upstream_id = message.pointer("/params/subscription").and_then(|id| id.as_u64());
+            let Some(subscription_id) = upstream_id.and_then(|id| self.upstream_subscriptions.get(&id).copied()) else {
+                debug!("[WS:{}] Dropping {} for unknown subscription {:?}", self.session_id, method, upstream_id);
+                return (None, None);
+            };
+            
+            message["params"]["subscription"] = json!(subscription_id);
+            
+            // signatureSubscribe is cancelled upstream after its final notification
+            let received_only = message.pointer("/params/result/value")
+                .and_then(|value| value.as_str())
+                == Some("receivedSignature");
+            if method == "signatureNotification" && !received_only {
+                self.subscriptions.remove(&subscription_id);
+                if let Some(upstream_id) = upstream_id {
+                    self.upstream_subscriptions.remove(&upstream_id);
+                }
+            }
+            
+            return (Some(message.to_string()), None);
+        }
+        
+        let Some(pending) = message.get("id").and_then(|id| id.as_u64()).and_then(|id| self.pending.remove(&id)) else {
+            debug!("[WS:{}] Dropping upstream response with unknown id", self.session_id);
+            return (None, None);
+        };
+        
+        let upstream_subscription = message.get("result").and_then(|result| result.as_u64());
+        
+        match pending {
+            PendingRequest::Subscribe { client_id, method, params } => {
+                if let Some(upstream_id) = upstream_subscription {
+                    let subscription_id = self.next_subscription_id;
+                    self.next_subscription_id += 1;
+                    self.subscriptions.insert(subscription_id, Subscription {
+                        method,
+                        params,
+                        upstream_id: Some(upstream_id),
+                    });
+                    self.upstream_subscriptions.insert(upstream_id, subscription_id);
+                    message["result"] = json!(subscription_id);
+                }
+                message["id"] = client_id;
+                (Some(message.to_string()), None)
+            }
+            PendingRequest::Resubscribe { subscription_id, method } => {
+                match upstream_subscription {
+                    Some(upstream_id) => match self.subscriptions.get_mut(&subscription_id) {
+                        Some(subscription) => {
+                            subscription.upstream_id = Some(upstream_id);
+                            self.upstream_subscriptions.insert(upstream_id, subscription_id);
+                            (None, None)
+                        }
+                        // Client unsubscribed while the resubscribe was in flight
+                        None => {
+                            let request_id = self.next_request_id();
+                            let unsubscribe = json!({
+                                "jsonrpc": "2.0",
+                                "id": request_id,
+                                "method": method.replace("Subscribe", "Unsubscribe"),
+                                "params": [upstream_id]
+                            });
+                            (None, Some(unsubscribe.to_string()))
+                        }
+                    },
+                    // The client would otherwise wait on a subscription that never notifies
+                    None => {
+                        warn!("⚠️  [WS:{}] Resubscribe of {} ({}) failed: {}",
+                              self.session_id, subscription_id, method, message);
+                        if self.subscriptions.remove(&subscription_id).is_none() {
+                            return (None, None);
+                        }
+                        let lost = json!({
+                            "jsonrpc": "2.0",
+                            "id": Value::Null,
+                            "error": {
+                                "code": -32000,
+                                "message": "Subscription lost on upstream failover, please resubscribe",
+                                "data": { "subscription": subscription_id, "method": method }
+                            }
+                        });
+                        (Some(lost.to_string()), None)
+                    }
+                }
+            }
+            PendingRequest::Unsubscribe { client_id } | PendingRequest::Passthrough { client_id } => {
+                message["id"] = client_id;
+                (Some(message.to_string()), None)
+            }
+        }
+    }
+}
+
+/// Returns true when the upstream connection is gone
+async fn send_upstream(upstream: &mut UpstreamSocket, text: String, session_id: &str, endpoint: &str) -> bool {
+    upstream.send(tungstenite::Message::Text(text)).await
+        .inspect_err(|e| warn!("⚠️  [WS:{}] Failed to send to upstream {}: {}", session_id, endpoint, e))
+        .is_err()
+}
+
+fn error_message(id: Value, code: i32, message: &str) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message
+        }
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_cache::CheckOutcome;
+    use crate::rate_limit::RateLimits;
+    use crate::types::RpcNode;
+    use tokio::sync::mpsc;
+    
+    fn session(node_cache: Arc<NodeCache>) -> PubsubSession {
+        let limits = ClientLimits {
+            client_ip: IpAddr::from([127, 0, 0, 1]),
+            rate_limits: RateLimits::new(0.0, None, Vec::new(), Vec::new()),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            api_keys: Arc::new(ApiKeys::new(Vec::new())),
+        };
+        PubsubSession::new(node_cache, None, MethodGuards::new(vec![], vec![], vec![], 0, 0, 0), limits)
+    }
+    
+    fn parse(text: Option<String>) -> Value {
+        serde_json::from_str(&text.expect("no message")).unwrap()
+    }
+    
+    /// Send a client request, returning the request the node receives
+    fn from_client(session: &mut PubsubSession, request: Value) -> Value {
+        let (to_client, to_upstream) = session.handle_client_message(&request.to_string());
+        assert_eq!(to_client, None);
+        parse(to_upstream)
+    }
+    
+    /// Deliver a node message, returning what the client receives
+    fn from_upstream(session: &mut PubsubSession, message: Value) -> Option<Value> {
+        let (to_client, to_upstream) = session.handle_upstream_message(&message.to_string());
+        assert_eq!(to_upstream, None);
+        to_client.map(|text| serde_json::from_str(&text).unwrap())
+    }
+    
+    /// Subscribe the client with request id `client_id`, the node assigning `upstream_id`.
+    /// Returns the subscription id the client was given.
+    fn subscribe(session: &mut PubsubSession, client_id: &str, upstream_id: u64) -> u64 {
+        let sent = from_client(session, json!({"jsonrpc": "2.0", "id": client_id, "method": "slotSubscribe"}));
+        let reply = from_upstream(session, json!({"jsonrpc": "2.0", "id": sent["id"], "result": upstream_id})).unwrap();
+        assert_eq!(reply["id"], json!(client_id));
+        reply["result"].as_u64().unwrap()
+    }
+    
+    #[test]
+    fn request_and_subscription_ids_are_rewritten() {
+        let mut session = session(Arc::new(NodeCache::default()));
+        
+        let sent = from_client(&mut session, json!({"jsonrpc": "2.0", "id": "a", "method": "slotSubscribe"}));
+        assert_ne!(sent["id"], json!("a"));
+        let reply = from_upstream(&mut session, json!({"jsonrpc": "2.0", "id": sent["id"], "result": 900})).unwrap();
+        assert_eq!(reply, json!({"jsonrpc": "2.0", "id": "a", "result": 1}));
+        
+        let notification = from_upstream(&mut session, json!({
+            "jsonrpc": "2.0", "method": "slotNotification", "params": {"subscription": 900, "result": {"slot": 5}}
+        })).unwrap();
+        assert_eq!(notification["params"]["subscription"], json!(1));
+        
+        let unknown = json!({"jsonrpc": "2.0", "method": "slotNotification", "params": {"subscription": 901, "result": {}}});
+        assert_eq!(from_upstream(&mut session, unknown), None);
+        
+        let sent = from_client(&mut session, json!({"jsonrpc": "2.0", "id": 7, "method": "slotUnsubscribe", "params": [1]}));
+        assert_eq!(sent["params"], json!([900]));
+        let reply = from_upstream(&mut session, json!({"jsonrpc": "2.0", "id": sent["id"], "result": true})).unwrap();
+        assert_eq!(reply, json!({"jsonrpc": "2.0", "id": 7, "result": true}));
+        assert!(session.subscriptions.is_empty() && session.upstream_subscriptions.is_empty());
+    }
+    
+    #[test]
+    fn final_signature_notification_ends_the_subscription() {
+        let mut session = session(Arc::new(NodeCache::default()));
+        let sent = from_client(&mut session, json!({"jsonrpc": "2.0", "id": 1, "method": "signatureSubscribe", "params": ["sig"]}));
+        from_upstream(&mut session, json!({"jsonrpc": "2.0", "id": sent["id"], "result": 40}));
+        
+        let notification = |value: Value| json!({
+            "jsonrpc": "2.0", "method": "signatureNotification",
+            "params": {"subscription": 40, "result": {"context": {"slot": 1}, "value": value}}
+        });
+        assert!(from_upstream(&mut session, notification(json!("receivedSignature"))).is_some());
+        assert_eq!(session.subscriptions.len(), 1);
+        assert!(from_upstream(&mut session, notification(json!({"err": null}))).is_some());
+        assert!(session.subscriptions.is_empty() && session.upstream_subscriptions.is_empty());
+    }
+    
+    #[test]
+    fn unknown_subscription_ids_are_refused() {
+        let mut session = session(Arc::new(NodeCache::default()));
+        let (to_client, to_upstream) = session.handle_client_message(
+            &json!({"jsonrpc": "2.0", "id": 3, "method": "slotUnsubscribe", "params": [42]}).to_string()
+        );
+        assert_eq!(to_upstream, None);
+        assert_eq!(parse(to_client)["error"]["code"], json!(-32602));
+    }
+    
+    /// PubSub node stand-in accepting one connection, passing on each message it receives
+    async fn upstream_node() -> (Arc<NodeCache>, mpsc::UnboundedReceiver<Value>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(tungstenite::Message::Text(text))) = socket.next().await {
+                let _ = tx.send(serde_json::from_str(&text).unwrap());
+            }
+        });
+        
+        let node_cache = Arc::new(NodeCache::default());
+        let mut node = RpcNode::new("http://127.0.0.1:1".to_string());
+        node.pubsub = Some(format!("ws://{}", address));
+        node_cache.update_node_status(node, CheckOutcome::Passed, Some(Duration::from_millis(5))).await;
+        (node_cache, rx)
+    }
+    
+    #[tokio::test]
+    async fn failover_replays_subscriptions_and_answers_requests_in_flight() {
+        let (node_cache, mut received) = upstream_node().await;
+        let mut session = session(node_cache);
+        let subscription_id = subscribe(&mut session, "a", 900);
+        from_client(&mut session, json!({"jsonrpc": "2.0", "id": "b", "method": "accountSubscribe", "params": ["acc"]}));
+        from_client(&mut session, json!({"jsonrpc": "2.0", "id": "c", "method": "getHealth"}));
+        
+        let (to_client, connection) = session.failover("http://failed").await;
+        assert!(connection.is_some());
+        let to_client: Vec<Value> = to_client.iter().map(|text| serde_json::from_str(text).unwrap()).collect();
+        assert_eq!(to_client.len(), 1);
+        assert_eq!(to_client[0]["id"], json!("c"));
+        
+        let mut replayed = HashMap::new();
+        for _ in 0..2 {
+            let request = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+            replayed.insert(request["method"].as_str().unwrap().to_string(), request);
+        }
+        
+        // The pending subscribe completes for the client as if nothing happened
+        let subscribe = &replayed["accountSubscribe"];
+        assert_eq!(subscribe["params"], json!(["acc"]));
+        let reply = from_upstream(&mut session, json!({"jsonrpc": "2.0", "id": subscribe["id"], "result": 31})).unwrap();
+        assert_eq!(reply["id"], json!("b"));
+        
+        // The replayed subscription keeps its id, notifications follow the new upstream id
+        assert_eq!(session.subscriptions[&subscription_id].upstream_id, None);
+        let resubscribe = &replayed["slotSubscribe"];
+        assert_eq!(from_upstream(&mut session, json!({"jsonrpc": "2.0", "id": resubscribe["id"], "result": 77})), None);
+        let notification = from_upstream(&mut session, json!({
+            "jsonrpc": "2.0", "method": "slotNotification", "params": {"subscription": 77, "result": {"slot": 9}}
+        })).unwrap();
+        assert_eq!(notification["params"]["subscription"], json!(subscription_id));
+    }
+    
+    #[tokio::test]
+    async fn failover_without_a_node_answers_requests_in_flight() {
+        let mut session = session(Arc::new(NodeCache::default()));
+        subscribe(&mut session, "a", 900);
+        from_client(&mut session, json!({"jsonrpc": "2.0", "id": "b", "method": "accountSubscribe", "params": ["acc"]}));
+        from_client(&mut session, json!({"jsonrpc": "2.0", "id": "c", "method": "slotUnsubscribe", "params": [1]}));
+        
+        let (to_client, connection) = session.failover("http://failed").await;
+        assert!(connection.is_none());
+        let mut to_client: Vec<Value> = to_client.iter().map(|text| serde_json::from_str(text).unwrap()).collect();
+        to_client.sort_by_key(|message| message["id"].to_string());
+        assert_eq!(to_client[0]["id"], json!("b"));
+        assert_eq!(to_client[0]["error"]["code"], json!(-32000));
+        assert_eq!(to_client[1], json!({"jsonrpc": "2.0", "id": "c", "result": true}));
+    }
+    
+    /// Session that lost its upstream with one subscription, returning its id and the
+    /// request id of the resubscribe in flight
+    fn resubscribing() -> (PubsubSession, u64, u64) {
+        let mut session = session(Arc::new(NodeCache::default()));
+        let subscription_id = subscribe(&mut session, "a", 900);
+        
+        session.upstream_subscriptions.clear();
+        session.subscriptions.get_mut(&subscription_id).unwrap().upstream_id = None;
+        let request_id = session.next_request_id();
+        session.pending.insert(request_id, PendingRequest::Resubscribe { subscription_id, method: "slotSubscribe".to_string() });
+        (session, subscription_id, request_id)
+    }
+    
+    #[test]
+    fn unsubscribing_during_a_resubscribe_cancels_it_upstream() {
+        let (mut session, subscription_id, request_id) = resubscribing();
+        
+        let (to_client, to_upstream) = session.handle_client_message(
+            &json!({"jsonrpc": "2.0", "id": 5, "method": "slotUnsubscribe", "params": [subscription_id]}).to_string()
+        );
+        assert_eq!(to_upstream, None);
+        assert_eq!(parse(to_client), json!({"jsonrpc": "2.0", "id": 5, "result": true}));
+        
+        let (to_client, to_upstream) = session.handle_upstream_message(
+            &json!({"jsonrpc": "2.0", "id": request_id, "result": 66}).to_string()
+        );
+        assert_eq!(to_client, None);
+        let unsubscribe = parse(to_upstream);
+        assert_eq!(unsubscribe["method"], json!("slotUnsubscribe"));
+        assert_eq!(unsubscribe["params"], json!([66]));
+    }
+    
+    #[test]
+    fn failed_resubscribe_is_reported_to_the_client() {
+        let (mut session, subscription_id, request_id) = resubscribing();
+        
+        let lost = from_upstream(&mut session, json!({
+            "jsonrpc": "2.0", "id": request_id, "error": {"code": -32602, "message": "Invalid params"}
+        })).unwrap();
+        assert_eq!(lost["id"], Value::Null);
+        assert_eq!(lost["error"]["data"]["subscription"], json!(subscription_id));
+        assert!(session.subscriptions.is_empty());
+    }
+}
@@ -0,0 +1,356 @@
+use anyhow::Result;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::auth::ApiKey;
+
+/// Clients idle this long are forgotten, their bucket would be full again anyway
+const IDLE_CLIENT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Request cost of methods that are expensive for nodes to serve, overridable with `--method-cost`
+const DEFAULT_METHOD_COSTS: [(&str, u32); 9] = [
+    ("getProgramAccounts", 10),
+    ("getLargestAccounts", 10),
+    ("getTokenLargestAccounts", 5),
+    ("getTokenAccountsByOwner", 5),
+    ("getTokenAccountsByDelegate", 5),
+    ("getSignaturesForAddress", 5),
+    ("getBlock", 5),
+    ("getBlocks", 3),
+    ("getMultipleAccounts", 3),
+];
+
+/// Proxy address or network (`10.0.0.1` or `10.0.0.0/8`) whose X-Forwarded-For header is trusted
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for TrustedProxy {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len.parse::<u8>()?)),
+            None => (s, None),
+        };
+        let network: IpAddr = address.parse()?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_prefix_len);
+        if prefix_len > max_prefix_len {
+            return Err(anyhow::anyhow!("prefix length {} is too long for {}", prefix_len, network));
+        }
+        Ok(Self { network, prefix_len })
+    }
+}
+
+impl TryFrom<String> for TrustedProxy {
+    type Error = anyhow::Error;
+    
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl TrustedProxy {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => (u32::from(network) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            _ => return false,
+        };
+        let host_bits = bits - self.prefix_len as u32;
+        host_bits >= bits || network >> host_bits == ip >> host_bits
+    }
+}
+
+/// Rate limit cost of a method, given on the command line as `method:cost`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MethodCost {
+    pub method: String,
+    pub cost: u32,
+}
+
+impl FromStr for MethodCost {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((method, cost)) = s.split_once(':') else {
+            return Err(anyhow::anyhow!("expected method:cost, got '{}'", s));
+        };
+        Ok(Self {
+            method: method.to_string(),
+            cost: cost.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Limit {
+    rps: f64,
+    burst: f64,
+}
+
+impl Limit {
+    /// `burst` defaults to two seconds worth of requests
+    fn new(rps: f64, burst: Option<u32>) -> Self {
+        Self { rps, burst: burst.map(f64::from).unwrap_or((rps * 2.0).ceil()).max(1.0) }
+    }
+}
+
+/// Token bucket limits per client. Clients are identified by their API key
+/// when it has a limit of its own, otherwise by IP address.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    per_ip: Option<Limit>,
+    method_costs: HashMap<String, u32>,
+    trusted_proxies: Vec<TrustedProxy>,
+}
+
+impl RateLimits {
+    /// `rps` of 0 leaves IP-identified clients unlimited. `burst` defaults to two seconds worth of requests.
+    pub fn new(
+        rps: f64,
+        burst: Option<u32>,
+        method_costs: Vec<MethodCost>,
+        trusted_proxies: Vec<TrustedProxy>,
+    ) -> Self {
+        Self {
+            per_ip: (rps > 0.0).then(|| Limit::new(rps, burst)),
+            method_costs: DEFAULT_METHOD_COSTS
+                .iter()
+                .map(|(method, cost)| (method.to_string(), *cost))
+                .chain(method_costs.into_iter().map(|method_cost| (method_cost.method, method_cost.cost)))
+                .collect(),
+            trusted_proxies,
+        }
+    }
+    
+    pub fn method_cost(&self, method: &str) -> u32 {
+        self.method_costs.get(method).copied().unwrap_or(1)
+    }
+    
+    /// Client address, taken from X-Forwarded-For when the connection comes from a trusted proxy
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.trusted_proxies.iter().any(|proxy| proxy.contains(peer)) {
+            return peer;
+        }
+        
+        // Walk back from the closest hop, the first untrusted address is the client
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        
+        forwarded
+            .iter()
+            .rev()
+            .find(|hop| !self.trusted_proxies.iter().any(|proxy| proxy.contains(**hop)))
+            .or(forwarded.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets for the clients seen recently
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Take `cost` tokens from the client's bucket. On rejection, returns how
+    /// long until the request would be admitted.
+    pub fn check(
+        &self,
+        limits: &RateLimits,
+        client_ip: IpAddr,
+        api_key: Option<&ApiKey>,
+        cost: u32,
+    ) -> Result<(), Duration> {
+        let key_limit = api_key.and_then(|api_key| Some((api_key, Limit::new(api_key.rps.filter(|rps| *rps > 0.0)?, api_key.burst))));
+        let (client, limit) = match key_limit {
+            Some((api_key, limit)) => (format!("key:{}", api_key.name), limit),
+            None => match limits.per_ip {
+                Some(limit) => (format!("ip:{}", client_ip), limit),
+                None => return Ok(()),
+            },
+        };
+        
+        // A request costing more than the burst could never be admitted
+        let cost = (cost as f64).min(limit.burst);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: limit.burst, updated: now });
+        
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rps).min(limit.burst);
+        bucket.updated = now;
+        
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((cost - bucket.tokens) / limit.rps))
+        }
+    }
+    
+    /// Forget clients that haven't sent requests for a while
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| now.duration_since(bucket.updated) < IDLE_CLIENT_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+    
+    fn limits(rps: f64, burst: Option<u32>) -> RateLimits {
+        RateLimits::new(rps, burst, Vec::new(), Vec::new())
+    }
+    
+    #[test]
+    fn trusted_proxy_matches_its_network() {
+        let network: TrustedProxy = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains(ip("10.1.0.1")));
+        assert!(network.contains(ip("10.1.255.255")));
+        assert!(!network.contains(ip("10.2.0.1")));
+        assert!(!network.contains(ip("::ffff:10.1.0.1")));
+        
+        let host: TrustedProxy = "192.168.1.5".parse().unwrap();
+        assert!(host.contains(ip("192.168.1.5")));
+        assert!(!host.contains(ip("192.168.1.6")));
+        
+        let v6: TrustedProxy = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12:3456::1")));
+        assert!(!v6.contains(ip("fe80::1")));
+        assert!(!v6.contains(ip("10.0.0.1")));
+    }
+    
+    #[test]
+    fn zero_prefix_trusts_every_address_of_the_family() {
+        let any_v4: TrustedProxy = "0.0.0.0/0".parse().unwrap();
+        assert!(any_v4.contains(ip("1.2.3.4")));
+        assert!(any_v4.contains(ip("255.255.255.255")));
+        assert!(!any_v4.contains(ip("::1")));
+        
+        let any_v6: TrustedProxy = "::/0".parse().unwrap();
+        assert!(any_v6.contains(ip("2001:db8::1")));
+    }
+    
+    #[test]
+    fn trusted_proxy_rejects_invalid_prefixes() {
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert!("::/129".parse::<TrustedProxy>().is_err());
+        assert!("10.0.0.0/x".parse::<TrustedProxy>().is_err());
+        assert!("not-an-ip".parse::<TrustedProxy>().is_err());
+    }
+    
+    #[test]
+    fn client_ip_comes_from_forwarded_for_only_behind_trusted_proxies() {
+        let limits = RateLimits::new(0.0, None, Vec::new(), vec!["10.0.0.0/8".parse().unwrap()]);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4, 5.6.7.8, 10.0.0.2".parse().unwrap());
+        
+        assert_eq!(limits.client_ip(ip("10.0.0.1"), &headers), ip("5.6.7.8"));
+        assert_eq!(limits.client_ip(ip("9.9.9.9"), &headers), ip("9.9.9.9"));
+    }
+    
+    #[test]
+    fn bucket_admits_the_burst_then_rejects() {
+        let limits = limits(1.0, Some(3));
+        let limiter = RateLimiter::new();
+        let client = ip("1.2.3.4");
+        
+        for _ in 0..3 {
+            assert!(limiter.check(&limits, client, None, 1).is_ok());
+        }
+        let retry_after = limiter.check(&limits, client, None, 1).unwrap_err();
+        assert!(retry_after > Duration::from_millis(900) && retry_after <= Duration::from_secs(1));
+        
+        // Other clients have buckets of their own
+        assert!(limiter.check(&limits, ip("5.6.7.8"), None, 1).is_ok());
+    }
+    
+    #[test]
+    fn bucket_charges_the_cost_and_caps_it_at_the_burst() {
+        let limits = limits(10.0, Some(5));
+        let limiter = RateLimiter::new();
+        let client = ip("1.2.3.4");
+        
+        assert!(limiter.check(&limits, client, None, 4).is_ok());
+        assert!(limiter.check(&limits, client, None, 4).is_err());
+        
+        // A cost above the burst is admitted once the bucket is full
+        let fresh = ip("5.6.7.8");
+        assert!(limiter.check(&limits, fresh, None, 100).is_ok());
+        assert!(limiter.check(&limits, fresh, None, 1).is_err());
+    }
+    
+    #[test]
+    fn bucket_refills_over_time() {
+        let limits = limits(100.0, Some(1));
+        let limiter = RateLimiter::new();
+        let client = ip("1.2.3.4");
+        
+        assert!(limiter.check(&limits, client, None, 1).is_ok());
+        assert!(limiter.check(&limits, client, None, 1).is_err());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.check(&limits, client, None, 1).is_ok());
+    }
+    
+    #[test]
+    fn keys_with_a_limit_get_their_own_bucket() {
+        let limits = limits(0.0, None);
+        let limiter = RateLimiter::new();
+        let client = ip("1.2.3.4");
+        let mut api_key: ApiKey = "acme:secret".parse().unwrap();
+        
+        // No per-IP limit and no key limit: unlimited
+        for _ in 0..100 {
+            assert!(limiter.check(&limits, client, Some(&api_key), 1).is_ok());
+        }
+        
+        api_key.rps = Some(1.0);
+        api_key.burst = Some(2);
+        assert!(limiter.check(&limits, client, Some(&api_key), 1).is_ok());
+        assert!(limiter.check(&limits, ip("5.6.7.8"), Some(&api_key), 1).is_ok());
+        assert!(limiter.check(&limits, client, Some(&api_key), 1).is_err());
+    }
+    
+    #[test]
+    fn default_method_costs_can_be_overridden() {
+        let limits = RateLimits::new(1.0, None, vec!["getBlock:2".parse().unwrap()], Vec::new());
+        assert_eq!(limits.method_cost("getProgramAccounts"), 10);
+        assert_eq!(limits.method_cost("getBlock"), 2);
+        assert_eq!(limits.method_cost("getSlot"), 1);
+    }
+}
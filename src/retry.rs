@@ -0,0 +1,173 @@
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Request header a client sets to allow retries of never-retry methods such as `sendTransaction`
+pub const RETRY_OPT_IN_HEADER: &str = "x-allow-retry";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one
+    pub max_attempts: u32,
+    /// Time budget across all attempts, measured from when forwarding starts
+    pub budget: Duration,
+}
+
+impl RetryPolicy {
+    pub fn single_attempt(budget: Duration) -> Self {
+        Self {
+            max_attempts: 1,
+            budget,
+        }
+    }
+    
+    /// Timeout for the next attempt, never outliving the budget left after `elapsed`
+    pub fn attempt_timeout(&self, request_timeout: Duration, elapsed: Duration) -> Duration {
+        request_timeout.min(self.budget.saturating_sub(elapsed))
+    }
+    
+    /// Whether another attempt may follow `attempt` (1-based) once `elapsed` has passed
+    pub fn allows_retry(&self, attempt: u32, elapsed: Duration) -> bool {
+        attempt < self.max_attempts && elapsed < self.budget
+    }
+}
+
+/// Per-method override given on the command line as `method:max_attempts:budget_secs`
+#[derive(Debug, Clone)]
+pub struct MethodRetryPolicy {
+    pub method: String,
+    pub policy: RetryPolicy,
+}
+
+impl FromStr for MethodRetryPolicy {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 3 || parts[0].is_empty() {
+            return Err(anyhow::anyhow!("expected method:max_attempts:budget_secs, got '{}'", s));
+        }
+        
+        let max_attempts: u32 = parts[1].parse()?;
+        if max_attempts == 0 {
+            return Err(anyhow::anyhow!("max_attempts must be at least 1"));
+        }
+        
+        Ok(Self {
+            method: parts[0].to_string(),
+            policy: RetryPolicy {
+                max_attempts,
+                budget: Duration::from_secs(parts[2].parse()?),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicies {
+    default: RetryPolicy,
+    per_method: HashMap<String, RetryPolicy>,
+    never_retry: HashSet<String>,
+}
+
+impl RetryPolicies {
+    pub fn new(default: RetryPolicy, overrides: Vec<MethodRetryPolicy>, never_retry: Vec<String>) -> Self {
+        Self {
+            default,
+            per_method: overrides
+                .into_iter()
+                .map(|o| (o.method, o.policy))
+                .collect(),
+            never_retry: never_retry.into_iter().collect(),
+        }
+    }
+    
+    /// Policy for `method`. Never-retry methods get a single attempt unless the client opted in.
+    pub fn for_method(&self, method: &str, client_opt_in: bool) -> RetryPolicy {
+        let policy = self.per_method.get(method).copied().unwrap_or(self.default);
+        
+        if self.never_retry.contains(method) && !client_opt_in {
+            RetryPolicy::single_attempt(policy.budget)
+        } else {
+            policy
+        }
+    }
+    
+    /// Most restrictive policy across `methods`, used when they travel upstream together
+    pub fn for_methods<'a>(&self, methods: impl IntoIterator<Item = &'a str>, client_opt_in: bool) -> RetryPolicy {
+        methods
+            .into_iter()
+            .map(|method| self.for_method(method, client_opt_in))
+            .reduce(|acc, policy| RetryPolicy {
+                max_attempts: acc.max_attempts.min(policy.max_attempts),
+                budget: acc.budget.min(policy.budget),
+            })
+            .unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn policy(max_attempts: u32, budget_secs: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            budget: Duration::from_secs(budget_secs),
+        }
+    }
+    
+    fn policies() -> RetryPolicies {
+        RetryPolicies::new(
+            policy(3, 10),
+            vec!["getProgramAccounts:2:30".parse().unwrap()],
+            vec!["sendTransaction".to_string()],
+        )
+    }
+    
+    #[test]
+    fn method_overrides_are_parsed() {
+        let parsed: MethodRetryPolicy = "getBlock:4:20".parse().unwrap();
+        assert_eq!(parsed.method, "getBlock");
+        assert_eq!(parsed.policy, policy(4, 20));
+        
+        for invalid in ["getBlock:4", ":4:20", "getBlock:0:20", "getBlock:x:20", "getBlock:4:-1"] {
+            assert!(invalid.parse::<MethodRetryPolicy>().is_err(), "{}", invalid);
+        }
+    }
+    
+    #[test]
+    fn never_retry_methods_keep_their_budget_in_a_single_attempt() {
+        assert_eq!(policies().for_method("sendTransaction", false), policy(1, 10));
+        assert_eq!(policies().for_method("sendTransaction", true), policy(3, 10));
+        assert_eq!(policies().for_method("getProgramAccounts", false), policy(2, 30));
+        assert_eq!(policies().for_method("getSlot", false), policy(3, 10));
+    }
+    
+    #[test]
+    fn attempts_stay_within_the_budget() {
+        let policy = policy(3, 10);
+        let request_timeout = Duration::from_secs(4);
+        assert_eq!(policy.attempt_timeout(request_timeout, Duration::from_secs(1)), request_timeout);
+        assert_eq!(policy.attempt_timeout(request_timeout, Duration::from_secs(8)), Duration::from_secs(2));
+        assert_eq!(policy.attempt_timeout(request_timeout, Duration::from_secs(12)), Duration::ZERO);
+    }
+    
+    #[test]
+    fn retries_stop_at_max_attempts_or_the_budget() {
+        let policy = policy(3, 10);
+        assert!(policy.allows_retry(1, Duration::from_secs(1)));
+        assert!(policy.allows_retry(2, Duration::from_secs(9)));
+        assert!(!policy.allows_retry(3, Duration::from_secs(1)));
+        assert!(!policy.allows_retry(1, Duration::from_secs(10)));
+        assert!(!RetryPolicy::single_attempt(Duration::from_secs(10)).allows_retry(1, Duration::ZERO));
+    }
+    
+    #[test]
+    fn batches_get_the_most_restrictive_policy() {
+        assert_eq!(policies().for_methods(["getProgramAccounts", "getSlot"], false), policy(2, 10));
+        assert_eq!(policies().for_methods(["getProgramAccounts", "sendTransaction"], false), policy(1, 10));
+        assert_eq!(policies().for_methods([], false), policy(3, 10));
+    }
+}
@@ -1,107 +1,648 @@
 use anyhow::Result;
-use reqwest::Client;
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use reqwest::{Client, StatusCode};
 use serde_json::json;
 use std::time::Duration;
+use tokio::time::Instant;
 use tracing::{debug, error};
 
-use crate::types::{RpcRequest, RpcResponse};
-
-pub async fn test_rpc_node(endpoint: &str, timeout_secs: u64) -> Result<()> {
-    let client = Client::new();
-    
-    // use more strict rpc call to validate if the node is a full rpc node
-    let test_request = RpcRequest {
-        jsonrpc: "2.0".to_string(),
-        id: json!(1),
-        method: "getTokenAccountsByOwner".to_string(),
-        params: Some(json!([
-            "A1TMhSGzQxMr1TboBKtgixKz1sS6REASMxPo1qsyTSJd",
-            {
-                "programId": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
-            },
-            {
-                "encoding": "jsonParsed"
+use crate::fault::{rpc_error_fault, status_fault, Fault};
+use crate::probe::{Probe, ProbeReport};
+use crate::version::Version;
+use crate::types::{NodeCapabilities, NodeSlots, ProbeResult, RpcError, RpcRequest, RpcResponse};
+
+/// How often idle connections are probed (TCP keepalive, HTTP/2 pings) so dead ones are noticed before a request lands on them
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Account whose token accounts and signatures capability probes ask for
+const PROBE_ACCOUNT: &str = "A1TMhSGzQxMr1TboBKtgixKz1sS6REASMxPo1qsyTSJd";
+
+const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+/// Non-2xx bodies are only read for the JSON-RPC error they may carry
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+/// Connection settings for upstream RPC nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamClientConfig {
+    /// Idle keep-alive connections kept per node
+    pub pool_max_idle_per_node: usize,
+    /// How long an idle connection is kept before being closed
+    pub pool_idle_timeout: Duration,
+    /// Limit on establishing a connection, separate from the request timeout
+    pub connect_timeout: Duration,
+    /// Negotiate HTTP/2 with nodes that offer it over TLS, HTTP/1.1 otherwise
+    pub http2: bool,
+}
+
+/// Limits on reading an upstream response body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyLimits {
+    /// Bodies up to this size are held in memory, larger ones are streamed to the client
+    pub buffer_bytes: usize,
+    /// Bodies are cut off beyond this size, 0 disables the limit
+    pub max_bytes: usize,
+    /// Time allowed for receiving the body once the response headers arrived
+    pub transfer_time: Duration,
+}
+
+/// Successful upstream response to a single request
+pub enum UpstreamResponse {
+    Buffered(Bytes),
+    /// Too large to hold in memory, passed through to the client as it arrives
+    Streaming(StreamingBody),
+}
+
+/// Upstream body of which only the first chunks have been read
+pub struct StreamingBody {
+    prefix: Bytes,
+    response: reqwest::Response,
+    received: usize,
+    max_bytes: usize,
+    deadline: Instant,
+}
+
+impl StreamingBody {
+    /// The whole body, ending with an error if the size or transfer time limit is hit
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes, ForwardError>> + Send + 'static {
+        let StreamingBody { prefix, response, received, max_bytes, deadline } = self;
+        
+        let rest = futures_util::stream::unfold(Some((response, received)), move |state| async move {
+            let (mut response, received) = state?;
+            match tokio::time::timeout_at(deadline, response.chunk()).await {
+                Ok(Ok(Some(chunk))) => {
+                    let received = received + chunk.len();
+                    if max_bytes > 0 && received > max_bytes {
+                        return Some((Err(ForwardError::TooLarge(max_bytes)), None));
+                    }
+                    Some((Ok(chunk), Some((response, received))))
+                }
+                Ok(Ok(None)) => None,
+                Ok(Err(e)) => Some((Err(e.into()), None)),
+                Err(_) => Some((Err(ForwardError::Timeout("response body transfer time exceeded".to_string())), None)),
+            }
+        });
+        
+        futures_util::stream::once(async move { Ok(prefix) }).chain(rest)
+    }
+}
+
+/// Read `response` until the body ends, or until it grows past `buffer_bytes`
+/// and has to be streamed instead
+async fn read_body(mut response: reqwest::Response, limits: &BodyLimits, buffer_bytes: usize) -> Result<UpstreamResponse, ForwardError> {
+    let too_large = |length: usize| limits.max_bytes > 0 && length > limits.max_bytes;
+    if response.content_length().is_some_and(|length| too_large(length as usize)) {
+        return Err(ForwardError::TooLarge(limits.max_bytes));
+    }
+    
+    let deadline = Instant::now() + limits.transfer_time;
+    let mut body = Vec::new();
+    loop {
+        let chunk = tokio::time::timeout_at(deadline, response.chunk())
+            .await
+            .map_err(|_| ForwardError::Timeout("response body transfer time exceeded".to_string()))??;
+        let Some(chunk) = chunk else {
+            return Ok(UpstreamResponse::Buffered(Bytes::from(body)));
+        };
+        
+        body.extend_from_slice(&chunk);
+        if too_large(body.len()) {
+            return Err(ForwardError::TooLarge(limits.max_bytes));
+        }
+        if body.len() > buffer_bytes {
+            return Ok(UpstreamResponse::Streaming(StreamingBody {
+                received: body.len(),
+                prefix: Bytes::from(body),
+                response,
+                max_bytes: limits.max_bytes,
+                deadline,
+            }));
+        }
+    }
+}
+
+/// JSON-RPC error in a raw single response body, if it carries one
+fn rpc_error(body: &[u8]) -> Option<RpcError> {
+    #[derive(serde::Deserialize)]
+    struct ErrorProbe {
+        error: Option<RpcError>,
+    }
+    
+    serde_json::from_slice::<ErrorProbe>(body).ok()?.error
+}
+
+/// JSON-RPC error in the body of a non-2xx response, if it carries one
+async fn read_error_body(response: reqwest::Response, limits: &BodyLimits) -> Option<RpcError> {
+    let limits = BodyLimits { max_bytes: MAX_ERROR_BODY_BYTES, ..*limits };
+    match read_body(response, &limits, usize::MAX).await {
+        Ok(UpstreamResponse::Buffered(body)) => rpc_error(&body),
+        _ => None,
+    }
+}
+
+/// HTTP client for upstream RPC nodes, shared by request forwarding and
+/// health checks. Connections are pooled per node and kept alive between
+/// requests; clones share the same pool.
+#[derive(Clone)]
+pub struct UpstreamClient {
+    client: Client,
+}
+
+/// Failure while forwarding a request upstream, classified so callers can
+/// decide whether another node is worth trying.
+#[derive(Debug, thiserror::Error)]
+pub enum ForwardError {
+    #[error("connection failed: {0}")]
+    Connect(reqwest::Error),
+    #[error("request timed out: {0}")]
+    Timeout(String),
+    /// Non-2xx status, or a JSON-RPC error another node may not give
+    #[error("RPC forwarding failed: {status}{}", describe_rpc_error(.error))]
+    Upstream { status: StatusCode, error: Option<RpcError> },
+    #[error("invalid upstream response: {0}")]
+    InvalidResponse(String),
+    #[error("response larger than {0} bytes")]
+    TooLarge(usize),
+    #[error("request failed: {0}")]
+    Request(reqwest::Error),
+}
+
+impl From<reqwest::Error> for ForwardError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            ForwardError::Connect(e)
+        } else if e.is_timeout() {
+            ForwardError::Timeout(e.to_string())
+        } else {
+            ForwardError::Request(e)
+        }
+    }
+}
+
+fn describe_rpc_error(error: &Option<RpcError>) -> String {
+    error
+        .as_ref()
+        .map(|error| format!(" (JSON-RPC error {}: {})", error.code, error.message))
+        .unwrap_or_default()
+}
+
+impl ForwardError {
+    pub fn fault(&self) -> Fault {
+        match self {
+            ForwardError::Connect(_) | ForwardError::Timeout(_) | ForwardError::InvalidResponse(_) | ForwardError::Request(_) => Fault::Node,
+            ForwardError::Upstream { status, error } => {
+                let error_fault = error.as_ref().map_or(Fault::Request, |error| rpc_error_fault(error.code));
+                match *status {
+                    status if status.is_success() => error_fault,
+                    // a throttling node says nothing about its health, whatever error it sends along
+                    StatusCode::TOO_MANY_REQUESTS => Fault::Unavailable,
+                    status => status_fault(status).max(error_fault),
+                }
             }
-        ])),
-    };
-    
-    debug!("🔍 Testing full RPC node: {} (getTokenAccountsByOwner timeout: {}s)", endpoint, timeout_secs);
-    
-    let response = client
-        .post(endpoint)
-        .json(&test_request)
-        .timeout(Duration::from_secs(timeout_secs))
-        .send()
-        .await?;
-    
-    if response.status().is_success() {
-        let rpc_response: RpcResponse = response.json().await?;
-        
-        // check if the response is valid (has result or specific error)
-        if rpc_response.result.is_some() {
-            debug!("✅ Full RPC node {} validation passed (has token account data)", endpoint);
-            Ok(())
-        } else if let Some(error) = &rpc_response.error {
-            // in some cases, the node may return specific errors but still be a valid full node
-            // for example, account not found, invalid params, etc. these errors indicate that the node can handle such requests
-            match error.code {
-                -32602 => {
-                    // Invalid params - the node can handle the request but the params are invalid
-                    debug!("✅ Full RPC node {} validation passed (processed request, invalid params is acceptable)", endpoint);
-                    Ok(())
+            // another node would send the same oversized body
+            ForwardError::TooLarge(_) => Fault::Request,
+        }
+    }
+    
+    /// Whether another node may succeed where this one failed
+    pub fn is_retryable(&self) -> bool {
+        self.fault().is_retryable()
+    }
+    
+    /// Whether the node itself is at fault and should stop receiving traffic
+    pub fn is_node_failure(&self) -> bool {
+        self.fault().is_node_failure()
+    }
+}
+
+fn parse_genesis_hash(result: &serde_json::Value) -> Option<String> {
+    result.as_str().map(str::to_string)
+}
+
+/// `getVersion` result as the node's version and feature set
+fn parse_version(result: &serde_json::Value) -> Option<(Version, Option<u32>)> {
+    let version = result.get("solana-core")?.as_str()?.parse().ok()?;
+    let feature_set = result.get("feature-set")
+        .and_then(|feature_set| feature_set.as_u64())
+        .and_then(|feature_set| u32::try_from(feature_set).ok());
+    Some((version, feature_set))
+}
+
+impl UpstreamClient {
+    pub fn new(config: &UpstreamClientConfig) -> Result<Self> {
+        let builder = Client::builder()
+            .pool_max_idle_per_host(config.pool_max_idle_per_node)
+            .pool_idle_timeout(config.pool_idle_timeout)
+            .connect_timeout(config.connect_timeout)
+            .tcp_nodelay(true)
+            .tcp_keepalive(KEEP_ALIVE_INTERVAL);
+        
+        let builder = if config.http2 {
+            builder
+                .http2_adaptive_window(true)
+                .http2_keep_alive_interval(KEEP_ALIVE_INTERVAL)
+                .http2_keep_alive_while_idle(true)
+        } else {
+            builder.http1_only()
+        };
+        
+        Ok(Self { client: builder.build()? })
+    }
+    
+    /// Run every probe against `endpoint` concurrently. The node's genesis hash and
+    /// version come from the `getGenesisHash` and `getVersion` probes, and are only
+    /// asked for separately when there is no such probe.
+    pub async fn run_probes(&self, endpoint: &str, probes: &[Probe], timeout_secs: u64) -> ProbeReport {
+        let outcomes = futures_util::future::join_all(probes.iter().map(|probe| async move {
+            let start = Instant::now();
+            let response = self.call(endpoint, &probe.method, probe.params.clone(), timeout_secs).await;
+            let error = match &response {
+                Ok(response) => probe.violation(response),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(error) = &error {
+                debug!("🔍 RPC node {} failed probe {}: {}", endpoint, probe.name(), error);
+            }
+            let result = ProbeResult {
+                name: probe.name().to_string(),
+                passed: error.is_none(),
+                required: probe.required,
+                weight: probe.weight,
+                latency_ms: start.elapsed().as_millis() as u64,
+                error,
+            };
+            (result, response.ok().and_then(|response| response.result))
+        }))
+        .await;
+        
+        // `Some` when the suite asked for `method`, holding the node's answer if it gave one
+        let answer = |method: &str| {
+            probes
+                .iter()
+                .zip(&outcomes)
+                .find(|(probe, _)| probe.method == method && probe.params.is_none())
+                .map(|(_, (_, answer))| answer.as_ref())
+        };
+        let genesis_hash = async {
+            match answer("getGenesisHash") {
+                Some(answer) => answer.and_then(parse_genesis_hash),
+                None => self.get_genesis_hash(endpoint, timeout_secs).await,
+            }
+        };
+        let version = async {
+            match answer("getVersion") {
+                Some(answer) => answer.and_then(parse_version),
+                None => self.get_version(endpoint, timeout_secs).await,
+            }
+        };
+        let (genesis_hash, version) = tokio::join!(genesis_hash, version);
+        
+        ProbeReport {
+            results: outcomes.into_iter().map(|(result, _)| result).collect(),
+            genesis_hash,
+            version,
+        }
+    }
+    
+    /// Query `getSlot` at every commitment level. Commitments the node fails to
+    /// answer are left empty rather than failing the whole check.
+    pub async fn get_node_slots(&self, endpoint: &str, timeout_secs: u64) -> NodeSlots {
+        let [processed, confirmed, finalized] = NodeSlots::COMMITMENTS.map(|commitment| async move {
+            let params = json!([{ "commitment": commitment }]);
+            self.probe(endpoint, "getSlot", Some(params), timeout_secs).await?.as_u64()
+        });
+        let (processed, confirmed, finalized) = tokio::join!(processed, confirmed, finalized);
+        let slots = NodeSlots { processed, confirmed, finalized };
+        
+        debug!("🎰 RPC node {} slots: {:?}", endpoint, slots);
+        slots
+    }
+    
+    /// Probe which history and index-backed requests the node can answer. A probe
+    /// the node errors on or doesn't answer within `timeout_secs` counts as unsupported.
+    /// Scanning nodes without an index rarely answer index probes in time; with one,
+    /// the probes ask about an account that owns nothing, so they cost next to nothing.
+    pub async fn probe_capabilities(&self, endpoint: &str, timeout_secs: u64) -> NodeCapabilities {
+        let minimum_ledger_slot = self.probe(endpoint, "minimumLedgerSlot", None, timeout_secs);
+        let first_available_block = self.probe(endpoint, "getFirstAvailableBlock", None, timeout_secs);
+        let spl_token_owner_index = self.probe(endpoint, "getTokenAccountsByOwner", Some(json!([
+            PROBE_ACCOUNT,
+            { "programId": TOKEN_PROGRAM_ID },
+            { "encoding": "base64", "dataSlice": { "offset": 0, "length": 0 } }
+        ])), timeout_secs);
+        let program_id_index = self.probe(endpoint, "getProgramAccounts", Some(json!([
+            PROBE_ACCOUNT,
+            { "encoding": "base64", "dataSlice": { "offset": 0, "length": 0 } }
+        ])), timeout_secs);
+        let transaction_history = self.probe(endpoint, "getSignaturesForAddress", Some(json!([
+            PROBE_ACCOUNT,
+            { "limit": 1 }
+        ])), timeout_secs);
+        
+        let (minimum_ledger_slot, first_available_block, spl_token_owner_index, program_id_index, transaction_history) = tokio::join!(
+            minimum_ledger_slot, first_available_block, spl_token_owner_index, program_id_index, transaction_history
+        );
+        
+        let capabilities = NodeCapabilities {
+            minimum_ledger_slot: minimum_ledger_slot.and_then(|slot| slot.as_u64()),
+            first_available_block: first_available_block.and_then(|slot| slot.as_u64()),
+            spl_token_owner_index: spl_token_owner_index.is_some(),
+            program_id_index: program_id_index.is_some(),
+            transaction_history: transaction_history.is_some(),
+            checked_at: None,
+            checked_version: None,
+        };
+        debug!("🧭 RPC node {} capabilities: {:?}", endpoint, capabilities);
+        capabilities
+    }
+    
+    /// Genesis hash of the cluster the node belongs to
+    pub async fn get_genesis_hash(&self, endpoint: &str, timeout_secs: u64) -> Option<String> {
+        parse_genesis_hash(&self.probe(endpoint, "getGenesisHash", None, timeout_secs).await?)
+    }
+    
+    /// Software version and feature set the node reports
+    pub async fn get_version(&self, endpoint: &str, timeout_secs: u64) -> Option<(Version, Option<u32>)> {
+        parse_version(&self.probe(endpoint, "getVersion", None, timeout_secs).await?)
+    }
+    
+    /// Nodes `getClusterNodes` on `endpoint` reports
+    pub async fn get_cluster_nodes(&self, endpoint: &str, timeout_secs: u64) -> Result<Vec<serde_json::Value>> {
+        let response = self.call(endpoint, "getClusterNodes", None, timeout_secs).await?;
+        if let Some(error) = response.error {
+            return Err(anyhow::anyhow!("RPC error {}: {}", error.code, error.message));
+        }
+        match response.result {
+            Some(serde_json::Value::Array(nodes)) => Ok(nodes),
+            _ => Err(anyhow::anyhow!("Invalid RPC response format")),
+        }
+    }
+    
+    /// Result of a single health check call, `None` if the node failed or returned an error
+    async fn probe(
+        &self,
+        endpoint: &str,
+        method: &str,
+        params: Option<serde_json::Value>,
+        timeout_secs: u64,
+    ) -> Option<serde_json::Value> {
+        self.call(endpoint, method, params, timeout_secs).await.ok()?.result
+    }
+    
+    /// Send one health check request and parse the JSON-RPC response
+    async fn call(
+        &self,
+        endpoint: &str,
+        method: &str,
+        params: Option<serde_json::Value>,
+        timeout_secs: u64,
+    ) -> Result<RpcResponse> {
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(1),
+            method: method.to_string(),
+            params,
+        };
+        
+        let response = self.client
+            .post(endpoint)
+            .json(&request)
+            .timeout(Duration::from_secs(timeout_secs))
+            .send()
+            .await?;
+        
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("HTTP {}", response.status()));
+        }
+        Ok(response.json().await?)
+    }
+    
+    /// Forward `request`, waiting at most `timeout` for the response headers.
+    /// The body then has `limits.transfer_time` to arrive.
+    pub async fn forward_rpc_request_raw(
+        &self,
+        endpoint: &str,
+        request: &RpcRequest,
+        timeout: Duration,
+        limits: &BodyLimits,
+    ) -> Result<UpstreamResponse, ForwardError> {
+        let request_id_str = match &request.id {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Number(n) => n.to_string(),
+            _ => request.id.to_string(),
+        };
+        
+        debug!("🔄 [ID:{}] Forwarding RPC request [{}] to: {} (request timeout: {:?})", 
+               request_id_str, request.method, endpoint, timeout);
+        
+        let response = send_within(timeout, self.client.post(endpoint).json(request)).await?;
+        let status = response.status();
+        
+        if status.is_success() {
+            let upstream_response = read_body(response, limits, limits.buffer_bytes).await?;
+            // Errors another node may not give are handed back for retrying, the rest go to the client as they are
+            if let UpstreamResponse::Buffered(body) = &upstream_response {
+                if let Some(error) = rpc_error(body).filter(|error| rpc_error_fault(error.code).is_retryable()) {
+                    debug!("⚠️  [ID:{}] RPC request [{}] answered by {} with JSON-RPC error {}: {}", 
+                           request_id_str, request.method, endpoint, error.code, error.message);
+                    return Err(ForwardError::Upstream { status, error: Some(error) });
                 }
-                -32601 => {
-                    // Method not found - the node does not support this method, not a full node
-                    error!("❌ RPC node {} is not a full RPC node (method not supported)", endpoint);
-                    Err(anyhow::anyhow!("Node does not support getTokenAccountsByOwner"))
+            }
+            debug!("✅ [ID:{}] RPC request [{}] forwarded successfully to: {}", 
+                   request_id_str, request.method, endpoint);
+            Ok(upstream_response)
+        } else {
+            error!("❌ [ID:{}] RPC forwarding failed for [{}] to {}, status code: {}", 
+                   request_id_str, request.method, endpoint, status);
+            Err(ForwardError::Upstream { status, error: read_error_body(response, limits).await })
+        }
+    }
+    
+    pub async fn forward_rpc_batch_raw(
+        &self,
+        endpoint: &str,
+        requests: &[RpcRequest],
+        timeout: Duration,
+        limits: &BodyLimits,
+    ) -> Result<Vec<serde_json::Value>, ForwardError> {
+        debug!("🔄 Forwarding RPC batch of {} requests to: {} (request timeout: {:?})", 
+               requests.len(), endpoint, timeout);
+        
+        let response = send_within(timeout, self.client.post(endpoint).json(requests)).await?;
+        let status = response.status();
+        
+        if status.is_success() {
+            // batch responses are split up by id, so they're always read whole
+            let UpstreamResponse::Buffered(raw_response) = read_body(response, limits, usize::MAX).await? else {
+                unreachable!("bodies are never streamed without a buffer limit");
+            };
+            let parsed = serde_json::from_slice::<serde_json::Value>(&raw_response)
+                .map_err(|e| ForwardError::InvalidResponse(e.to_string()))?;
+            match parsed {
+                serde_json::Value::Array(responses) => {
+                    debug!("✅ RPC batch of {} requests forwarded successfully to: {}", requests.len(), endpoint);
+                    Ok(responses)
                 }
-                _ => {
-                    // other errors, but the node can handle the request, is considered valid
-                    debug!("✅ Full RPC node {} validation passed (processed request, error code: {})", endpoint, error.code);
-                    Ok(())
+                // some nodes answer a whole batch with a single error object (e.g. batch too large)
+                other => {
+                    error!("❌ RPC batch forwarding to {} returned a non-batch response: {}", endpoint, other);
+                    match other.get("error").cloned().and_then(|error| serde_json::from_value::<RpcError>(error).ok()) {
+                        Some(error) => Err(ForwardError::Upstream { status, error: Some(error) }),
+                        None => Err(ForwardError::InvalidResponse(format!("upstream returned {}", other))),
+                    }
                 }
             }
         } else {
-            error!("❌ RPC node {} returned invalid response (no result, no error)", endpoint);
-            Err(anyhow::anyhow!("Invalid RPC response"))
+            error!("❌ RPC batch forwarding of {} requests to {} failed, status code: {}", 
+                   requests.len(), endpoint, status);
+            Err(ForwardError::Upstream { status, error: read_error_body(response, limits).await })
+        }
+    }
+}
+
+/// Send `request`, giving up if the response headers don't arrive within `timeout`
+async fn send_within(timeout: Duration, request: reqwest::RequestBuilder) -> Result<reqwest::Response, ForwardError> {
+    tokio::time::timeout(timeout, request.send())
+        .await
+        .map_err(|_| ForwardError::Timeout(format!("no response within {:?}", timeout)))?
+        .map_err(ForwardError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::{get, post}, serve, Json, Router};
+    
+    /// Answers `/fixed` with `chunks * chunk_size` bytes and their content length, and
+    /// `/chunked` with the same bytes as `chunks` chunks `pause` apart and no length
+    async fn upstream_node(chunks: usize, chunk_size: usize, pause: Duration) -> String {
+        let app = Router::new()
+            .route("/fixed", get(move || async move { vec![b'x'; chunks * chunk_size] }))
+            .route("/chunked", get(move || async move {
+                Body::from_stream(futures_util::stream::iter(0..chunks).then(move |index| async move {
+                    if index > 0 {
+                        tokio::time::sleep(pause).await;
+                    }
+                    Ok::<_, std::convert::Infallible>(Bytes::from(vec![b'x'; chunk_size]))
+                }))
+            }));
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { serve(listener, app).await.unwrap() });
+        endpoint
+    }
+    
+    #[tokio::test]
+    async fn slots_are_read_per_commitment() {
+        let app = Router::new().route("/", post(|Json(request): Json<serde_json::Value>| async move {
+            let reply = match request["params"][0]["commitment"].as_str() {
+                Some("processed") => json!({ "jsonrpc": "2.0", "id": request["id"], "result": 1002 }),
+                Some("confirmed") => json!({ "jsonrpc": "2.0", "id": request["id"], "result": 1000 }),
+                _ => json!({ "jsonrpc": "2.0", "id": request["id"], "error": { "code": -32005, "message": "Node is behind" } }),
+            };
+            Json(reply)
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { serve(listener, app).await.unwrap() });
+        
+        let client = UpstreamClient { client: Client::new() };
+        let slots = client.get_node_slots(&endpoint, 5).await;
+        assert_eq!((slots.processed, slots.confirmed, slots.finalized), (Some(1002), Some(1000), None));
+    }
+    
+    async fn fetch(url: String) -> reqwest::Response {
+        Client::new().get(url).send().await.unwrap()
+    }
+    
+    fn limits(buffer_bytes: usize, max_bytes: usize, transfer_time: Duration) -> BodyLimits {
+        BodyLimits { buffer_bytes, max_bytes, transfer_time }
+    }
+    
+    /// Bytes the stream passed on and the error it ended with, if any
+    async fn drain(body: StreamingBody) -> (usize, Option<ForwardError>) {
+        let mut stream = Box::pin(body.into_stream());
+        let mut received = 0;
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => received += chunk.len(),
+                Err(e) => {
+                    assert!(stream.next().await.is_none(), "stream went on after an error");
+                    return (received, Some(e));
+                }
+            }
         }
-    } else {
-        error!("❌ RPC node {} validation failed, HTTP status: {}", endpoint, response.status());
-        Err(anyhow::anyhow!("RPC node validation failed: HTTP {}", response.status()))
-    }
-}
-
-pub async fn forward_rpc_request_raw(
-    endpoint: &str,
-    request: &RpcRequest,
-    timeout_secs: u64,
-) -> Result<String> {
-    let client = Client::new();
-    
-    let request_id_str = match &request.id {
-        serde_json::Value::String(s) => s.clone(),
-        serde_json::Value::Number(n) => n.to_string(),
-        _ => request.id.to_string(),
-    };
-    
-    debug!("🔄 [ID:{}] Forwarding RPC request [{}] to: {} (request timeout: {}s)", 
-           request_id_str, request.method, endpoint, timeout_secs);
-    
-    let response = client
-        .post(endpoint)
-        .json(request)
-        .timeout(Duration::from_secs(timeout_secs))
-        .send()
-        .await?;
-    
-    if response.status().is_success() {
-        let raw_response = response.text().await?;
-        debug!("✅ [ID:{}] RPC request [{}] forwarded successfully to: {}", 
-               request_id_str, request.method, endpoint);
-        Ok(raw_response)
-    } else {
-        error!("❌ [ID:{}] RPC forwarding failed for [{}] to {}, status code: {}", 
-               request_id_str, request.method, endpoint, response.status());
-        Err(anyhow::anyhow!("RPC forwarding failed: {}", response.status()))
-    }
-} 
\ No newline at end of file
+        (received, None)
+    }
+    
+    #[tokio::test]
+    async fn small_bodies_are_buffered_whole() {
+        let node = upstream_node(4, 100, Duration::from_millis(10)).await;
+        let limits = limits(1000, 1000, Duration::from_secs(5));
+        let body = read_body(fetch(format!("{}/chunked", node)).await, &limits, limits.buffer_bytes).await;
+        assert!(matches!(body, Ok(UpstreamResponse::Buffered(body)) if body.len() == 400));
+    }
+    
+    #[tokio::test]
+    async fn over_limit_buffered_bodies_are_refused() {
+        let node = upstream_node(4, 500, Duration::from_millis(10)).await;
+        let limits = limits(usize::MAX, 1000, Duration::from_secs(5));
+        
+        // an announced length over the limit isn't read at all
+        let response = fetch(format!("{}/fixed", node)).await;
+        assert_eq!(response.content_length(), Some(2000));
+        let body = read_body(response, &limits, limits.buffer_bytes).await;
+        assert!(matches!(body, Err(ForwardError::TooLarge(1000))));
+        
+        let body = read_body(fetch(format!("{}/chunked", node)).await, &limits, limits.buffer_bytes).await;
+        assert!(matches!(body, Err(ForwardError::TooLarge(1000))));
+    }
+    
+    #[tokio::test]
+    async fn large_bodies_are_streamed_in_full() {
+        let node = upstream_node(10, 200, Duration::from_millis(10)).await;
+        let limits = limits(300, 0, Duration::from_secs(5));
+        let body = read_body(fetch(format!("{}/chunked", node)).await, &limits, limits.buffer_bytes).await;
+        let Ok(UpstreamResponse::Streaming(body)) = body else {
+            panic!("a body past the buffer limit wasn't streamed");
+        };
+        
+        let (received, error) = drain(body).await;
+        assert_eq!(received, 2000);
+        assert!(error.is_none());
+    }
+    
+    #[tokio::test]
+    async fn over_limit_streamed_bodies_are_cut_off_mid_stream() {
+        let node = upstream_node(10, 200, Duration::from_millis(10)).await;
+        let limits = limits(300, 1000, Duration::from_secs(5));
+        let body = read_body(fetch(format!("{}/chunked", node)).await, &limits, limits.buffer_bytes).await;
+        let Ok(UpstreamResponse::Streaming(body)) = body else {
+            panic!("a body past the buffer limit wasn't streamed");
+        };
+        
+        let (received, error) = drain(body).await;
+        assert!(received > 300 && received <= 1000, "{}", received);
+        assert!(matches!(error, Some(ForwardError::TooLarge(1000))));
+    }
+    
+    #[tokio::test]
+    async fn slow_bodies_run_out_of_transfer_time() {
+        let node = upstream_node(3, 200, Duration::from_millis(500)).await;
+        let limits = limits(usize::MAX, 0, Duration::from_millis(200));
+        let body = read_body(fetch(format!("{}/chunked", node)).await, &limits, limits.buffer_bytes).await;
+        assert!(matches!(body, Err(ForwardError::Timeout(_))));
+        
+        // the deadline carries over into streaming
+        let limits = BodyLimits { buffer_bytes: 100, ..limits };
+        let body = read_body(fetch(format!("{}/chunked", node)).await, &limits, limits.buffer_bytes).await;
+        let Ok(UpstreamResponse::Streaming(body)) = body else {
+            panic!("a body past the buffer limit wasn't streamed");
+        };
+        let (received, error) = drain(body).await;
+        assert_eq!(received, 200);
+        assert!(matches!(error, Some(ForwardError::Timeout(_))));
+    }
+}
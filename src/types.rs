@@ -1,26 +1,334 @@
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+
+use crate::version::Version;
+
+/// Slot reported by a node for each commitment level, as of its last health check
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NodeSlots {
+    pub processed: Option<u64>,
+    pub confirmed: Option<u64>,
+    pub finalized: Option<u64>,
+}
+
+impl NodeSlots {
+    pub const COMMITMENTS: [&'static str; 3] = ["processed", "confirmed", "finalized"];
+    
+    pub fn get(&self, commitment: &str) -> Option<u64> {
+        match commitment {
+            "processed" => self.processed,
+            "confirmed" => self.confirmed,
+            "finalized" => self.finalized,
+            _ => None,
+        }
+    }
+}
+
+/// What a node can answer beyond recent state, as of its last health check
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NodeCapabilities {
+    /// `minimumLedgerSlot`, the oldest slot still in the node's ledger
+    pub minimum_ledger_slot: Option<u64>,
+    /// `getFirstAvailableBlock`, the oldest block the node can return
+    pub first_available_block: Option<u64>,
+    /// Answers `getTokenAccountsByOwner` from an spl-token-owner index
+    pub spl_token_owner_index: bool,
+    /// Answers `getProgramAccounts` from a program-id index
+    pub program_id_index: bool,
+    /// Serves `getTransaction` and `getSignaturesForAddress`
+    pub transaction_history: bool,
+    /// When the capabilities were probed, `None` if they never were
+    pub checked_at: Option<SystemTime>,
+    /// Version the node ran when they were probed
+    pub checked_version: Option<Version>,
+}
+
+impl NodeCapabilities {
+    /// Capabilities change with the node's software and configuration, so they are
+    /// probed again after a version change or once they are `max_age` old
+    pub fn are_stale(&self, version: Option<Version>, max_age: Duration, now: SystemTime) -> bool {
+        match self.checked_at {
+            Some(checked_at) => {
+                self.checked_version != version
+                    || now.duration_since(checked_at).unwrap_or_default() >= max_age
+            }
+            None => true,
+        }
+    }
+    
+    /// Oldest slot the node can answer block and history requests for, `None` when unknown
+    pub fn oldest_slot(&self) -> Option<u64> {
+        self.first_available_block.or(self.minimum_ledger_slot)
+    }
+}
+
+/// Outcome of one health check probe on a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub name: String,
+    pub passed: bool,
+    pub required: bool,
+    pub weight: f64,
+    pub latency_ms: u64,
+    /// Why the probe failed
+    pub error: Option<String>,
+}
+
+/// Smoothing factor of the per-node EWMAs, the weight of the newest request
+const EWMA_ALPHA: f64 = 0.1;
+
+/// Kind of load a request puts on a node. Nodes can be fast at simple reads
+/// and slow at scans, so live traffic is tracked per class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MethodClass {
+    Light,
+    /// Scans, history and multi-account reads
+    Heavy,
+    /// Transaction submission and simulation
+    Transaction,
+}
+
+impl MethodClass {
+    pub const ALL: [MethodClass; 3] = [MethodClass::Light, MethodClass::Heavy, MethodClass::Transaction];
+    
+    pub fn of(method: &str) -> Self {
+        match method {
+            "getProgramAccounts" | "getLargestAccounts" | "getTokenLargestAccounts"
+            | "getTokenAccountsByOwner" | "getTokenAccountsByDelegate" | "getSignaturesForAddress"
+            | "getBlock" | "getBlocks" | "getBlocksWithLimit" | "getTransaction"
+            | "getMultipleAccounts" | "getSupply" => MethodClass::Heavy,
+            "sendTransaction" | "simulateTransaction" | "requestAirdrop" => MethodClass::Transaction,
+            _ => MethodClass::Light,
+        }
+    }
+    
+    /// Class of several requests travelling together, the heaviest among them
+    pub fn of_all<'a>(methods: impl IntoIterator<Item = &'a str>) -> Self {
+        let classes: Vec<MethodClass> = methods.into_iter().map(MethodClass::of).collect();
+        if classes.contains(&MethodClass::Heavy) {
+            MethodClass::Heavy
+        } else if classes.contains(&MethodClass::Transaction) {
+            MethodClass::Transaction
+        } else {
+            MethodClass::Light
+        }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MethodClass::Light => "light",
+            MethodClass::Heavy => "heavy",
+            MethodClass::Transaction => "transaction",
+        }
+    }
+}
+
+impl std::str::FromStr for MethodClass {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        MethodClass::ALL
+            .into_iter()
+            .find(|class| class.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown method group '{}', expected light, heavy or transaction", s))
+    }
+}
+
+/// How a node has been doing with live traffic of one method class
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TrafficStats {
+    /// EWMA of upstream attempt latency, `None` before the first request
+    pub latency_ms: Option<f64>,
+    /// EWMA of the share of attempts the node couldn't serve
+    pub error_rate: f64,
+    pub requests: u64,
+}
+
+impl TrafficStats {
+    pub fn record(&mut self, latency: std::time::Duration, failed: bool) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(average) => average + EWMA_ALPHA * (latency_ms - average),
+            None => latency_ms,
+        });
+        let failed = if failed { 1.0 } else { 0.0 };
+        self.error_rate += EWMA_ALPHA * (failed - self.error_rate);
+        self.requests += 1;
+    }
+}
+
+/// Live traffic stats per method class
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NodeTraffic {
+    pub light: TrafficStats,
+    pub heavy: TrafficStats,
+    pub transaction: TrafficStats,
+}
+
+impl NodeTraffic {
+    pub fn get(&self, class: MethodClass) -> &TrafficStats {
+        match class {
+            MethodClass::Light => &self.light,
+            MethodClass::Heavy => &self.heavy,
+            MethodClass::Transaction => &self.transaction,
+        }
+    }
+    
+    pub fn get_mut(&mut self, class: MethodClass) -> &mut TrafficStats {
+        match class {
+            MethodClass::Light => &mut self.light,
+            MethodClass::Heavy => &mut self.heavy,
+            MethodClass::Transaction => &mut self.transaction,
+        }
+    }
+}
+
+/// Where a node is in its failure handling. Forwarding failures make a healthy
+/// node suspect and, once they add up, quarantined. A quarantined node is
+/// re-probed after an exponentially growing backoff and returns to healthy as
+/// soon as a probe passes. Newly discovered nodes are unchecked until their
+/// first health check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeState {
+    #[default]
+    Healthy,
+    /// Discovered and waiting for its first health check, receives no traffic
+    Unchecked,
+    /// Failed recently but still receives traffic, a success makes it healthy again
+    Suspect,
+    /// Receives no traffic until `quarantined_until`
+    Quarantined,
+    /// Quarantine expired, a health check is running
+    Probing,
+}
+
+impl NodeState {
+    pub const ALL: [NodeState; 5] = [
+        NodeState::Unchecked,
+        NodeState::Healthy,
+        NodeState::Suspect,
+        NodeState::Quarantined,
+        NodeState::Probing,
+    ];
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeState::Unchecked => "unchecked",
+            NodeState::Healthy => "healthy",
+            NodeState::Suspect => "suspect",
+            NodeState::Quarantined => "quarantined",
+            NodeState::Probing => "probing",
+        }
+    }
+}
+
+/// Failure tracking behind a node's state, kept across health checks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NodeHealth {
+    pub state: NodeState,
+    /// Failures since the last success
+    pub consecutive_failures: u32,
+    /// Quarantines in a row, the backoff doubles with each
+    pub quarantines: u32,
+    pub quarantined_until: Option<std::time::SystemTime>,
+    /// When the node last left quarantine
+    pub recovered_at: Option<std::time::SystemTime>,
+    /// When a healthy or suspect node is checked next, `None` when due right away
+    pub next_check_at: Option<std::time::SystemTime>,
+    /// A health check of the node is running
+    #[serde(skip)]
+    pub checking: bool,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RpcNode {
     pub endpoint: String,
+    /// PubSub websocket address advertised by the node, if known
+    pub pubsub: Option<String>,
+    /// Shred version the node advertises in gossip, if known
+    pub shred_version: Option<u16>,
+    /// Software version the node reported through getVersion or gossip
+    pub version: Option<Version>,
+    pub feature_set: Option<u32>,
     pub last_seen: std::time::SystemTime,
     pub response_time: Option<Duration>,
+    /// Whether the node receives traffic, i.e. it is healthy or suspect
     pub is_active: bool,
+    pub slots: NodeSlots,
+    pub capabilities: NodeCapabilities,
+    /// Weighted share of health check probes passed at the last check, from 0 to 1
+    pub health_score: Option<f64>,
+    pub probes: Vec<ProbeResult>,
+    pub health: NodeHealth,
+    /// Passive health from forwarded requests. The cache keeps it apart from the
+    /// node and fills it in on the nodes `get_slot_lag_stats` reports.
+    pub traffic: NodeTraffic,
+    /// Discovery rounds in a row that didn't list the node
+    pub missed_discoveries: u32,
 }
 
 impl RpcNode {
     pub fn new(endpoint: String) -> Self {
         Self {
             endpoint,
+            pubsub: None,
+            shred_version: None,
+            version: None,
+            feature_set: None,
             last_seen: std::time::SystemTime::now(),
             response_time: None,
             is_active: false,
+            slots: NodeSlots::default(),
+            capabilities: NodeCapabilities::default(),
+            health_score: None,
+            probes: Vec::new(),
+            health: NodeHealth::default(),
+            traffic: NodeTraffic::default(),
+            missed_discoveries: 0,
+        }
+    }
+    
+    pub fn with_pubsub(mut self, pubsub: Option<String>) -> Self {
+        self.pubsub = pubsub;
+        self
+    }
+    
+    pub fn with_shred_version(mut self, shred_version: Option<u16>) -> Self {
+        self.shred_version = shred_version;
+        self
+    }
+    
+    pub fn with_version(mut self, version: Option<Version>, feature_set: Option<u32>) -> Self {
+        self.version = version;
+        self.feature_set = feature_set;
+        self
+    }
+    
+    /// Websocket URL for this node's PubSub service. Uses the advertised
+    /// address when known, otherwise follows the Solana convention of
+    /// RPC port + 1 (URLs without an explicit port keep the same host).
+    pub fn pubsub_endpoint(&self) -> Option<String> {
+        if let Some(pubsub) = &self.pubsub {
+            if pubsub.starts_with("ws") {
+                return Some(pubsub.clone());
+            }
+            let scheme = if self.endpoint.starts_with("https") { "wss" } else { "ws" };
+            return Some(format!("{}://{}", scheme, pubsub));
+        }
+        
+        let mut url = reqwest::Url::parse(&self.endpoint).ok()?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        if let Some(port) = url.port() {
+            url.set_port(Some(port.checked_add(1)?)).ok()?;
         }
+        url.set_scheme(scheme).ok()?;
+        Some(url.to_string().trim_end_matches('/').to_string())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RpcRequest {
     pub jsonrpc: String,
     pub id: serde_json::Value,
@@ -36,9 +344,32 @@ pub struct RpcResponse {
     pub error: Option<RpcError>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RpcError {
     pub code: i32,
     pub message: String,
     pub data: Option<serde_json::Value>,
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn capabilities_go_stale_on_age_or_version_change() {
+        let now = SystemTime::now();
+        let max_age = Duration::from_secs(3600);
+        let version: Option<Version> = Some("2.0.1".parse().unwrap());
+        
+        assert!(NodeCapabilities::default().are_stale(version, max_age, now));
+        
+        let capabilities = NodeCapabilities {
+            checked_at: Some(now),
+            checked_version: version,
+            ..NodeCapabilities::default()
+        };
+        assert!(!capabilities.are_stale(version, max_age, now + Duration::from_secs(60)));
+        assert!(capabilities.are_stale(version, max_age, now + max_age));
+        assert!(capabilities.are_stale(Some("2.1.0".parse().unwrap()), max_age, now));
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::types::ProbeResult;
+
+/// Name of the version check among a node's probe results
+const VERSION_PROBE: &str = "nodeVersion";
+
+/// Node software version, `major.minor.patch`. Missing parts count as 0 and
+/// anything after the patch number (`-beta`, build metadata) is ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.trim().splitn(3, '.');
+        let mut part = |name: &str| -> Result<u64> {
+            let Some(part) = parts.next() else {
+                return Ok(0);
+            };
+            let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse().map_err(|_| anyhow::anyhow!("invalid {} version in '{}'", name, s))
+        };
+        
+        Ok(Self {
+            major: part("major")?,
+            minor: part("minor")?,
+            patch: part("patch")?,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Versions a node may run, both ends inclusive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: Option<Version>,
+    pub max: Option<Version>,
+}
+
+impl VersionRange {
+    pub fn is_unbounded(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+    
+    /// Whether a node on `version` is within the range. A node whose version
+    /// is unknown only satisfies an unbounded range.
+    pub fn contains(&self, version: Option<Version>) -> bool {
+        if self.is_unbounded() {
+            return true;
+        }
+        let Some(version) = version else {
+            return false;
+        };
+        self.min.is_none_or(|min| version >= min) && self.max.is_none_or(|max| version <= max)
+    }
+    
+    /// Range satisfying both `self` and `other`
+    pub fn intersect(self, other: VersionRange) -> VersionRange {
+        VersionRange {
+            min: self.min.max(other.min),
+            max: match (self.max, other.max) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "{} to {}", min, max),
+            (Some(min), None) => write!(f, "{} or newer", min),
+            (None, Some(max)) => write!(f, "{} or older", max),
+            (None, None) => write!(f, "any version"),
+        }
+    }
+}
+
+/// Per-method version requirement given on the command line as
+/// `method:min_version` or `method:min_version:max_version`, either end may be empty
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MethodVersion {
+    pub method: String,
+    #[serde(default)]
+    pub min_version: Option<Version>,
+    #[serde(default)]
+    pub max_version: Option<Version>,
+}
+
+impl FromStr for MethodVersion {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if !(2..=3).contains(&parts.len()) || parts[0].is_empty() {
+            return Err(anyhow::anyhow!("expected method:min_version[:max_version], got '{}'", s));
+        }
+        
+        let version = |part: Option<&&str>| -> Result<Option<Version>> {
+            match part {
+                Some(part) if !part.is_empty() => Ok(Some(part.parse()?)),
+                _ => Ok(None),
+            }
+        };
+        
+        Ok(Self {
+            method: parts[0].to_string(),
+            min_version: version(parts.get(1))?,
+            max_version: version(parts.get(2))?,
+        })
+    }
+}
+
+/// Versions nodes must run to receive a method's requests
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodVersions {
+    per_method: HashMap<String, VersionRange>,
+}
+
+impl MethodVersions {
+    pub fn new(requirements: Vec<MethodVersion>) -> Self {
+        Self {
+            per_method: requirements
+                .into_iter()
+                .map(|requirement| (requirement.method, VersionRange {
+                    min: requirement.min_version,
+                    max: requirement.max_version,
+                }))
+                .collect(),
+        }
+    }
+    
+    pub fn for_method(&self, method: &str) -> VersionRange {
+        self.per_method.get(method).copied().unwrap_or_default()
+    }
+    
+    /// Every method with a version requirement
+    pub fn ranges(&self) -> impl Iterator<Item = (&str, VersionRange)> {
+        self.per_method.iter().map(|(method, range)| (method.as_str(), *range))
+    }
+}
+
+/// Whether `version` is known and outside `allowed`, as opposed to unknown
+pub fn is_disallowed(version: Option<Version>, allowed: &VersionRange) -> bool {
+    version.is_some_and(|version| !allowed.contains(Some(version)))
+}
+
+/// Check a node running `version` against the versions allowed in the pool
+pub fn verify(version: Option<Version>, allowed: &VersionRange) -> ProbeResult {
+    let error = match version {
+        None => Some("version unknown".to_string()),
+        Some(version) if !allowed.contains(Some(version)) => {
+            Some(format!("version {}, expected {}", version, allowed))
+        }
+        Some(_) => None,
+    };
+    
+    ProbeResult {
+        name: VERSION_PROBE.to_string(),
+        passed: error.is_none(),
+        required: true,
+        weight: 0.0,
+        latency_ms: 0,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn version(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+    
+    fn range(min: Option<&str>, max: Option<&str>) -> VersionRange {
+        VersionRange {
+            min: min.map(version),
+            max: max.map(version),
+        }
+    }
+    
+    #[test]
+    fn versions_are_parsed_leniently() {
+        assert_eq!(version("1.18.22"), Version { major: 1, minor: 18, patch: 22 });
+        assert_eq!(version(" 2.1 "), Version { major: 2, minor: 1, patch: 0 });
+        assert_eq!(version("2"), Version { major: 2, minor: 0, patch: 0 });
+        assert_eq!(version("2.0.15-beta.1"), Version { major: 2, minor: 0, patch: 15 });
+        assert!("".parse::<Version>().is_err());
+        assert!("v1.2.3".parse::<Version>().is_err());
+        assert!("1.x.3".parse::<Version>().is_err());
+    }
+    
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(version("1.18.9") < version("1.18.10"));
+        assert!(version("1.9.0") < version("1.10.0"));
+        assert!(version("2.0.0") > version("1.99.99"));
+        assert_eq!(version("1.18.0-rc"), version("1.18"));
+        assert_eq!(version("1.18.22").to_string(), "1.18.22");
+    }
+    
+    #[test]
+    fn ranges_are_inclusive_and_unknown_versions_only_satisfy_unbounded_ones() {
+        let bounded = range(Some("1.18.0"), Some("2.0.0"));
+        assert!(bounded.contains(Some(version("1.18.0"))));
+        assert!(bounded.contains(Some(version("2.0.0"))));
+        assert!(!bounded.contains(Some(version("2.0.1"))));
+        assert!(!bounded.contains(None));
+        assert!(VersionRange::default().contains(None));
+        
+        assert!(is_disallowed(Some(version("1.17.0")), &bounded));
+        assert!(!is_disallowed(None, &bounded));
+        assert!(!verify(None, &bounded).passed);
+    }
+    
+    #[test]
+    fn intersecting_ranges_keeps_the_tighter_ends() {
+        let a = range(Some("1.17.0"), Some("2.1.0"));
+        let b = range(Some("1.18.0"), None);
+        assert_eq!(a.intersect(b), range(Some("1.18.0"), Some("2.1.0")));
+        assert_eq!(b.intersect(VersionRange::default()), b);
+    }
+    
+    #[test]
+    fn method_versions_are_parsed() {
+        let parsed: MethodVersion = "getBlock:1.18.0".parse().unwrap();
+        assert_eq!((parsed.min_version, parsed.max_version), (Some(version("1.18.0")), None));
+        let parsed: MethodVersion = "getBlock::2.0.0".parse().unwrap();
+        assert_eq!((parsed.min_version, parsed.max_version), (None, Some(version("2.0.0"))));
+        
+        for invalid in ["getBlock", ":1.18.0", "getBlock:1:2:3", "getBlock:x"] {
+            assert!(invalid.parse::<MethodVersion>().is_err(), "{}", invalid);
+        }
+    }
+}